@@ -1,7 +1,7 @@
 //! This module contains the definitions for command line argument parsing
 //! and related types used throughout the application.
 //! It includes enums, functions, traits, and subcommands tailored for different modes of operation
-//! such as `X3DGeospatial` and `Obj`.
+//! such as `X3DGeospatial`, `Obj`, `Gltf`, `Stl`, `Ply`, `ThreeMf`, `Usd`, `X3D` and `Collada`.
 use argh::FromArgs;
 use std::cmp::min;
 use std::thread::available_parallelism;
@@ -63,6 +63,13 @@ pub struct TopLevelCommands {
 pub enum MySubCommandEnum {
     SubCommandX3DGeospatial(CLIArgsX3DGeospatial),
     SubCommandObj(CLIArgsObj),
+    SubCommandGltf(CLIArgsGltf),
+    SubCommandStl(CLIArgsStl),
+    SubCommandPly(CLIArgsPly),
+    SubCommandThreeMf(CLIArgsThreeMf),
+    SubCommandUsd(CLIArgsUsd),
+    SubCommandX3D(CLIArgsX3D),
+    SubCommandCollada(CLIArgsCollada),
 }
 
 /// Common arguments getter trait.
@@ -74,6 +81,7 @@ pub trait Args {
     fn jobs(&self) -> usize;
     fn data_source_dir(&self) -> Option<&String>;
     fn output_dir(&self) -> Option<&String>;
+    fn subdivisions(&self) -> Option<GeoPointIndex>;
 }
 
 /// Subcommand for X3DGeospatial mode.
@@ -131,6 +139,9 @@ impl Args for CLIArgsX3DGeospatial {
     fn output_dir(&self) -> Option<&String> {
         self.output_dir.as_ref()
     }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        None
+    }
 }
 
 /// Subcommand for color mode.
@@ -164,6 +175,10 @@ pub struct CLIArgsObj {
     /// output directory (default: current directory).
     #[argh(option)]
     pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
 }
 
 impl Args for CLIArgsObj {
@@ -188,4 +203,455 @@ impl Args for CLIArgsObj {
     fn output_dir(&self) -> Option<&String> {
         self.output_dir.as_ref()
     }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for glTF mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "gltf")]
+pub struct CLIArgsGltf {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsGltf {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for STL mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "stl")]
+pub struct CLIArgsStl {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsStl {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for PLY mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ply")]
+pub struct CLIArgsPly {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsPly {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for 3MF mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "3mf")]
+pub struct CLIArgsThreeMf {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsThreeMf {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for USD mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "usd")]
+pub struct CLIArgsUsd {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsUsd {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for X3D mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "x3d")]
+pub struct CLIArgsX3D {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsX3D {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
+}
+
+/// Subcommand for Collada mode.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "dae")]
+pub struct CLIArgsCollada {
+    /// model type (Texture or Color).
+    #[argh(positional, from_str_fn(get_model_type))]
+    pub model_type: ModelType,
+
+    /// data source type.
+    #[argh(positional, from_str_fn(get_data_source_name))]
+    pub data_source: DataSourceName,
+
+    /// planet name (will be used in output file names)
+    #[argh(option, default = "default_planet_name()")]
+    pub planet_name: String,
+
+    /// model size (may be implicitly changed to the nearest valid value)
+    #[argh(option)]
+    pub model_size: Option<GeoPointIndex>,
+
+    /// number of jobs (default: min(2, available parallelism))
+    #[argh(option, default = "default_jobs()")]
+    pub jobs: usize,
+
+    /// data source directory (default: current directory)
+    #[argh(option)]
+    pub data_source_dir: Option<String>,
+
+    /// output directory (default: current directory).
+    #[argh(option)]
+    pub output_dir: Option<String>,
+
+    /// subdivision level of the icosphere and cube-sphere tessellations (default: derived from model size)
+    #[argh(option)]
+    pub subdivisions: Option<GeoPointIndex>,
+}
+
+impl Args for CLIArgsCollada {
+    fn data_source(&self) -> DataSourceName {
+        self.data_source.clone()
+    }
+    fn model_type(&self) -> ModelType {
+        self.model_type
+    }
+    fn planet_name(&self) -> &String {
+        &self.planet_name
+    }
+    fn model_size(&self) -> Option<GeoPointIndex> {
+        self.model_size
+    }
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+    fn data_source_dir(&self) -> Option<&String> {
+        self.data_source_dir.as_ref()
+    }
+    fn output_dir(&self) -> Option<&String> {
+        self.output_dir.as_ref()
+    }
+    fn subdivisions(&self) -> Option<GeoPointIndex> {
+        self.subdivisions
+    }
 }
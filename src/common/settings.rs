@@ -28,6 +28,8 @@ pub struct Settings<'a> {
     pub model_size: Option<GeoPointIndex>,
     /// Number of parallel jobs to run during processing
     pub jobs: usize,
+    /// Subdivision level of the tessellation (if given on the command line)
+    pub subdivisions: Option<GeoPointIndex>,
     /// Type of data source being used
     pub data_source: DataSourceName,
     /// Directory path where source data tiles are located
@@ -71,6 +73,7 @@ impl<'a> Settings<'a> {
             let planet_name = args.planet_name().clone();
             let model_size = args.model_size();
             let jobs = args.jobs();
+            let subdivisions = args.subdivisions();
 
             let y_ds = match data_source {
                 DataSourceName::DemArcSec3 => &settings["DataSource"]["DemArcSec3"],
@@ -137,6 +140,7 @@ impl<'a> Settings<'a> {
                 planet_name,
                 model_size,
                 jobs,
+                subdivisions,
                 data_source,
                 data_source_dir,
                 output_dir,
@@ -150,6 +154,13 @@ impl<'a> Settings<'a> {
         match &tl_commands.inner_enum {
             SubCommandX3DGeospatial(args) => make_for_model_name(args, "X3DGeospatial"),
             SubCommandObj(args) => make_for_model_name(args, "Obj"),
+            SubCommandGltf(args) => make_for_model_name(args, "Gltf"),
+            SubCommandStl(args) => make_for_model_name(args, "Stl"),
+            SubCommandPly(args) => make_for_model_name(args, "Ply"),
+            SubCommandThreeMf(args) => make_for_model_name(args, "ThreeMf"),
+            SubCommandUsd(args) => make_for_model_name(args, "Usd"),
+            SubCommandX3D(args) => make_for_model_name(args, "X3D"),
+            SubCommandCollada(args) => make_for_model_name(args, "Collada"),
         }
     }
 
@@ -267,6 +278,7 @@ mod tests {
             output_dir: None,
             planet_name: "some name".to_string(),
             model_size: None,
+            subdivisions: None,
         };
         let tl_command = TopLevelCommands {
             inner_enum: MySubCommandEnum::SubCommandObj(args),
@@ -297,6 +309,7 @@ mod tests {
             planet_name: "Earth".to_string(),
             model_size: None,
             jobs: 4,
+            subdivisions: None,
             data_source: DataSourceName::DemArcSec3,
             data_source_dir: Path::new(DEFAULT_DATA_SOURCE_DIR),
             output_dir: Path::new(DEFAULT_OUTPUT_DIR),
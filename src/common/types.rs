@@ -16,7 +16,7 @@ pub type HeightInt = i16;
 /// Coordinate as floating point number
 pub type Coord = f64;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 /// Geopoint representing a location with longitude and latitude coordinates
 ///
 /// This struct is used to represent geographic locations in the application,
@@ -128,6 +128,25 @@ pub struct DemArc3SecData<'a> {
     dem_data: Option<Box<[i16]>>,
 }
 
+impl<'a> DemArc3SecData<'a> {
+    /// Finds the DEM grid cell (row, column) of a geographic point
+    ///
+    /// Returns `None` if the point is out of the tile.
+    fn find_cell(&self, geo_point: &GeoPoint) -> Option<(usize, usize)> {
+        let GeoPoint { lon, lat } = *geo_point;
+        if (lon < (self.lon_left as Coord) || lon >= ((1 + self.lon_left) as Coord))
+            || (lat < (self.lat_bottom as Coord) || lat >= ((1 + self.lat_bottom) as Coord))
+        {
+            return None;
+        }
+        let x = lon - (self.lon_left as Coord);
+        let y = lat - (self.lat_bottom as Coord);
+        let i = (x * DEM_SIZE).floor() as usize;
+        let j = (y * DEM_SIZE).floor() as usize;
+        Some((i, DEM_EDGE_SIZE - 1 - j))
+    }
+}
+
 impl<'a> TileData<'a> for DemArc3SecData<'a> {
     /// Gets elevation at a specific row and column in the DEM grid
     ///
@@ -145,33 +164,29 @@ impl<'a> TileData<'a> for DemArc3SecData<'a> {
     /// the elevation at the specified geographic coordinates. It handles coordinate
     /// conversion and grid indexing to find the appropriate elevation value.
     fn calc_height(&self, geo_point: &GeoPoint) -> Option<Height> {
-        let GeoPoint { lon, lat } = *geo_point;
-        if (lon < (self.lon_left as Coord) || lon >= ((1 + self.lon_left) as Coord))
-            || (lat < (self.lat_bottom as Coord) || lat >= ((1 + self.lat_bottom) as Coord))
-        {
-            return None;
-        } else {
-            let x = lon - (self.lon_left as Coord);
-            let y = lat - (self.lat_bottom as Coord);
-            let i = (x * DEM_SIZE).floor() as usize;
-            let j = (y * DEM_SIZE).floor() as usize;
-
-            // rough; possible 3d models have much bigger cells then arc3sec dem elementary distances
-            let h = match self.get_dem_height(i, DEM_EDGE_SIZE - 1 - j) {
-                Some(h_int) => {
-                    if h_int as HeightInt == self.tile.get_nodata() {
-                        self.tile.get_sea_level()
-                    }
-                    // nodata implies sea
-                    else {
-                        h_int as HeightInt
-                    }
+        let (i, j) = self.find_cell(geo_point)?;
+        // rough; possible 3d models have much bigger cells then arc3sec dem elementary distances
+        let h = match self.get_dem_height(i, j) {
+            Some(h_int) => {
+                if h_int as HeightInt == self.tile.get_nodata() {
+                    self.tile.get_sea_level()
                 }
-                None => self.tile.get_sea_level(), // missing tiles implies sea
-            } as Height;
+                // nodata implies sea
+                else {
+                    h_int as HeightInt
+                }
+            }
+            None => self.tile.get_sea_level(), // missing tiles implies sea
+        } as Height;
 
-            return Some(h);
-        }
+        Some(h)
+    }
+
+    /// Checks that the DEM has a value other than nodata at a geographic point
+    fn has_data(&self, geo_point: &GeoPoint) -> bool {
+        self.find_cell(geo_point)
+            .and_then(|(i, j)| self.get_dem_height(i, j))
+            .is_some_and(|h_int| h_int != self.tile.get_nodata())
     }
 
     /// Loads a DEM tile from a file system path
@@ -396,6 +411,58 @@ mod tests {
         assert_eq!(height, Some(10.0)); // Should return sea level
     }
 
+    /// Test that nodata replaced by the sea level isn't a valid sample
+    #[test]
+    fn has_data_t1() {
+        let dem_data = vec![DEFAULT_NODATA; DEM_ARRAY_SIZE].into_boxed_slice();
+        let dem_tile = DemArc3SecOpts {
+            nodata: -32767,
+            sea_level: 10,
+        };
+        let dem = DemArc3SecData {
+            lon_left: 0,
+            lat_bottom: 0,
+            tile: &dem_tile,
+            dem_data: Some(dem_data),
+        };
+        let nodata_point = GeoPoint {
+            lat: 0.0833,
+            lon: 0.0833,
+        };
+        assert_eq!(dem.calc_height(&nodata_point), Some(10.0));
+        assert!(!dem.has_data(&nodata_point));
+    }
+
+    /// Test that valid samples are told apart from nodata
+    #[test]
+    fn has_data_t0() {
+        let mut dem_data = vec![DEFAULT_NODATA; DEM_ARRAY_SIZE].into_boxed_slice();
+        dem_data[DEM_ARRAY_SIZE / 2] = 0;
+        let dem_tile = DemArc3SecOpts {
+            nodata: -32767,
+            sea_level: 0,
+        };
+        let dem = DemArc3SecData {
+            lon_left: 50,
+            lat_bottom: 50,
+            tile: &dem_tile,
+            dem_data: Some(dem_data),
+        };
+        let p = GeoPoint {
+            lat: 50.5 + 0.5 / DEM_SIZE,
+            lon: 50.5 + 0.5 / DEM_SIZE,
+        };
+        assert!(dem.has_data(&p));
+        assert!(!dem.has_data(&GeoPoint {
+            lat: 50.0,
+            lon: 50.0
+        }));
+        assert!(!dem.has_data(&GeoPoint {
+            lat: 51.5,
+            lon: 50.5
+        }));
+    }
+
     /// Test for elevation calculation at a specific point
     ///
     /// This test verifies that the elevation calculation works correctly by creating
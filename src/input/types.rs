@@ -153,6 +153,12 @@ pub trait TileData<'a> {
     /// at an arbitrary geographic point within the tile.
     fn calc_height(&self, geo_point: &GeoPoint) -> Option<Height>;
 
+    /// Checks that the tile has valid (not nodata) elevation at geographic coordinates
+    ///
+    /// Heights of nodata cells and points out of the tile are filled with the sea level,
+    /// this method tells them apart from sampled sea level.
+    fn has_data(&self, geo_point: &GeoPoint) -> bool;
+
     /// Loads a DEM tile from the specified directory with given options
     ///
     /// This static method loads a tile from disk using the provided directory
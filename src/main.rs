@@ -10,8 +10,15 @@ mod model;
 use common::args::*;
 use common::settings::Settings;
 use common::types::ErrBox;
+use model::collada::Collada;
+use model::gltf::Gltf;
 use model::obj::Obj;
+use model::ply::Ply;
+use model::stl::Stl;
+use model::threemf::ThreeMf;
 use model::types::Model;
+use model::usd::Usd;
+use model::x3d::X3D;
 use model::x3dgeospatial::X3DGeospatial;
 
 const HARDCODED_CONFIG_FILE: &str = "./settings.yaml";
@@ -20,7 +27,7 @@ const HARDCODED_CONFIG_FILE: &str = "./settings.yaml";
 ///
 /// This function orchestrates the creation of a 3D model based on the provided
 /// command-line arguments (`tl_commands`). It reads settings from a YAML configuration file
-/// and initializes the appropriate model type (either `X3DGeospatial` or `Obj`) using these settings.
+/// and initializes the appropriate model type (`X3DGeospatial`, `Obj`, `Gltf`, `Stl`, `Ply`, `ThreeMf`, `Usd`, `X3D` or `Collada`) using these settings.
 /// The created model is then saved to disk.
 ///
 /// ## Arguments
@@ -39,6 +46,27 @@ fn materialize(tl_commands: &TopLevelCommands) -> Result<(), ErrBox> {
         MySubCommandEnum::SubCommandObj(args) => {
             Ok(Obj::create(args.model_type, &settings)?.save()?)
         }
+        MySubCommandEnum::SubCommandGltf(args) => {
+            Ok(Gltf::create(args.model_type, &settings)?.save()?)
+        }
+        MySubCommandEnum::SubCommandStl(args) => {
+            Ok(Stl::create(args.model_type, &settings)?.save()?)
+        }
+        MySubCommandEnum::SubCommandPly(args) => {
+            Ok(Ply::create(args.model_type, &settings)?.save()?)
+        }
+        MySubCommandEnum::SubCommandThreeMf(args) => {
+            Ok(ThreeMf::create(args.model_type, &settings)?.save()?)
+        }
+        MySubCommandEnum::SubCommandUsd(args) => {
+            Ok(Usd::create(args.model_type, &settings)?.save()?)
+        }
+        MySubCommandEnum::SubCommandX3D(args) => {
+            Ok(X3D::create(args.model_type, &settings)?.save()?)
+        }
+        MySubCommandEnum::SubCommandCollada(args) => {
+            Ok(Collada::create(args.model_type, &settings)?.save()?)
+        }
     }
 }
 
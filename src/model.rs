@@ -1,3 +1,30 @@
+pub mod collada;
+pub mod compression;
+pub mod edits;
+pub mod erosion;
+pub mod filter;
+pub mod frame;
+pub mod gltf;
+pub mod mask;
+pub mod mesh;
+pub mod normals;
 pub mod obj;
+pub mod ply;
+pub mod refine;
+pub mod relief;
+pub mod simplify;
+pub mod solid;
+pub mod split;
+pub mod stl;
+pub mod surface;
+pub mod tessellation;
+#[cfg(test)]
+pub mod testing;
+pub mod threemf;
 pub mod types;
+pub mod units;
+pub mod usd;
+pub mod vector;
+pub mod vrml;
+pub mod x3d;
 pub mod x3dgeospatial;
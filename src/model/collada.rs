@@ -0,0 +1,631 @@
+//! # Collada Model Generation Module
+//!
+//! This module writes models as Collada 1.4.1 documents (`.dae`) for older engine
+//! importers and SketchUp workflows.
+//!
+//! ## Features
+//!
+//! - **Geometry**: the `Surface` mesh with position, normal, texture coordinate
+//!   (**Texture** models) and vertex color (**Color** models) sources, with one
+//!   index per vertex (see `mesh`), so all inputs of `triangles` share offset 0
+//! - **Materials**: a Lambert or Phong effect with the texture `texture_uri`, or
+//!   white for vertex colors. Submeshes (cube-sphere faces) are `triangles` of
+//!   their own materials with textures named as in OBJ materials
+//! - **Asset**: `unit` of physical units or ECEF coordinates, `up_axis` of the frame
+//! - **Metadata**: the planet name, radius, elevation scale, model size and data
+//!   source are written to `extra` of the planet node
+//!
+//! Vertices are placed by the same parameters as OBJ vertices (see `surface`).
+//!
+//! ## Configuration Parameters
+//!
+//! - `texture_uri`: texture image of texture models (default: "texture.png")
+//! - `shading`: "lambert" (default) or "phong"
+//! - `normals`, `crease_angle`: see `normals`
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::frame::*;
+use crate::model::mesh::*;
+use crate::model::normals::*;
+use crate::model::obj::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::writer::Writer;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_TEXTURE_URI: &str = "texture.png";
+const COLLADA_NAMESPACE: &str = "http://www.collada.org/2005/11/COLLADASchema";
+const MESH_ID: &str = "Surface";
+
+/// Shading model of Collada materials
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shading {
+    Lambert,
+    Phong,
+}
+
+impl Shading {
+    /// Reads the shading model from the `shading` parameter
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings.get_parameter_str("shading", "lambert")?.as_str() {
+            "lambert" => Ok(Shading::Lambert),
+            "phong" => Ok(Shading::Phong),
+            s => Err(format!("Unknown shading '{}'", s).into()),
+        }
+    }
+}
+
+/// Document-level data of the Collada model
+#[derive(Debug, Clone, PartialEq)]
+struct Asset {
+    /// Planet name, the title of the document and the name of the planet node
+    title: String,
+    /// Creation time in ISO 8601 format
+    created: String,
+    /// Up axis of the document
+    up: UpAxis,
+    /// Name of units and metres per unit, if known
+    unit: Option<(&'static str, Coord)>,
+    /// Planet parameters written to `extra` of the planet node
+    extra: Vec<(&'static str, String)>,
+    /// Texture URIs of the mesh primitives (`None` for color models)
+    textures: Option<Vec<String>>,
+    shading: Shading,
+}
+
+/// Collada model structure
+///
+/// This struct represents a 3D geospatial model in Collada format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct Collada<'a> {
+    model_type: ModelType,
+    model_size: GeoPointIndex,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    texture_uri: String,
+    shading: Shading,
+    normal_opts: Option<NormalOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for Collada<'a> {
+    /// No files are required, textures are referenced by their URIs
+    fn options_check(_settings: &'a Settings) -> Result<(), ErrBox> {
+        Ok(())
+    }
+
+    /// Builds and constructs a Collada model instance
+    fn build_model(
+        model_type: ModelType,
+        model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        Ok(Collada {
+            model_type,
+            model_size,
+            settings,
+            surface: Surface::from_settings(settings, &components)?,
+            texture_uri: settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?,
+            shading: Shading::from_settings(settings)?,
+            normal_opts: NormalOpts::from_settings(settings)?,
+            components,
+        })
+    }
+
+    /// Saves the model to the dae file
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        let normals = self.normal_opts.as_ref().map(|opts| solid.normals(opts));
+        let mesh = IndexedMesh::from_solid(
+            &solid,
+            normals.as_ref(),
+            match self.model_type {
+                ModelType::Texture => None,
+                ModelType::Color => Some(self.components.get_colors()?),
+            },
+            self.components.submeshes.as_ref(),
+            &self.surface.frame,
+            origin,
+        )?;
+
+        let frame = &self.surface.frame;
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let asset = Asset {
+            title: planet_name.clone(),
+            created: iso_date_time(seconds),
+            up: frame.up,
+            unit: match &self.surface.physical_size {
+                Some(physical_size) => {
+                    Some((physical_size.units.name(), physical_size.units.metres()))
+                }
+                None if frame.is_ecef() => Some(("meter", 1.0)),
+                None => None,
+            },
+            extra: vec![
+                ("planet_name", planet_name.clone()),
+                ("radius", self.surface.radius.to_string()),
+                ("scale", self.surface.scale.to_string()),
+                ("model_size", self.model_size.to_string()),
+                ("data_source", format!("{:?}", settings.data_source)),
+            ],
+            textures: match self.model_type {
+                ModelType::Texture => Some(
+                    mesh.primitives
+                        .iter()
+                        .map(|(name, _)| match name {
+                            Some(name) => make_tile_uri(&self.texture_uri, name),
+                            None => self.texture_uri.clone(),
+                        })
+                        .collect(),
+                ),
+                ModelType::Color => None,
+            },
+            shading: self.shading,
+        };
+
+        let result_path = Path::new(&output_path)
+            .join(planet_name)
+            .with_extension("dae");
+        let file = File::create(&result_path)
+            .map_err(|err| format!("Can't create {:?}: {}", result_path, err))?;
+        write_document(BufWriter::new(file), &mesh, &asset)
+            .map_err(|err| format!("Can't write dae file {:?}: {}", result_path, err).into())
+    }
+}
+
+/// Writes the Collada document of the mesh
+fn write_document(writer: impl Write, mesh: &IndexedMesh, asset: &Asset) -> std::io::Result<()> {
+    let mut writer = Writer::new_with_indent(writer, b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+    let mut collada = BytesStart::new("COLLADA");
+    collada.push_attribute(("xmlns", COLLADA_NAMESPACE));
+    collada.push_attribute(("version", "1.4.1"));
+    writer.write_event(Event::Start(collada))?;
+
+    write_start(&mut writer, "asset", &[])?;
+    write_start(&mut writer, "contributor", &[])?;
+    write_text(&mut writer, "authoring_tool", &[], "plmat")?;
+    writer.write_event(Event::End(BytesEnd::new("contributor")))?;
+    write_text(&mut writer, "created", &[], &asset.created)?;
+    write_text(&mut writer, "modified", &[], &asset.created)?;
+    write_text(&mut writer, "title", &[], &asset.title)?;
+    if let Some((name, metres)) = asset.unit {
+        let mut unit = BytesStart::new("unit");
+        unit.push_attribute(("name", name));
+        unit.push_attribute(("meter", metres.to_string().as_str()));
+        writer.write_event(Event::Empty(unit))?;
+    }
+    let up = match asset.up {
+        UpAxis::Y => "Y_UP",
+        UpAxis::Z => "Z_UP",
+    };
+    write_text(&mut writer, "up_axis", &[], up)?;
+    writer.write_event(Event::End(BytesEnd::new("asset")))?;
+
+    let materials: Vec<String> = mesh
+        .primitives
+        .iter()
+        .map(|(name, _)| match name {
+            Some(name) => format!("Material_{}", name),
+            None => "Material".to_string(),
+        })
+        .collect();
+
+    if let Some(textures) = &asset.textures {
+        write_start(&mut writer, "library_images", &[])?;
+        for (material, texture) in materials.iter().zip(textures) {
+            let id = format!("{}-image", material);
+            write_start(&mut writer, "image", &[("id", &id), ("name", &id)])?;
+            write_text(&mut writer, "init_from", &[], texture)?;
+            writer.write_event(Event::End(BytesEnd::new("image")))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("library_images")))?;
+    }
+
+    write_start(&mut writer, "library_effects", &[])?;
+    for material in &materials {
+        write_effect(&mut writer, material, asset)?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("library_effects")))?;
+
+    write_start(&mut writer, "library_materials", &[])?;
+    for material in &materials {
+        write_start(
+            &mut writer,
+            "material",
+            &[("id", material), ("name", material)],
+        )?;
+        let mut effect = BytesStart::new("instance_effect");
+        effect.push_attribute(("url", format!("#{}-effect", material).as_str()));
+        writer.write_event(Event::Empty(effect))?;
+        writer.write_event(Event::End(BytesEnd::new("material")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("library_materials")))?;
+
+    write_start(&mut writer, "library_geometries", &[])?;
+    let mesh_id = format!("{}-mesh", MESH_ID);
+    write_start(
+        &mut writer,
+        "geometry",
+        &[("id", &mesh_id), ("name", MESH_ID)],
+    )?;
+    write_start(&mut writer, "mesh", &[])?;
+    let vec3 = |(x, y, z): &Point3d| format!("{} {} {}", *x as f32, *y as f32, *z as f32);
+    let mut inputs = vec![("VERTEX", "vertices")];
+    write_source(
+        &mut writer,
+        "positions",
+        "XYZ",
+        mesh.positions.iter().map(vec3),
+    )?;
+    if !mesh.normals.is_empty() {
+        write_source(&mut writer, "normals", "XYZ", mesh.normals.iter().map(vec3))?;
+        inputs.push(("NORMAL", "normals"));
+    }
+    if !mesh.texture_coordinates.is_empty() {
+        let st = |(s, t): &(Coord, Coord)| format!("{} {}", *s as f32, *t as f32);
+        write_source(
+            &mut writer,
+            "uvs",
+            "ST",
+            mesh.texture_coordinates.iter().map(st),
+        )?;
+        inputs.push(("TEXCOORD", "uvs"));
+    }
+    if !mesh.colors.is_empty() {
+        write_source(
+            &mut writer,
+            "colors",
+            "RGB",
+            mesh.colors.iter().map(|color| color.to_string()),
+        )?;
+        inputs.push(("COLOR", "colors"));
+    }
+    let vertices_id = format!("{}-vertices", MESH_ID);
+    write_start(&mut writer, "vertices", &[("id", &vertices_id)])?;
+    let mut input = BytesStart::new("input");
+    input.push_attribute(("semantic", "POSITION"));
+    input.push_attribute(("source", format!("#{}-positions", MESH_ID).as_str()));
+    writer.write_event(Event::Empty(input))?;
+    writer.write_event(Event::End(BytesEnd::new("vertices")))?;
+    for ((_, triangles), material) in mesh.primitives.iter().zip(&materials) {
+        let count = triangles.len().to_string();
+        write_start(
+            &mut writer,
+            "triangles",
+            &[("material", material), ("count", &count)],
+        )?;
+        for (semantic, source) in &inputs {
+            let mut input = BytesStart::new("input");
+            input.push_attribute(("semantic", *semantic));
+            input.push_attribute(("source", format!("#{}-{}", MESH_ID, source).as_str()));
+            input.push_attribute(("offset", "0"));
+            if matches!(*semantic, "TEXCOORD" | "COLOR") {
+                input.push_attribute(("set", "0"));
+            }
+            writer.write_event(Event::Empty(input))?;
+        }
+        let indices = triangles
+            .iter()
+            .flat_map(|triangle| triangle.map(|i| i.to_string()))
+            .collect::<Vec<String>>()
+            .join(" ");
+        write_text(&mut writer, "p", &[], &indices)?;
+        writer.write_event(Event::End(BytesEnd::new("triangles")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("mesh")))?;
+    writer.write_event(Event::End(BytesEnd::new("geometry")))?;
+    writer.write_event(Event::End(BytesEnd::new("library_geometries")))?;
+
+    write_start(&mut writer, "library_visual_scenes", &[])?;
+    write_start(
+        &mut writer,
+        "visual_scene",
+        &[("id", "Scene"), ("name", "Scene")],
+    )?;
+    write_start(
+        &mut writer,
+        "node",
+        &[("id", "Planet"), ("name", &asset.title), ("type", "NODE")],
+    )?;
+    write_start(
+        &mut writer,
+        "instance_geometry",
+        &[("url", &format!("#{}", mesh_id)), ("name", MESH_ID)],
+    )?;
+    write_start(&mut writer, "bind_material", &[])?;
+    write_start(&mut writer, "technique_common", &[])?;
+    for material in &materials {
+        let target = format!("#{}", material);
+        write_start(
+            &mut writer,
+            "instance_material",
+            &[("symbol", material), ("target", &target)],
+        )?;
+        if asset.textures.is_some() {
+            let mut bind = BytesStart::new("bind_vertex_input");
+            bind.push_attribute(("semantic", "UVMap"));
+            bind.push_attribute(("input_semantic", "TEXCOORD"));
+            bind.push_attribute(("input_set", "0"));
+            writer.write_event(Event::Empty(bind))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("instance_material")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("technique_common")))?;
+    writer.write_event(Event::End(BytesEnd::new("bind_material")))?;
+    writer.write_event(Event::End(BytesEnd::new("instance_geometry")))?;
+    write_start(&mut writer, "extra", &[])?;
+    write_start(&mut writer, "technique", &[("profile", "plmat")])?;
+    for (name, value) in &asset.extra {
+        write_text(&mut writer, name, &[], value)?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("technique")))?;
+    writer.write_event(Event::End(BytesEnd::new("extra")))?;
+    writer.write_event(Event::End(BytesEnd::new("node")))?;
+    writer.write_event(Event::End(BytesEnd::new("visual_scene")))?;
+    writer.write_event(Event::End(BytesEnd::new("library_visual_scenes")))?;
+
+    write_start(&mut writer, "scene", &[])?;
+    let mut scene = BytesStart::new("instance_visual_scene");
+    scene.push_attribute(("url", "#Scene"));
+    writer.write_event(Event::Empty(scene))?;
+    writer.write_event(Event::End(BytesEnd::new("scene")))?;
+    writer.write_event(Event::End(BytesEnd::new("COLLADA")))?;
+    writer.into_inner().flush()
+}
+
+/// Writes the effect of a material, sampling its texture or white for vertex colors
+fn write_effect<W: Write>(
+    writer: &mut Writer<W>,
+    material: &str,
+    asset: &Asset,
+) -> std::io::Result<()> {
+    let id = format!("{}-effect", material);
+    write_start(writer, "effect", &[("id", &id)])?;
+    write_start(writer, "profile_COMMON", &[])?;
+    let textured = asset.textures.is_some();
+    if textured {
+        let surface = format!("{}-surface", material);
+        write_start(writer, "newparam", &[("sid", &surface)])?;
+        write_start(writer, "surface", &[("type", "2D")])?;
+        write_text(writer, "init_from", &[], &format!("{}-image", material))?;
+        writer.write_event(Event::End(BytesEnd::new("surface")))?;
+        writer.write_event(Event::End(BytesEnd::new("newparam")))?;
+        write_start(
+            writer,
+            "newparam",
+            &[("sid", &format!("{}-sampler", material))],
+        )?;
+        write_start(writer, "sampler2D", &[])?;
+        write_text(writer, "source", &[], &surface)?;
+        writer.write_event(Event::End(BytesEnd::new("sampler2D")))?;
+        writer.write_event(Event::End(BytesEnd::new("newparam")))?;
+    }
+    write_start(writer, "technique", &[("sid", "common")])?;
+    let shading = match asset.shading {
+        Shading::Lambert => "lambert",
+        Shading::Phong => "phong",
+    };
+    write_start(writer, shading, &[])?;
+    write_start(writer, "diffuse", &[])?;
+    if textured {
+        let mut texture = BytesStart::new("texture");
+        texture.push_attribute(("texture", format!("{}-sampler", material).as_str()));
+        texture.push_attribute(("texcoord", "UVMap"));
+        writer.write_event(Event::Empty(texture))?;
+    } else {
+        write_text(writer, "color", &[("sid", "diffuse")], "1 1 1 1")?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("diffuse")))?;
+    if let Shading::Phong = asset.shading {
+        write_start(writer, "specular", &[])?;
+        write_text(writer, "color", &[("sid", "specular")], "0.1 0.1 0.1 1")?;
+        writer.write_event(Event::End(BytesEnd::new("specular")))?;
+        write_start(writer, "shininess", &[])?;
+        write_text(writer, "float", &[("sid", "shininess")], "10")?;
+        writer.write_event(Event::End(BytesEnd::new("shininess")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new(shading)))?;
+    writer.write_event(Event::End(BytesEnd::new("technique")))?;
+    writer.write_event(Event::End(BytesEnd::new("profile_COMMON")))?;
+    writer.write_event(Event::End(BytesEnd::new("effect")))
+}
+
+/// Writes a float source of the mesh with an accessor of the named parameters
+fn write_source<W: Write>(
+    writer: &mut Writer<W>,
+    name: &str,
+    params: &str,
+    values: impl ExactSizeIterator<Item = String>,
+) -> std::io::Result<()> {
+    let id = format!("{}-{}", MESH_ID, name);
+    let array_id = format!("{}-array", id);
+    let count = values.len();
+    let stride = params.len();
+    write_start(writer, "source", &[("id", &id)])?;
+    write_text(
+        writer,
+        "float_array",
+        &[("id", &array_id), ("count", &(count * stride).to_string())],
+        &values.collect::<Vec<String>>().join(" "),
+    )?;
+    write_start(writer, "technique_common", &[])?;
+    write_start(
+        writer,
+        "accessor",
+        &[
+            ("source", &format!("#{}", array_id)),
+            ("count", &count.to_string()),
+            ("stride", &stride.to_string()),
+        ],
+    )?;
+    for param in params.chars() {
+        let mut elem = BytesStart::new("param");
+        elem.push_attribute(("name", param.to_string().as_str()));
+        elem.push_attribute(("type", "float"));
+        writer.write_event(Event::Empty(elem))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("accessor")))?;
+    writer.write_event(Event::End(BytesEnd::new("technique_common")))?;
+    writer.write_event(Event::End(BytesEnd::new("source")))
+}
+
+/// Writes a start tag with attributes
+fn write_start<W: Write>(
+    writer: &mut Writer<W>,
+    name: &str,
+    attributes: &[(&str, &str)],
+) -> std::io::Result<()> {
+    let mut elem = BytesStart::new(name);
+    elem.extend_attributes(attributes.iter().copied());
+    writer.write_event(Event::Start(elem))
+}
+
+/// Writes an element with attributes and text
+fn write_text<W: Write>(
+    writer: &mut Writer<W>,
+    name: &str,
+    attributes: &[(&str, &str)],
+    text: &str,
+) -> std::io::Result<()> {
+    write_start(writer, name, attributes)?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(BytesEnd::new(name)))
+}
+
+/// Formats seconds since the Unix epoch as an ISO 8601 UTC date and time
+fn iso_date_time(seconds: u64) -> String {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    // days to a civil date, the era starts on March 1, 0000
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::color::*;
+
+    fn make_mesh() -> IndexedMesh {
+        IndexedMesh {
+            positions: vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+            normals: vec![(0.0, 0.0, 1.0); 3],
+            sources: vec![0, 1, 2],
+            primitives: vec![(None, vec![[0, 1, 2]])],
+            ..Default::default()
+        }
+    }
+
+    fn make_asset() -> Asset {
+        Asset {
+            title: "Test".to_string(),
+            created: iso_date_time(0),
+            up: UpAxis::Y,
+            unit: Some(("millimeter", 0.001)),
+            extra: vec![("model_size", "16".to_string())],
+            textures: None,
+            shading: Shading::Lambert,
+        }
+    }
+
+    fn write(mesh: &IndexedMesh, asset: &Asset) -> String {
+        let mut bytes = vec![];
+        write_document(&mut bytes, mesh, asset).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn iso_date_time_t0() {
+        assert_eq!(iso_date_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_date_time(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso_date_time(1792281600 + 3723), "2026-10-18T01:02:03Z");
+    }
+
+    #[test]
+    fn write_document_t0() {
+        let mut mesh = make_mesh();
+        mesh.colors = vec![RGB(1.0, 0.0, 0.0), RGB(0.0, 1.0, 0.0), RGB(0.0, 0.0, 1.0)];
+        let dae = write(&mesh, &make_asset());
+        assert!(dae.contains(
+            r#"<COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">"#
+        ));
+        assert!(dae.contains(r#"<unit name="millimeter" meter="0.001"/>"#));
+        assert!(dae.contains("<up_axis>Y_UP</up_axis>"));
+        assert!(dae.contains(
+            r#"<float_array id="Surface-positions-array" count="9">0 0 0 1 0 0 0 1 0</float_array>"#
+        ));
+        assert!(dae.contains(
+            r#"<float_array id="Surface-colors-array" count="9">1 0 0 0 1 0 0 0 1</float_array>"#
+        ));
+        assert!(dae.contains(
+            r##"<input semantic="COLOR" source="#Surface-colors" offset="0" set="0"/>"##
+        ));
+        assert!(dae.contains(r#"<triangles material="Material" count="1">"#));
+        assert!(dae.contains("<p>0 1 2</p>"));
+        assert!(dae.contains(r#"<color sid="diffuse">1 1 1 1</color>"#));
+        assert!(dae.contains("<model_size>16</model_size>"));
+        assert!(!dae.contains("library_images"));
+        assert!(!dae.contains("<phong>"));
+    }
+
+    #[test]
+    fn write_document_t1() {
+        let mut mesh = make_mesh();
+        mesh.texture_coordinates = vec![(0.0, 0.0), (0.5, 0.0), (0.0, 1.0)];
+        mesh.primitives = vec![(Some("px".to_string()), vec![[0, 1, 2]])];
+        let asset = Asset {
+            textures: Some(vec!["image_px.png".to_string()]),
+            shading: Shading::Phong,
+            ..make_asset()
+        };
+        let dae = write(&mesh, &asset);
+        assert!(dae.contains(r#"<image id="Material_px-image" name="Material_px-image">"#));
+        assert!(dae.contains("<init_from>image_px.png</init_from>"));
+        assert!(dae.contains(r#"<texture texture="Material_px-sampler" texcoord="UVMap"/>"#));
+        assert!(dae.contains("<phong>"));
+        assert!(dae.contains(
+            r##"<input semantic="TEXCOORD" source="#Surface-uvs" offset="0" set="0"/>"##
+        ));
+        assert!(dae.contains(r#"<triangles material="Material_px" count="1">"#));
+        assert!(dae.contains(
+            r#"<bind_vertex_input semantic="UVMap" input_semantic="TEXCOORD" input_set="0"/>"#
+        ));
+    }
+}
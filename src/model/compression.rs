@@ -0,0 +1,165 @@
+//! # Output Compression
+//!
+//! This module compresses large text outputs (OBJ files and X3D scenes) while
+//! they are written. Files are written through streaming gzip or zstd encoders,
+//! so the compressed file is never held in memory.
+//!
+//! Compressed files get the extension of the encoder appended (`.obj.gz`,
+//! `.obj.zst`), except the gzipped X3D and VRML extensions that browsers know:
+//! `.x3dz`, `.x3dvz` and `.wrz`.
+//!
+//! ## Configuration Parameters
+//!
+//! - `compression`: "none" (default), "gzip" or "zstd"
+//! - `compression_level`: level of the encoder (default: 6 for gzip, 3 for zstd)
+use crate::common::settings::*;
+use crate::common::types::*;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Default level of gzip compression
+const DEFAULT_GZIP_LEVEL: u32 = 6;
+/// Default level of zstd compression
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Compression of output files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip(u32),
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Reads the compression from the `compression` and `compression_level` parameters
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings.get_parameter_str("compression", "none")?.as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" => {
+                let level = settings.get_parameter_num("compression_level", DEFAULT_GZIP_LEVEL)?;
+                if level > 9 {
+                    return Err(format!("Gzip compression level {} isn't in 0..9", level).into());
+                }
+                Ok(Compression::Gzip(level))
+            }
+            "zstd" => {
+                let level = settings.get_parameter_num("compression_level", DEFAULT_ZSTD_LEVEL)?;
+                if !zstd::compression_level_range().contains(&level) {
+                    return Err(format!("Unsupported zstd compression level {}", level).into());
+                }
+                Ok(Compression::Zstd(level))
+            }
+            s => Err(format!("Unknown compression '{}'", s).into()),
+        }
+    }
+
+    /// Returns the extension of compressed files with the `extension`
+    pub fn extension(&self, extension: &str) -> String {
+        match (self, extension) {
+            (Compression::None, _) => extension.to_string(),
+            (Compression::Gzip(_), "x3d") => "x3dz".to_string(),
+            (Compression::Gzip(_), "x3dv") => "x3dvz".to_string(),
+            (Compression::Gzip(_), "wrl") => "wrz".to_string(),
+            (Compression::Gzip(_), _) => format!("{}.gz", extension),
+            (Compression::Zstd(_), _) => format!("{}.zst", extension),
+        }
+    }
+
+    /// Creates the output file, written through the encoder
+    pub fn create(&self, path: &Path) -> io::Result<OutputFile> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match self {
+            Compression::None => OutputFile::Plain(file),
+            Compression::Gzip(level) => {
+                OutputFile::Gzip(GzEncoder::new(file, flate2::Compression::new(*level)))
+            }
+            Compression::Zstd(level) => OutputFile::Zstd(zstd::Encoder::new(file, *level)?),
+        })
+    }
+}
+
+/// Output file, possibly compressed
+///
+/// `finish` must be called after writing to end the compressed stream.
+pub enum OutputFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl OutputFile {
+    /// Ends the compressed stream and flushes the file
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            OutputFile::Plain(file) => file,
+            OutputFile::Gzip(encoder) => encoder.finish()?,
+            OutputFile::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(file) => file.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf),
+            OutputFile::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(file) => file.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush(),
+            OutputFile::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn extension_t0() {
+        assert_eq!(Compression::None.extension("obj"), "obj");
+        assert_eq!(Compression::Gzip(6).extension("obj"), "obj.gz");
+        assert_eq!(Compression::Gzip(6).extension("x3d"), "x3dz");
+        assert_eq!(Compression::Gzip(6).extension("wrl"), "wrz");
+        assert_eq!(Compression::Zstd(3).extension("x3d"), "x3d.zst");
+    }
+
+    #[test]
+    fn create_t0() {
+        let dir = std::env::temp_dir().join(format!("plmat_create_t0_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = "v 0.00000 0.00000 1.00000\n".repeat(100);
+        for (compression, name) in [
+            (Compression::Gzip(6), "plmat_create_t0.obj.gz"),
+            (Compression::Zstd(3), "plmat_create_t0.obj.zst"),
+        ] {
+            let path = dir.join(name);
+            let mut file = compression.create(&path).unwrap();
+            file.write_all(data.as_bytes()).unwrap();
+            file.finish().unwrap();
+
+            let file = File::open(&path).unwrap();
+            let mut text = String::new();
+            match compression {
+                Compression::Gzip(_) => flate2::read::GzDecoder::new(file)
+                    .read_to_string(&mut text)
+                    .unwrap(),
+                _ => zstd::Decoder::new(file)
+                    .unwrap()
+                    .read_to_string(&mut text)
+                    .unwrap(),
+            };
+            assert_eq!(text, data);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+//! # Terrain Edits
+//!
+//! This module applies an edit layer to the sampled elevations, so that real DEMs
+//! can be adjusted: an island added, a plateau flattened, a canal carved.
+//!
+//! Edits are read from a GeoJSON file. Every feature is an edit applied in the
+//! file order; its geometry is the edited region and its properties are the operation:
+//!
+//! - `"operation": "set"` with `"height"`: sets elevations
+//! - `"operation": "add"` with `"offset"`: raises (or lowers) elevations
+//! - `"operation": "clamp"` with `"min"` and/or `"max"`: clamps elevations
+//! - `"falloff"`: width in km of the smooth transition around the region (default: 0)
+//! - `"width"`: width in km of the corridor along lines (default: 0)
+//!
+//! Polygons and multipolygons (with holes) edit their insides, line strings and
+//! points edit corridors of the given width around them. Distances are measured in
+//! a local equirectangular projection around each vertex, which is accurate for
+//! regions of up to several hundred kilometres.
+//!
+//! ## Configuration Parameters
+//!
+//! - `edits_file`: GeoJSON file with edits (FeatureCollection or Feature)
+//! - `radius`: planet radius in metres (default: 6378000.0)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::surface::DEFAULT_RADIUS;
+use crate::model::types::*;
+use serde_json::Value;
+use std::fs;
+
+/// Operation of an edit
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditOperation {
+    /// Sets elevations to the height
+    Set(Height),
+    /// Adds the offset to elevations
+    Add(Height),
+    /// Clamps elevations to the range
+    Clamp(Option<Height>, Option<Height>),
+}
+
+/// Region of an edit
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditRegion {
+    /// Polygons as rings of (lon, lat) points, holes included
+    Polygons(Vec<Vec<Vec<GeoPoint>>>),
+    /// Line strings (single points are lines of one point)
+    Lines(Vec<Vec<GeoPoint>>),
+}
+
+/// Single edit of the terrain
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    /// Operation
+    pub operation: EditOperation,
+    /// Region
+    pub region: EditRegion,
+    /// Width of the smooth transition around the region in km
+    pub falloff: Coord,
+    /// Width of corridors along lines in km
+    pub width: Coord,
+}
+
+/// Edit layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditOpts {
+    /// Edits in order of application
+    pub edits: Vec<Edit>,
+    /// Planet radius in km
+    pub radius: Coord,
+}
+
+impl EditOpts {
+    /// Reads the edit layer from the file given in the settings
+    ///
+    /// Returns `None` if no edits file is given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let path = settings.get_parameter_str("edits_file", "")?;
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("Can't read edits file '{}': {}", path, err))?;
+        let edits =
+            parse_edits(&text).map_err(|err| format!("Invalid edits file '{}': {}", path, err))?;
+        Ok(Some(EditOpts {
+            edits,
+            radius: settings.get_parameter_num("radius", DEFAULT_RADIUS)? / 1000.0,
+        }))
+    }
+}
+
+/// Parses edits from GeoJSON text
+pub fn parse_edits(text: &str) -> Result<Vec<Edit>, String> {
+    let json: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    geojson_features(&json)?
+        .iter()
+        .enumerate()
+        .map(|(i, feature)| parse_feature(feature).map_err(|err| format!("feature {}: {}", i, err)))
+        .collect()
+}
+
+/// Features of a FeatureCollection or a single Feature
+pub fn geojson_features(json: &Value) -> Result<Vec<&Value>, String> {
+    match json["type"].as_str() {
+        Some("FeatureCollection") => Ok(json["features"]
+            .as_array()
+            .ok_or("'features' must be an array")?
+            .iter()
+            .collect()),
+        Some("Feature") => Ok(vec![json]),
+        _ => Err("GeoJSON must be a FeatureCollection or a Feature".to_string()),
+    }
+}
+
+fn parse_feature(feature: &Value) -> Result<Edit, String> {
+    let properties = &feature["properties"];
+    let number = |name: &str| -> Result<Option<Height>, String> {
+        match &properties[name] {
+            Value::Null => Ok(None),
+            value => value
+                .as_f64()
+                .map(Some)
+                .ok_or(format!("'{}' must be a number", name)),
+        }
+    };
+    let required = |name: &str| -> Result<Height, String> {
+        number(name)?.ok_or(format!("'{}' is required", name))
+    };
+    let operation = match properties["operation"].as_str() {
+        Some("set") => EditOperation::Set(required("height")?),
+        Some("add") => EditOperation::Add(required("offset")?),
+        Some("clamp") => {
+            let (min, max) = (number("min")?, number("max")?);
+            if min.is_none() && max.is_none() {
+                return Err("'clamp' needs 'min' or 'max'".to_string());
+            }
+            EditOperation::Clamp(min, max)
+        }
+        Some(s) => return Err(format!("unknown operation '{}'", s)),
+        None => return Err("'operation' is required".to_string()),
+    };
+    Ok(Edit {
+        operation,
+        region: parse_geometry(&feature["geometry"])?,
+        falloff: number("falloff")?.unwrap_or(0.0).max(0.0),
+        width: number("width")?.unwrap_or(0.0).max(0.0),
+    })
+}
+
+fn parse_point(value: &Value) -> Result<GeoPoint, String> {
+    match value.as_array().map(|a| &a[..]) {
+        Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+            (Some(lon), Some(lat)) => Ok(GeoPoint { lon, lat }),
+            _ => Err("coordinates must be numbers".to_string()),
+        },
+        _ => Err("position must be [lon, lat]".to_string()),
+    }
+}
+
+fn parse_line(value: &Value) -> Result<Vec<GeoPoint>, String> {
+    value
+        .as_array()
+        .ok_or("line must be an array of positions")?
+        .iter()
+        .map(parse_point)
+        .collect()
+}
+
+fn parse_polygon(value: &Value) -> Result<Vec<Vec<GeoPoint>>, String> {
+    value
+        .as_array()
+        .ok_or("polygon must be an array of rings")?
+        .iter()
+        .map(parse_line)
+        .collect()
+}
+
+fn parse_array<T>(
+    value: &Value,
+    parse: impl Fn(&Value) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    value
+        .as_array()
+        .ok_or("coordinates must be an array")?
+        .iter()
+        .map(parse)
+        .collect()
+}
+
+/// Parses a GeoJSON geometry
+pub fn parse_geometry(geometry: &Value) -> Result<EditRegion, String> {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str() {
+        Some("Polygon") => Ok(EditRegion::Polygons(vec![parse_polygon(coordinates)?])),
+        Some("MultiPolygon") => Ok(EditRegion::Polygons(parse_array(
+            coordinates,
+            parse_polygon,
+        )?)),
+        Some("LineString") => Ok(EditRegion::Lines(vec![parse_line(coordinates)?])),
+        Some("MultiLineString") => Ok(EditRegion::Lines(parse_array(coordinates, parse_line)?)),
+        Some("Point") => Ok(EditRegion::Lines(vec![vec![parse_point(coordinates)?]])),
+        Some("MultiPoint") => Ok(EditRegion::Lines(
+            parse_array(coordinates, parse_point)?
+                .into_iter()
+                .map(|p| vec![p])
+                .collect(),
+        )),
+        Some(s) => Err(format!("unsupported geometry '{}'", s)),
+        None => Err("geometry is required".to_string()),
+    }
+}
+
+/// Projects a point to the plane tangent at `origin`, in km
+fn project(origin: &GeoPoint, p: &GeoPoint, radius: Coord) -> (Coord, Coord) {
+    let dlon = (p.lon - origin.lon + 180.0).rem_euclid(360.0) - 180.0;
+    (
+        dlon.to_radians() * origin.lat.to_radians().cos() * radius,
+        (p.lat - origin.lat).to_radians() * radius,
+    )
+}
+
+/// Distance from the origin to the segment
+fn segment_distance(a: (Coord, Coord), b: (Coord, Coord)) -> Coord {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let l2 = dx * dx + dy * dy;
+    let t = if l2 > 0.0 {
+        (-(a.0 * dx + a.1 * dy) / l2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a.0 + t * dx).hypot(a.1 + t * dy)
+}
+
+/// Distance from the origin to a line, in km
+fn line_distance(line: &[(Coord, Coord)]) -> Coord {
+    match line {
+        [] => Coord::INFINITY,
+        [p] => p.0.hypot(p.1),
+        _ => line
+            .windows(2)
+            .map(|w| segment_distance(w[0], w[1]))
+            .fold(Coord::INFINITY, Coord::min),
+    }
+}
+
+/// Returns true if the origin is inside of the ring (even-odd rule)
+fn ring_contains(ring: &[(Coord, Coord)]) -> bool {
+    let mut inside = false;
+    for (i, a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        if (a.1 > 0.0) != (b.1 > 0.0) && 0.0 < a.0 + (b.0 - a.0) * (0.0 - a.1) / (b.1 - a.1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+impl Edit {
+    /// Distance from a point to the edited region in km, zero inside of it
+    fn distance(&self, p: &GeoPoint, radius: Coord) -> Coord {
+        let project_line = |line: &[GeoPoint]| -> Vec<(Coord, Coord)> {
+            line.iter().map(|q| project(p, q, radius)).collect()
+        };
+        match &self.region {
+            EditRegion::Polygons(polygons) => polygons
+                .iter()
+                .map(|rings| {
+                    let rings: Vec<Vec<(Coord, Coord)>> =
+                        rings.iter().map(|ring| project_line(ring)).collect();
+                    let inside = rings.iter().filter(|ring| ring_contains(ring)).count() % 2 == 1;
+                    if inside {
+                        0.0
+                    } else {
+                        rings
+                            .iter()
+                            .map(|ring| {
+                                let mut closed = ring.clone();
+                                closed.extend(ring.first());
+                                line_distance(&closed)
+                            })
+                            .fold(Coord::INFINITY, Coord::min)
+                    }
+                })
+                .fold(Coord::INFINITY, Coord::min),
+            EditRegion::Lines(lines) => lines
+                .iter()
+                .map(|line| (line_distance(&project_line(line)) - self.width / 2.0).max(0.0))
+                .fold(Coord::INFINITY, Coord::min),
+        }
+    }
+
+    /// Weight of the edit at a point, from 1 inside of the region to 0 beyond the falloff
+    fn weight(&self, p: &GeoPoint, radius: Coord) -> Height {
+        let d = self.distance(p, radius);
+        if d <= 0.0 {
+            1.0
+        } else if d < self.falloff {
+            let t = 1.0 - d / self.falloff;
+            t * t * (3.0 - 2.0 * t)
+        } else {
+            0.0
+        }
+    }
+
+    /// Points of the region
+    fn points(&self) -> Vec<&GeoPoint> {
+        match &self.region {
+            EditRegion::Polygons(polygons) => polygons.iter().flatten().flatten().collect(),
+            EditRegion::Lines(lines) => lines.iter().flatten().collect(),
+        }
+    }
+
+    /// Edited elevation
+    fn apply(&self, h: Height, w: Height) -> Height {
+        let target = match self.operation {
+            EditOperation::Set(height) => height,
+            EditOperation::Add(offset) => h + offset,
+            EditOperation::Clamp(min, max) => {
+                let h = min.map_or(h, |min| h.max(min));
+                max.map_or(h, |max| h.min(max))
+            }
+        };
+        h + w * (target - h)
+    }
+}
+
+/// Applies the edit layer to the elevations of the model vertices
+pub fn edit_heights(heights: &mut Heights, vertices: &Vertices, opts: &EditOpts) {
+    for edit in &opts.edits {
+        // vertices further in latitude than the falloff are not affected
+        let margin = (edit.falloff + edit.width / 2.0) / opts.radius;
+        let (lat_min, lat_max) = edit
+            .points()
+            .iter()
+            .fold((Coord::INFINITY, Coord::NEG_INFINITY), |(min, max), p| {
+                (min.min(p.lat), max.max(p.lat))
+            });
+        let (lat_min, lat_max) = (lat_min - margin.to_degrees(), lat_max + margin.to_degrees());
+        for (k, h) in heights.iter_mut() {
+            let Some(p) = vertices.get(k) else {
+                continue;
+            };
+            if p.lat < lat_min || p.lat > lat_max {
+                continue;
+            }
+            let w = edit.weight(p, opts.radius);
+            if w > 0.0 {
+                *h = edit.apply(*h, w);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const EDITS: &str = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": {"operation": "set", "height": 500, "falloff": 100},
+                "geometry": {"type": "Polygon", "coordinates": [[[179, -1], [-179, -1], [-179, 1], [179, 1], [179, -1]]]}
+            },
+            {
+                "type": "Feature",
+                "properties": {"operation": "add", "offset": -50, "width": 20},
+                "geometry": {"type": "LineString", "coordinates": [[10, 0], [20, 0]]}
+            },
+            {
+                "type": "Feature",
+                "properties": {"operation": "clamp", "max": 100},
+                "geometry": {"type": "Point", "coordinates": [30, 0]}
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_edits_t0() {
+        let edits = parse_edits(EDITS).unwrap();
+        assert_eq!(edits.len(), 3);
+        assert_eq!(edits[0].operation, EditOperation::Set(500.0));
+        assert_eq!(edits[1].width, 20.0);
+        assert_eq!(edits[2].operation, EditOperation::Clamp(None, Some(100.0)));
+        assert!(parse_edits(r#"{"type": "Feature", "properties": {"operation": "set"}}"#).is_err());
+        assert!(parse_edits("[]").is_err());
+    }
+
+    #[test]
+    fn edit_heights_t0() {
+        let points = [
+            (180.0, 0.0), // inside of the island crossing the antimeridian
+            (178.5, 0.0), // in the falloff of the island
+            (170.0, 0.0), // far from the island
+            (15.0, 0.05), // in the canal
+            (15.0, 1.0),  // beside the canal
+            (30.0, 0.0),  // at the clamped point
+        ];
+        let vertices: Vertices = points
+            .iter()
+            .enumerate()
+            .map(|(k, (lon, lat))| {
+                (
+                    k,
+                    GeoPoint {
+                        lon: *lon,
+                        lat: *lat,
+                    },
+                )
+            })
+            .collect();
+        let mut heights: Heights = (0..points.len()).map(|k| (k, 200.0)).collect();
+        let opts = EditOpts {
+            edits: parse_edits(EDITS).unwrap(),
+            radius: 6378.0,
+        };
+        edit_heights(&mut heights, &vertices, &opts);
+        let expected: BTreeMap<usize, Height> =
+            [(0, 500.0), (2, 200.0), (3, 150.0), (4, 200.0), (5, 100.0)].into();
+        for (k, h) in expected {
+            assert_eq!(heights[&k], h);
+        }
+        assert!(heights[&1] > 200.0 && heights[&1] < 500.0);
+    }
+}
@@ -0,0 +1,425 @@
+//! # Terrain Erosion
+//!
+//! This module post-processes the sampled elevations of procedurally generated
+//! or painted worlds with erosion simulations, so that raw noise gets valleys,
+//! ridges and debris slopes.
+//!
+//! Simulations run on the vertex graph of the model mesh: neighbours of a vertex
+//! are the vertices sharing a face with it, and edge lengths are great circle
+//! distances. So they work the same at all latitudes and across the antimeridian.
+//!
+//! ## Thermal Erosion
+//!
+//! Material slides from a vertex to its lower neighbours where the slope is
+//! steeper than the talus angle. Every iteration moves a part of the excess,
+//! computed for all vertices at once, so the result doesn't depend on the vertex order.
+//!
+//! ## Hydraulic Erosion
+//!
+//! Droplets start at random vertices and flow along the steepest descent. A droplet
+//! carries sediment up to its capacity, which grows with the slope, the speed and
+//! the amount of water. It erodes the terrain while it carries less than the
+//! capacity, and deposits sediment when it carries more. Water evaporates at
+//! every step. When a droplet stops in a pit, at the sea level or after its last
+//! step, its sediment fills the pit up to the lowest neighbour and the rest is
+//! spread over the vertex and its neighbours, so material is conserved.
+//!
+//! Droplet starts come from a seeded generator, so results are reproducible.
+//!
+//! ## Configuration Parameters
+//!
+//! - `erosion_seed`: seed of the random generator (default: 0)
+//! - `erosion_thermal_iterations`: number of thermal erosion iterations (default: 0)
+//! - `erosion_talus_angle`: steepest stable slope in degrees (default: 35.0)
+//! - `erosion_droplets`: number of hydraulic erosion droplets (default: 0)
+//! - `erosion_droplet_steps`: maximum number of steps of a droplet (default: 64)
+//! - `erosion_capacity`: sediment capacity of a droplet in metres per unit slope (default: 50.0)
+//! - `erosion_rate`: part of the free capacity eroded at a step (default: 0.3)
+//! - `erosion_deposition`: part of the excess sediment deposited at a step (default: 0.3)
+//! - `erosion_evaporation`: part of the water evaporated at a step (default: 0.02)
+//! - `radius`: planet radius in metres (default: 6378000.0)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::surface::DEFAULT_RADIUS;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::collections::{BTreeSet, HashMap};
+
+const DEFAULT_TALUS_ANGLE: f64 = 35.0;
+const DEFAULT_DROPLET_STEPS: usize = 64;
+const DEFAULT_CAPACITY: f64 = 50.0;
+const DEFAULT_EROSION_RATE: f64 = 0.3;
+const DEFAULT_DEPOSITION: f64 = 0.3;
+const DEFAULT_EVAPORATION: f64 = 0.02;
+/// Part of the excess slope removed by a thermal erosion iteration
+const THERMAL_RATE: f64 = 0.25;
+/// Slope used for the capacity of droplets on flat terrain
+const MIN_SLOPE: f64 = 0.001;
+/// Acceleration of droplets per unit slope
+const GRAVITY: f64 = 4.0;
+
+/// Options of the erosion simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErosionOpts {
+    /// Seed of the random generator
+    pub seed: u64,
+    /// Number of thermal erosion iterations
+    pub thermal_iterations: usize,
+    /// Tangent of the talus angle
+    pub talus: Height,
+    /// Number of droplets
+    pub droplets: usize,
+    /// Maximum number of steps of a droplet
+    pub droplet_steps: usize,
+    /// Sediment capacity in metres per unit slope
+    pub capacity: Height,
+    /// Part of the free capacity eroded at a step
+    pub erosion_rate: Height,
+    /// Part of the excess sediment deposited at a step
+    pub deposition: Height,
+    /// Part of the water evaporated at a step
+    pub evaporation: Height,
+    /// Planet radius in metres
+    pub radius: Coord,
+}
+
+impl ErosionOpts {
+    /// Reads erosion options from the settings
+    ///
+    /// Returns `None` if neither thermal nor hydraulic erosion is enabled.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let thermal_iterations = settings.get_parameter_num("erosion_thermal_iterations", 0)?;
+        let droplets = settings.get_parameter_num("erosion_droplets", 0)?;
+        if thermal_iterations == 0 && droplets == 0 {
+            return Ok(None);
+        }
+        let talus_angle = settings.get_parameter_num("erosion_talus_angle", DEFAULT_TALUS_ANGLE)?;
+        if !(0.0..90.0).contains(&talus_angle) {
+            return Err("'erosion_talus_angle' parameter must be in [0, 90) degrees".into());
+        }
+        Ok(Some(ErosionOpts {
+            seed: settings.get_parameter_num("erosion_seed", 0)?,
+            thermal_iterations,
+            talus: talus_angle.to_radians().tan(),
+            droplets,
+            droplet_steps: settings
+                .get_parameter_num("erosion_droplet_steps", DEFAULT_DROPLET_STEPS)?,
+            capacity: settings.get_parameter_num("erosion_capacity", DEFAULT_CAPACITY)?,
+            erosion_rate: settings.get_parameter_num("erosion_rate", DEFAULT_EROSION_RATE)?,
+            deposition: settings.get_parameter_num("erosion_deposition", DEFAULT_DEPOSITION)?,
+            evaporation: settings.get_parameter_num("erosion_evaporation", DEFAULT_EVAPORATION)?,
+            radius: settings.get_parameter_num("radius", DEFAULT_RADIUS)?,
+        }))
+    }
+}
+
+/// SplitMix64 generator, small and reproducible on all platforms
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Random index below `n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Vertex graph of the mesh with edge lengths in metres
+struct VertexGraph {
+    neighbours: Vec<Vec<(usize, Coord)>>,
+}
+
+impl VertexGraph {
+    /// Makes the graph of the vertices, indexed by their positions in `keys`
+    fn new(
+        keys: &[GeoPointIndex],
+        vertices: &Vertices,
+        faces: &Faces,
+        texture_mapping: Option<&PointsMapping>,
+        radius: Coord,
+    ) -> Result<Self, ErrBox> {
+        let dense: HashMap<GeoPointIndex, usize> =
+            keys.iter().enumerate().map(|(i, k)| (*k, i)).collect();
+        let vertex = |t: &GeoPointIndex| -> Result<usize, ErrBox> {
+            let v = match texture_mapping {
+                Some(pmap) => pmap
+                    .get(t)
+                    .ok_or(format!("Point {} isn't found in points mapping", t))?,
+                None => t,
+            };
+            dense
+                .get(v)
+                .cloned()
+                .ok_or(format!("Vertex {} has no elevation", v).into())
+        };
+        let mut edges: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); keys.len()];
+        for (t0, t1, t2) in faces {
+            let [a, b, c] = [vertex(t0)?, vertex(t1)?, vertex(t2)?];
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                if p != q {
+                    edges[p].insert(q);
+                    edges[q].insert(p);
+                }
+            }
+        }
+        let points: Vec<Point3d> = keys
+            .iter()
+            .map(|k| {
+                vertices
+                    .get(k)
+                    .map(|gp| geo_to_point3d(gp.lon, gp.lat))
+                    .ok_or(format!("Vertex {} isn't found", k).into())
+            })
+            .collect::<Result<_, ErrBox>>()?;
+        let neighbours = edges
+            .iter()
+            .enumerate()
+            .map(|(p, qs)| {
+                qs.iter()
+                    .map(|q| {
+                        let (a, b) = (points[p], points[*q]);
+                        let chord = length(sub(a, b));
+                        // coincident vertices are kept apart by a tiny distance
+                        let d = (radius * 2.0 * (chord / 2.0).min(1.0).asin()).max(1e-6);
+                        (*q, d)
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(VertexGraph { neighbours })
+    }
+
+    /// Neighbour with the steepest descent and its slope, `None` in pits
+    fn steepest_descent(&self, h: &[Height], v: usize) -> Option<(usize, Height)> {
+        self.neighbours[v]
+            .iter()
+            .map(|(q, d)| (*q, (h[v] - h[*q]) / d))
+            .filter(|(_, slope)| *slope > 0.0)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+/// Moves material down the slopes steeper than the talus angle
+fn thermal_erosion(graph: &VertexGraph, h: &mut [Height], opts: &ErosionOpts) {
+    for _ in 0..opts.thermal_iterations {
+        let mut delta = vec![0.0; h.len()];
+        for (v, neighbours) in graph.neighbours.iter().enumerate() {
+            let excess: Vec<(usize, Height)> = neighbours
+                .iter()
+                .map(|(q, d)| (*q, h[v] - h[*q] - opts.talus * d))
+                .filter(|(_, e)| *e > 0.0)
+                .collect();
+            let total: Height = excess.iter().map(|(_, e)| e).sum();
+            if total <= 0.0 {
+                continue;
+            }
+            let max_excess = excess.iter().map(|(_, e)| *e).fold(0.0, Height::max);
+            let moved = THERMAL_RATE * max_excess / 2.0;
+            delta[v] -= moved;
+            for (q, e) in excess {
+                delta[q] += moved * e / total;
+            }
+        }
+        for (hv, dv) in h.iter_mut().zip(delta) {
+            *hv += dv;
+        }
+    }
+}
+
+/// Erodes the terrain by droplets flowing down the slopes
+fn hydraulic_erosion(graph: &VertexGraph, h: &mut [Height], sea_level: Height, opts: &ErosionOpts) {
+    let mut rng = SplitMix64(opts.seed);
+    for _ in 0..opts.droplets {
+        let mut v = rng.below(h.len());
+        let (mut water, mut speed, mut sediment) = (1.0, 1.0, 0.0);
+        for _ in 0..opts.droplet_steps {
+            if h[v] <= sea_level {
+                break;
+            }
+            let Some((next, slope)) = graph.steepest_descent(h, v) else {
+                break;
+            };
+            let drop = h[v] - h[next];
+            let capacity = opts.capacity * slope.max(MIN_SLOPE) * speed * water;
+            if sediment > capacity {
+                let deposited = (sediment - capacity) * opts.deposition;
+                sediment -= deposited;
+                h[v] += deposited;
+            } else {
+                // never dig below the next vertex
+                let eroded = ((capacity - sediment) * opts.erosion_rate).min(drop);
+                sediment += eroded;
+                h[v] -= eroded;
+            }
+            speed = (speed * speed + GRAVITY * slope).sqrt();
+            water *= 1.0 - opts.evaporation;
+            v = next;
+        }
+        if sediment > 0.0 {
+            settle(graph, h, v, sediment);
+        }
+    }
+}
+
+/// Deposits the rest of the sediment of a droplet around its last vertex
+///
+/// A pit is filled up to its lowest neighbour, the rest is spread evenly over
+/// the vertex and its neighbours, so that no spikes grow where droplets stop.
+fn settle(graph: &VertexGraph, h: &mut [Height], v: usize, sediment: Height) {
+    let lowest = graph.neighbours[v]
+        .iter()
+        .map(|(q, _)| h[*q])
+        .fold(Height::INFINITY, Height::min);
+    let fill = if lowest > h[v] {
+        sediment.min(lowest - h[v])
+    } else {
+        0.0
+    };
+    h[v] += fill;
+    let share = (sediment - fill) / (graph.neighbours[v].len() + 1) as Height;
+    if share > 0.0 {
+        h[v] += share;
+        for (q, _) in &graph.neighbours[v] {
+            h[*q] += share;
+        }
+    }
+}
+
+/// Erodes the elevations of the model vertices
+///
+/// Thermal erosion runs after hydraulic erosion, so that it settles the slopes
+/// of the carved valleys.
+pub fn erode_heights(
+    heights: &mut Heights,
+    vertices: &Vertices,
+    faces: &Faces,
+    texture_mapping: Option<&PointsMapping>,
+    sea_level: Height,
+    opts: &ErosionOpts,
+) -> Result<(), ErrBox> {
+    if faces.is_empty() {
+        return Err("Erosion needs a model mesh with faces".into());
+    }
+    let keys: Vec<GeoPointIndex> = heights.keys().cloned().collect();
+    let graph = VertexGraph::new(&keys, vertices, faces, texture_mapping, opts.radius)?;
+    let mut h: Vec<Height> = keys.iter().map(|k| heights[k]).collect();
+    if opts.droplets > 0 {
+        hydraulic_erosion(&graph, &mut h, sea_level, opts);
+    }
+    thermal_erosion(&graph, &mut h, opts);
+    for (k, hv) in keys.iter().zip(h) {
+        heights.insert(*k, hv);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts() -> ErosionOpts {
+        ErosionOpts {
+            seed: 7,
+            thermal_iterations: 0,
+            talus: 1.0,
+            droplets: 0,
+            droplet_steps: DEFAULT_DROPLET_STEPS,
+            capacity: DEFAULT_CAPACITY,
+            erosion_rate: DEFAULT_EROSION_RATE,
+            deposition: DEFAULT_DEPOSITION,
+            evaporation: DEFAULT_EVAPORATION,
+            radius: 1000.0,
+        }
+    }
+
+    fn mountain() -> (Vertices, Faces, PointsMapping, Heights) {
+        let TessellationData {
+            modeldata: ModelData(vertices, faces, pmap),
+            ..
+        } = create_icosphere(3);
+        // a cone around the north pole
+        let heights = vertices
+            .iter()
+            .map(|(k, gp)| (*k, (gp.lat - 30.0).max(0.0) * 10.0))
+            .collect();
+        (vertices, faces, pmap.unwrap(), heights)
+    }
+
+    fn volume(heights: &Heights) -> Height {
+        heights.values().sum()
+    }
+
+    #[test]
+    fn thermal_erosion_t0() {
+        let (vertices, faces, pmap, mut heights) = mountain();
+        let before = heights.clone();
+        let opts = ErosionOpts {
+            thermal_iterations: 50,
+            talus: 0.2,
+            ..opts()
+        };
+        erode_heights(&mut heights, &vertices, &faces, Some(&pmap), 0.0, &opts).unwrap();
+        // material is conserved and the peak is lowered
+        assert!((volume(&heights) - volume(&before)).abs() < 1e-6 * volume(&before));
+        let peak = |h: &Heights| h.values().cloned().fold(0.0, Height::max);
+        assert!(peak(&heights) < peak(&before));
+    }
+
+    #[test]
+    fn hydraulic_erosion_t0() {
+        let (vertices, faces, pmap, heights) = mountain();
+        let opts = ErosionOpts {
+            droplets: 200,
+            ..opts()
+        };
+        let run = |seed| {
+            let mut h = heights.clone();
+            let opts = ErosionOpts {
+                seed,
+                ..opts.clone()
+            };
+            erode_heights(&mut h, &vertices, &faces, Some(&pmap), 0.0, &opts).unwrap();
+            h
+        };
+        let eroded = run(7);
+        assert_ne!(eroded, heights);
+        assert_eq!(eroded, run(7));
+        assert_ne!(eroded, run(8));
+        // droplets only carry material
+        assert!((volume(&eroded) - volume(&heights)).abs() < 1e-6 * volume(&heights));
+    }
+
+    #[test]
+    fn hydraulic_erosion_t1() {
+        // droplets stopped mid-slope deposit their whole load
+        let (vertices, faces, pmap, heights) = mountain();
+        let mut eroded = heights.clone();
+        let opts = ErosionOpts {
+            droplets: 500,
+            droplet_steps: 2,
+            ..opts()
+        };
+        erode_heights(&mut eroded, &vertices, &faces, Some(&pmap), 0.0, &opts).unwrap();
+        assert!((volume(&eroded) - volume(&heights)).abs() < 1e-6 * volume(&heights));
+        let peak = |h: &Heights| h.values().cloned().fold(0.0, Height::max);
+        assert!(peak(&eroded) <= peak(&heights));
+    }
+
+    #[test]
+    fn erode_heights_t0() {
+        let (vertices, _, _, mut heights) = mountain();
+        let opts = ErosionOpts {
+            droplets: 1,
+            ..opts()
+        };
+        assert!(erode_heights(&mut heights, &vertices, &vec![], None, 0.0, &opts).is_err());
+    }
+}
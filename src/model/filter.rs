@@ -0,0 +1,315 @@
+//! # Height Filters
+//!
+//! This module filters the sampled elevations before the model is built, so that
+//! single-cell spikes and DEM artefacts don't become spikes of printed models.
+//!
+//! Kernels are computed on the sphere: the neighbourhood of a vertex is a
+//! spherical cap of the filter radius around it, found with a hash grid of unit
+//! vectors. So kernels cover the same area at all latitudes, and no special
+//! handling of the poles and the antimeridian is needed. Vertices of meshes
+//! coarser than the filter radius have no neighbours and are kept unchanged.
+//!
+//! ## Filters
+//!
+//! - **Gaussian**: weighted mean with the standard deviation of half the radius
+//! - **Median**: median of elevations within the radius, removes spikes
+//! - **Bilateral**: Gaussian weights multiplied by weights of elevation differences,
+//!   smooths noise while keeping cliffs and coastlines
+//!
+//! ## Configuration Parameters
+//!
+//! - `height_filters`: comma separated chain of filters applied in order, e.g.
+//!   "median 2, bilateral 5 150, gaussian 3". Radii are in km, the second parameter
+//!   of the bilateral filter is the elevation difference in metres (default: 100.0)
+//! - `radius`: planet radius in metres (default: 6378000.0)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::surface::DEFAULT_RADIUS;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::collections::HashMap;
+use std::thread;
+
+const DEFAULT_BILATERAL_RANGE: f64 = 100.0;
+
+/// Filter of elevations
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeightFilter {
+    /// Gaussian filter with the radius in radians
+    Gaussian(Coord),
+    /// Median filter with the radius in radians
+    Median(Coord),
+    /// Bilateral filter with the radius in radians and the elevation difference in metres
+    Bilateral(Coord, Height),
+}
+
+/// Chain of filters applied in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterOpts {
+    /// Filters
+    pub filters: Vec<HeightFilter>,
+}
+
+impl FilterOpts {
+    /// Reads the chain of filters from the settings
+    ///
+    /// Returns `None` if no filters are given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let chain = settings.get_parameter_str("height_filters", "")?;
+        if chain.trim().is_empty() {
+            return Ok(None);
+        }
+        let radius = settings.get_parameter_num("radius", DEFAULT_RADIUS)?;
+        let filters = chain
+            .split(',')
+            .map(|spec| parse_filter(spec, radius))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Invalid 'height_filters' parameter: {}", err))?;
+        Ok(Some(FilterOpts { filters }))
+    }
+}
+
+/// Parses a filter as "name radius_km [parameter]"
+fn parse_filter(spec: &str, radius: Coord) -> Result<HeightFilter, String> {
+    let words: Vec<&str> = spec.split_whitespace().collect();
+    let values = words
+        .iter()
+        .skip(1)
+        .map(|s| s.parse::<Coord>())
+        .collect::<Result<Vec<Coord>, _>>()
+        .map_err(|err| format!("{} in '{}'", err, spec.trim()))?;
+    let angle = |km: Coord| -> Result<Coord, String> {
+        if km > 0.0 {
+            Ok(1000.0 * km / radius)
+        } else {
+            Err(format!("radius must be positive in '{}'", spec.trim()))
+        }
+    };
+    match (words.first(), &values[..]) {
+        (Some(&"gaussian"), [km]) => Ok(HeightFilter::Gaussian(angle(*km)?)),
+        (Some(&"median"), [km]) => Ok(HeightFilter::Median(angle(*km)?)),
+        (Some(&"bilateral"), [km]) => Ok(HeightFilter::Bilateral(
+            angle(*km)?,
+            DEFAULT_BILATERAL_RANGE,
+        )),
+        (Some(&"bilateral"), [km, range]) if *range > 0.0 => {
+            Ok(HeightFilter::Bilateral(angle(*km)?, *range))
+        }
+        _ => Err(format!("unknown filter '{}'", spec.trim())),
+    }
+}
+
+impl HeightFilter {
+    /// Radius in radians
+    fn radius(&self) -> Coord {
+        match *self {
+            HeightFilter::Gaussian(r) | HeightFilter::Median(r) | HeightFilter::Bilateral(r, _) => {
+                r
+            }
+        }
+    }
+
+    /// Filtered elevation of a vertex from its neighbours
+    ///
+    /// `neighbours` are (angular distance, elevation) pairs, including the vertex itself.
+    fn apply(&self, h: Height, neighbours: &mut [(Coord, Height)]) -> Height {
+        let gaussian = |d: Coord| {
+            let sigma = self.radius() / 2.0;
+            (-d * d / (2.0 * sigma * sigma)).exp()
+        };
+        match *self {
+            HeightFilter::Gaussian(_) => {
+                let (sum, weight) = neighbours.iter().fold((0.0, 0.0), |(s, w), (d, hn)| {
+                    let k = gaussian(*d);
+                    (s + k * hn, w + k)
+                });
+                sum / weight
+            }
+            HeightFilter::Median(_) => {
+                neighbours.sort_by(|a, b| a.1.total_cmp(&b.1));
+                let n = neighbours.len();
+                if n % 2 == 1 {
+                    neighbours[n / 2].1
+                } else {
+                    (neighbours[n / 2 - 1].1 + neighbours[n / 2].1) / 2.0
+                }
+            }
+            HeightFilter::Bilateral(_, range) => {
+                let (sum, weight) = neighbours.iter().fold((0.0, 0.0), |(s, w), (d, hn)| {
+                    let k = gaussian(*d) * (-(hn - h).powi(2) / (2.0 * range * range)).exp();
+                    (s + k * hn, w + k)
+                });
+                sum / weight
+            }
+        }
+    }
+}
+
+/// Hash grid of unit vectors
+struct PointGrid {
+    cell: Coord,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl PointGrid {
+    fn new(points: &[Point3d], cell: Coord) -> Self {
+        let mut cells: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        for (i, p) in points.iter().enumerate() {
+            cells.entry(Self::key(*p, cell)).or_default().push(i);
+        }
+        PointGrid { cell, cells }
+    }
+
+    fn key(p: Point3d, cell: Coord) -> (i64, i64, i64) {
+        (
+            (p.0 / cell).floor() as i64,
+            (p.1 / cell).floor() as i64,
+            (p.2 / cell).floor() as i64,
+        )
+    }
+
+    /// Points within the chord distance of the cell size, with their angular distances
+    fn neighbours(&self, points: &[Point3d], p: Point3d, out: &mut Vec<(usize, Coord)>) {
+        out.clear();
+        let (x, y, z) = Self::key(p, self.cell);
+        for key in (x - 1..=x + 1).flat_map(|i| {
+            (y - 1..=y + 1).flat_map(move |j| (z - 1..=z + 1).map(move |k| (i, j, k)))
+        }) {
+            if let Some(cell) = self.cells.get(&key) {
+                for &n in cell {
+                    let q = points[n];
+                    let chord = length(sub(p, q));
+                    if chord <= self.cell {
+                        out.push((n, 2.0 * (chord / 2.0).min(1.0).asin()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Filters the elevations of the model vertices by the chain of filters
+///
+/// Work is split between `jobs` threads.
+pub fn filter_heights(heights: &mut Heights, vertices: &Vertices, opts: &FilterOpts, jobs: usize) {
+    let keys: Vec<GeoPointIndex> = heights.keys().cloned().collect();
+    let points: Vec<Point3d> = keys
+        .iter()
+        .map(|k| match vertices.get(k) {
+            Some(gp) => geo_to_point3d(gp.lon, gp.lat),
+            None => (Coord::NAN, Coord::NAN, Coord::NAN),
+        })
+        .collect();
+    let mut values: Vec<Height> = keys.iter().map(|k| heights[k]).collect();
+
+    for filter in &opts.filters {
+        let cell = 2.0
+            * (filter.radius() / 2.0)
+                .min(std::f64::consts::FRAC_PI_2)
+                .sin();
+        let grid = PointGrid::new(&points, cell);
+        let chunk = values.len().div_ceil(jobs.max(1)).max(1);
+        let source = &values;
+        let filtered: Vec<Height> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..values.len())
+                .step_by(chunk)
+                .map(|start| {
+                    let grid = &grid;
+                    let points = &points;
+                    scope.spawn(move || {
+                        let mut found = vec![];
+                        let mut neighbours = vec![];
+                        (start..(start + chunk).min(source.len()))
+                            .map(|i| {
+                                if points[i].0.is_nan() {
+                                    return source[i];
+                                }
+                                grid.neighbours(points, points[i], &mut found);
+                                neighbours.clear();
+                                neighbours.extend(found.iter().map(|(n, d)| (*d, source[*n])));
+                                filter.apply(source[i], &mut neighbours)
+                            })
+                            .collect::<Vec<Height>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        values = filtered;
+    }
+
+    for (k, h) in keys.iter().zip(values) {
+        heights.insert(*k, h);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn icosphere_heights() -> (Vertices, Heights) {
+        let TessellationData {
+            modeldata: ModelData(vertices, _, _),
+            ..
+        } = create_icosphere(4);
+        let heights = vertices.keys().map(|k| (*k, 0.0)).collect();
+        (vertices, heights)
+    }
+
+    #[test]
+    fn parse_filter_t0() {
+        assert_eq!(
+            parse_filter(" median 6.378 ", 6378000.0),
+            Ok(HeightFilter::Median(0.001))
+        );
+        assert_eq!(
+            parse_filter("bilateral 6378 50", 6378000.0),
+            Ok(HeightFilter::Bilateral(1.0, 50.0))
+        );
+        assert!(parse_filter("gaussian", 6378000.0).is_err());
+        assert!(parse_filter("gaussian -1", 6378000.0).is_err());
+        assert!(parse_filter("box 5", 6378000.0).is_err());
+    }
+
+    #[test]
+    fn filter_heights_t0() {
+        // a single spike is removed by the median filter and spread by the gaussian one
+        let (vertices, mut heights) = icosphere_heights();
+        heights.insert(5, 1000.0);
+        let mut median = heights.clone();
+        let opts = |filter| FilterOpts {
+            filters: vec![filter],
+        };
+        filter_heights(&mut median, &vertices, &opts(HeightFilter::Median(0.2)), 3);
+        assert!(median.values().all(|h| *h == 0.0));
+        filter_heights(
+            &mut heights,
+            &vertices,
+            &opts(HeightFilter::Gaussian(0.2)),
+            2,
+        );
+        assert!(heights[&5] > 0.0 && heights[&5] < 1000.0);
+        assert!(heights.values().filter(|h| **h > 0.0).count() > 1);
+    }
+
+    #[test]
+    fn filter_heights_t1() {
+        // the bilateral filter keeps a cliff between two hemispheres
+        let (vertices, mut heights) = icosphere_heights();
+        for (k, gp) in vertices.iter() {
+            heights.insert(*k, if gp.lon > 0.0 { 2000.0 } else { 0.0 });
+        }
+        let expected = heights.clone();
+        let opts = FilterOpts {
+            filters: vec![HeightFilter::Bilateral(0.2, 100.0)],
+        };
+        filter_heights(&mut heights, &vertices, &opts, 4);
+        for (k, h) in heights.iter() {
+            assert!((h - expected[k]).abs() < 1.0);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! # Output Coordinate Frame
+//!
+//! This module defines the coordinate frame of written vertices. By default
+//! models are written around a unit sphere (see `calc_point3d`). GIS and
+//! simulation tools need vertices in metres in the Earth-centred, Earth-fixed
+//! (ECEF) frame, or relative to a local origin, because single precision floats
+//! of far away vertices lose centimetres.
+//!
+//! Solids are built, hollowed and split in the model frame (centred at the
+//! planet centre, Z to the north pole). The origin offset and the axis
+//! convention are applied only when vertices are written.
+//!
+//! ## Configuration Parameters
+//!
+//! - `coordinates`: "normalised" (default), "ecef" or "ecef_local"
+//! - `ecef_origin`: origin of "ecef_local" coordinates as "x y z" in metres, or
+//!   "auto" (default) for the centre of the bounding box of the model
+//! - `ellipsoid`: "sphere" (default) of the `radius` or "wgs84"
+//! - `axes`: up axis of output coordinates, "z_up" (default) or "y_up"
+//! - `handedness`: "right" (default) or "left"
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::common::util::*;
+use crate::model::tessellation::Point3d;
+use std::f64::consts::PI;
+
+/// Semi-major axis of the WGS84 ellipsoid in metres
+const WGS84_A: f64 = 6378137.0;
+/// Flattening of the WGS84 ellipsoid
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+/// Coordinates of written vertices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coordinates {
+    /// Around a unit sphere, in the orientation of `calc_point3d`
+    Normalised,
+    /// Earth-centred, Earth-fixed in metres
+    Ecef,
+    /// ECEF minus the origin, `None` for the automatic origin
+    EcefLocal(Option<Point3d>),
+}
+
+/// Reference surface of ECEF coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ellipsoid {
+    /// Sphere of the planet radius
+    Sphere,
+    /// WGS84 ellipsoid
+    Wgs84,
+}
+
+/// Up axis of output coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+/// Handedness of output coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+/// Coordinate frame of the output
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameOpts {
+    /// Coordinates of written vertices
+    pub coordinates: Coordinates,
+    /// Reference surface of ECEF coordinates
+    pub ellipsoid: Ellipsoid,
+    /// Up axis
+    pub up: UpAxis,
+    /// Handedness
+    pub handedness: Handedness,
+}
+
+impl Default for FrameOpts {
+    fn default() -> Self {
+        FrameOpts {
+            coordinates: Coordinates::Normalised,
+            ellipsoid: Ellipsoid::Sphere,
+            up: UpAxis::Z,
+            handedness: Handedness::Right,
+        }
+    }
+}
+
+impl FrameOpts {
+    /// Reads the coordinate frame from the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        let coordinates = match settings
+            .get_parameter_str("coordinates", "normalised")?
+            .as_str()
+        {
+            "normalised" => Coordinates::Normalised,
+            "ecef" => Coordinates::Ecef,
+            "ecef_local" => {
+                let origin = settings.get_parameter_str("ecef_origin", "auto")?;
+                if origin == "auto" {
+                    Coordinates::EcefLocal(None)
+                } else {
+                    let values = origin
+                        .split_whitespace()
+                        .map(|s| s.parse::<Coord>())
+                        .collect::<Result<Vec<Coord>, _>>()
+                        .map_err(|err| format!("Invalid 'ecef_origin' parameter: {}", err))?;
+                    let [x, y, z] = values[..] else {
+                        return Err("'ecef_origin' parameter must be 'x y z' or 'auto'".into());
+                    };
+                    Coordinates::EcefLocal(Some((x, y, z)))
+                }
+            }
+            s => return Err(format!("Unknown coordinates '{}'", s).into()),
+        };
+        let ellipsoid = match settings.get_parameter_str("ellipsoid", "sphere")?.as_str() {
+            "sphere" => Ellipsoid::Sphere,
+            "wgs84" => Ellipsoid::Wgs84,
+            s => return Err(format!("Unknown ellipsoid '{}'", s).into()),
+        };
+        let up = match settings.get_parameter_str("axes", "z_up")?.as_str() {
+            "z_up" => UpAxis::Z,
+            "y_up" => UpAxis::Y,
+            s => return Err(format!("Unknown axes '{}'", s).into()),
+        };
+        let handedness = match settings.get_parameter_str("handedness", "right")?.as_str() {
+            "right" => Handedness::Right,
+            "left" => Handedness::Left,
+            s => return Err(format!("Unknown handedness '{}'", s).into()),
+        };
+        Ok(FrameOpts {
+            coordinates,
+            ellipsoid,
+            up,
+            handedness,
+        })
+    }
+
+    /// Returns true if vertices are in metres
+    pub fn is_ecef(&self) -> bool {
+        self.coordinates != Coordinates::Normalised
+    }
+
+    /// Calculates the position of a model vertex in the model frame
+    ///
+    /// `scale` multiplies elevations in all coordinates.
+    pub fn position(
+        &self,
+        radius: Height,
+        scale: Height,
+        height: Height,
+        lon: Coord,
+        lat: Coord,
+    ) -> Point3d {
+        if !self.is_ecef() {
+            return calc_point3d(radius, scale, height, lon, lat);
+        }
+        let h = scale * height;
+        let phi = lon * PI / 180.0;
+        let theta = lat * PI / 180.0;
+        match self.ellipsoid {
+            Ellipsoid::Sphere => {
+                let r = radius + h;
+                (
+                    r * phi.cos() * theta.cos(),
+                    r * phi.sin() * theta.cos(),
+                    r * theta.sin(),
+                )
+            }
+            Ellipsoid::Wgs84 => {
+                let e2 = WGS84_F * (2.0 - WGS84_F);
+                let n = WGS84_A / (1.0 - e2 * theta.sin().powi(2)).sqrt();
+                (
+                    (n + h) * phi.cos() * theta.cos(),
+                    (n + h) * phi.sin() * theta.cos(),
+                    (n * (1.0 - e2) + h) * theta.sin(),
+                )
+            }
+        }
+    }
+
+    /// Origin of output coordinates in the model frame
+    pub fn origin(&self, positions: &[Point3d]) -> Point3d {
+        match self.coordinates {
+            Coordinates::EcefLocal(Some(origin)) => origin,
+            Coordinates::EcefLocal(None) if !positions.is_empty() => {
+                let mut min = positions[0];
+                let mut max = positions[0];
+                for &(x, y, z) in positions {
+                    min = (min.0.min(x), min.1.min(y), min.2.min(z));
+                    max = (max.0.max(x), max.1.max(y), max.2.max(z));
+                }
+                (
+                    (min.0 + max.0) / 2.0,
+                    (min.1 + max.1) / 2.0,
+                    (min.2 + max.2) / 2.0,
+                )
+            }
+            _ => (0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Transforms a position of the model frame to output coordinates
+    ///
+    /// Faces keep their order, so in a left-handed frame they are clockwise
+    /// when seen from outside, as left-handed engines expect.
+    pub fn transform(&self, origin: Point3d, p: Point3d) -> Point3d {
+        let (x, y, z) = (p.0 - origin.0, p.1 - origin.1, p.2 - origin.2);
+        match (self.up, self.handedness) {
+            (UpAxis::Z, Handedness::Right) => (x, y, z),
+            (UpAxis::Z, Handedness::Left) => (x, -y, z),
+            (UpAxis::Y, Handedness::Right) => (x, z, -y),
+            (UpAxis::Y, Handedness::Left) => (x, z, y),
+        }
+    }
+
+    /// Makes the content of the sidecar file describing the local origin
+    pub fn origin_sidecar(&self, origin: Point3d) -> String {
+        format!(
+            "# Origin of local coordinates in ECEF metres\n\
+             origin: [{:.3}, {:.3}, {:.3}]\n\
+             ellipsoid: {}\n\
+             axes: {}\n\
+             handedness: {}\n",
+            origin.0,
+            origin.1,
+            origin.2,
+            match self.ellipsoid {
+                Ellipsoid::Sphere => "sphere",
+                Ellipsoid::Wgs84 => "wgs84",
+            },
+            match self.up {
+                UpAxis::Y => "y_up",
+                UpAxis::Z => "z_up",
+            },
+            match self.handedness {
+                Handedness::Right => "right",
+                Handedness::Left => "left",
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ecef(ellipsoid: Ellipsoid) -> FrameOpts {
+        FrameOpts {
+            coordinates: Coordinates::Ecef,
+            ellipsoid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn position_t0() {
+        let frame = ecef(Ellipsoid::Wgs84);
+        let p = frame.position(6378000.0, 1.0, 0.0, 0.0, 0.0);
+        assert!((p.0 - WGS84_A).abs() < 1e-6 && p.1.abs() < 1e-6 && p.2.abs() < 1e-6);
+        let p = frame.position(6378000.0, 1.0, 100.0, 90.0, 90.0);
+        // semi-minor axis of WGS84 plus the elevation
+        assert!((p.2 - 6356852.314).abs() < 1e-3);
+        let p = ecef(Ellipsoid::Sphere).position(6378000.0, 2.0, 500.0, 90.0, 0.0);
+        assert!(p.0.abs() < 1e-6 && (p.1 - 6379000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn origin_t0() {
+        let positions = [(1.0, 2.0, 3.0), (3.0, 6.0, -1.0)];
+        let mut frame = FrameOpts {
+            coordinates: Coordinates::EcefLocal(None),
+            ..Default::default()
+        };
+        assert_eq!(frame.origin(&positions), (2.0, 4.0, 1.0));
+        frame.coordinates = Coordinates::EcefLocal(Some((5.0, 5.0, 5.0)));
+        assert_eq!(frame.origin(&positions), (5.0, 5.0, 5.0));
+        assert_eq!(ecef(Ellipsoid::Sphere).origin(&positions), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn transform_t0() {
+        let mut frame = FrameOpts::default();
+        let p = (1.0, 2.0, 3.0);
+        assert_eq!(frame.transform((0.0, 0.0, 1.0), p), (1.0, 2.0, 2.0));
+        frame.up = UpAxis::Y;
+        assert_eq!(frame.transform((0.0, 0.0, 0.0), p), (1.0, 3.0, -2.0));
+        frame.handedness = Handedness::Left;
+        assert_eq!(frame.transform((0.0, 0.0, 0.0), p), (1.0, 3.0, 2.0));
+    }
+}
@@ -0,0 +1,434 @@
+//! # glTF Model Generation Module
+//!
+//! This module writes models as binary glTF 2.0 (GLB) files, which engines and
+//! web viewers load directly.
+//!
+//! ## Features
+//!
+//! - **Indexed Geometry**: positions, normals and texture coordinates with a single
+//!   index per vertex (see `mesh`), triangles with 32-bit indices
+//! - **Vertex Colors**: color models have true per-vertex `COLOR_0` colors instead of
+//!   quantized materials
+//! - **Textures**: texture models reference the image by `texture_uri` or embed it
+//!   into the binary chunk. Submeshes (cube-sphere faces) are separate primitives
+//!   with their own textures, named as in OBJ materials
+//! - **Metadata**: the planet name, radius, elevation scale, model size and data
+//!   source are written to `extras` of the planet node
+//!
+//! glTF is Y-up, so `axes: "y_up"` should be set for engines. It's right-handed,
+//! so triangles are reversed in left-handed frames to keep counterclockwise front
+//! faces. Vertices are placed by the same parameters as OBJ vertices (see `surface`).
+//!
+//! ## Configuration Parameters
+//!
+//! - `texture_uri`: texture image of texture models (default: "texture.png")
+//! - `texture_mode`: "reference" (default) to reference the image by its URI,
+//!   or "embed" to embed it (PNG or JPEG) into the GLB file
+//! - `normals`, `crease_angle`: see `normals`
+use crate::common::color::*;
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::common::util::*;
+use crate::model::frame::*;
+use crate::model::mesh::*;
+use crate::model::normals::*;
+use crate::model::obj::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_TEXTURE_URI: &str = "texture.png";
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F534A;
+const CHUNK_BIN: u32 = 0x004E4942;
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// How the texture image is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureMode {
+    /// Referenced by its URI
+    Reference,
+    /// Embedded into the binary chunk
+    Embed,
+}
+
+impl TextureMode {
+    /// Reads the texture mode from the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings
+            .get_parameter_str("texture_mode", "reference")?
+            .as_str()
+        {
+            "reference" => Ok(TextureMode::Reference),
+            "embed" => Ok(TextureMode::Embed),
+            s => Err(format!("Unknown texture mode '{}'", s).into()),
+        }
+    }
+}
+
+/// glTF model structure
+///
+/// This struct represents a 3D geospatial model in binary glTF format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct Gltf<'a> {
+    model_type: ModelType,
+    model_size: GeoPointIndex,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    texture_uri: String,
+    texture_mode: TextureMode,
+    normal_opts: Option<NormalOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for Gltf<'a> {
+    /// Checks that the embedded texture exists
+    fn options_check(settings: &'a Settings) -> Result<(), ErrBox> {
+        if let TextureMode::Embed = TextureMode::from_settings(settings)? {
+            let str = settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?;
+            check_file(Path::new(&str))?;
+        }
+        Ok(())
+    }
+
+    /// Builds and constructs a glTF model instance
+    fn build_model(
+        model_type: ModelType,
+        model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        Ok(Gltf {
+            model_type,
+            model_size,
+            settings,
+            surface: Surface::from_settings(settings, &components)?,
+            texture_uri: settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?,
+            texture_mode: TextureMode::from_settings(settings)?,
+            normal_opts: NormalOpts::from_settings(settings)?,
+            components,
+        })
+    }
+
+    /// Saves the model to the GLB file
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        let normals = self.normal_opts.as_ref().map(|opts| solid.normals(opts));
+        let mut mesh = IndexedMesh::from_solid(
+            &solid,
+            normals.as_ref(),
+            match self.model_type {
+                ModelType::Texture => None,
+                ModelType::Color => Some(self.components.get_colors()?),
+            },
+            self.components.submeshes.as_ref(),
+            &self.surface.frame,
+            origin,
+        )?;
+        // glTF is right-handed with counterclockwise front faces
+        if self.surface.frame.handedness == Handedness::Left {
+            mesh.reverse_winding();
+        }
+
+        let (json, bin) = self.make_gltf(&mesh)?;
+        let result_path = Path::new(&output_path)
+            .join(planet_name)
+            .with_extension("glb");
+        fs::write(&result_path, make_glb(&json, &bin))
+            .map_err(|err| format!("Can't write glb file {:?}: {}", result_path, err).into())
+    }
+}
+
+/// Binary chunk with its buffer views and accessors
+#[derive(Default)]
+struct BinaryChunk {
+    data: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl BinaryChunk {
+    /// Appends a buffer view aligned to 4 bytes, returns its index
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.data.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    /// Appends an accessor of float vectors, returns its index
+    fn push_floats(&mut self, values: &[Vec<f32>], kind: &str, bounds: bool) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let view = self.push_view(&bytes, Some(TARGET_ARRAY_BUFFER));
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": COMPONENT_FLOAT,
+            "count": values.len(),
+            "type": kind,
+        });
+        if bounds && !values.is_empty() {
+            let n = values[0].len();
+            let fold = |f: fn(f32, f32) -> f32| -> Vec<f32> {
+                (0..n)
+                    .map(|i| values.iter().map(|v| v[i]).reduce(f).unwrap())
+                    .collect()
+            };
+            accessor["min"] = json!(fold(f32::min));
+            accessor["max"] = json!(fold(f32::max));
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// Appends an accessor of triangle indices, returns its index
+    fn push_indices(&mut self, triangles: &[[u32; 3]]) -> usize {
+        let bytes: Vec<u8> = triangles
+            .iter()
+            .flatten()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        let view = self.push_view(&bytes, Some(TARGET_ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": COMPONENT_UNSIGNED_INT,
+            "count": 3 * triangles.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+impl<'a> Gltf<'a> {
+    /// Makes the glTF document and its binary chunk
+    fn make_gltf(&self, mesh: &IndexedMesh) -> Result<(Value, Vec<u8>), ErrBox> {
+        let mut chunk = BinaryChunk::default();
+        let vec3 = |p: &Point3d| vec![p.0 as f32, p.1 as f32, p.2 as f32];
+
+        let mut attributes = json!({
+            "POSITION": chunk.push_floats(
+                &mesh.positions.iter().map(vec3).collect::<Vec<_>>(),
+                "VEC3",
+                true,
+            ),
+        });
+        if !mesh.normals.is_empty() {
+            attributes["NORMAL"] = json!(chunk.push_floats(
+                &mesh.normals.iter().map(vec3).collect::<Vec<_>>(),
+                "VEC3",
+                false,
+            ));
+        }
+        if !mesh.texture_coordinates.is_empty() {
+            // glTF images start at the top left corner
+            attributes["TEXCOORD_0"] = json!(
+                chunk.push_floats(
+                    &mesh
+                        .texture_coordinates
+                        .iter()
+                        .map(|(u, v)| vec![*u as f32, (1.0 - v) as f32])
+                        .collect::<Vec<_>>(),
+                    "VEC2",
+                    false,
+                )
+            );
+        }
+        if !mesh.colors.is_empty() {
+            attributes["COLOR_0"] = json!(
+                chunk.push_floats(
+                    &mesh
+                        .colors
+                        .iter()
+                        .map(|RGB(r, g, b)| vec![*r, *g, *b])
+                        .collect::<Vec<_>>(),
+                    "VEC3",
+                    false,
+                )
+            );
+        }
+
+        let mut materials = vec![];
+        let mut textures = vec![];
+        let mut images = vec![];
+        let mut primitives = vec![];
+        for (name, triangles) in &mesh.primitives {
+            let indices = chunk.push_indices(triangles);
+            let mut material = json!({
+                "name": match name {
+                    Some(name) => format!("Material_{}", name),
+                    None => "Material".to_string(),
+                },
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 1.0, 1.0, 1.0],
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0,
+                },
+            });
+            if let ModelType::Texture = self.model_type {
+                let uri = match name {
+                    Some(name) => make_tile_uri(&self.texture_uri, name),
+                    None => self.texture_uri.clone(),
+                };
+                images.push(self.make_image(&uri, &mut chunk)?);
+                textures.push(json!({"sampler": 0, "source": images.len() - 1}));
+                material["pbrMetallicRoughness"]["baseColorTexture"] =
+                    json!({"index": textures.len() - 1});
+            }
+            materials.push(material);
+            primitives.push(json!({
+                "attributes": attributes,
+                "indices": indices,
+                "material": materials.len() - 1,
+                "mode": 4,
+            }));
+        }
+
+        let settings = self.settings;
+        let mut gltf = json!({
+            "asset": {"version": "2.0", "generator": "plmat"},
+            "scene": 0,
+            "scenes": [{"name": settings.planet_name, "nodes": [0]}],
+            "nodes": [{
+                "name": settings.planet_name,
+                "mesh": 0,
+                "extras": {
+                    "planet_name": settings.planet_name,
+                    "radius": self.surface.radius,
+                    "scale": self.surface.scale,
+                    "model_size": self.model_size,
+                    "data_source": format!("{:?}", settings.data_source),
+                },
+            }],
+            "meshes": [{"name": "Planet", "primitives": primitives}],
+            "materials": materials,
+            "buffers": [{"byteLength": chunk.data.len()}],
+            "bufferViews": chunk.buffer_views,
+            "accessors": chunk.accessors,
+        });
+        if !images.is_empty() {
+            gltf["images"] = json!(images);
+            gltf["textures"] = json!(textures);
+            gltf["samplers"] = json!([{"wrapS": 10497, "wrapT": 33071}]);
+        }
+        Ok((gltf, chunk.data))
+    }
+
+    /// Makes the image referencing the texture or embedding it into the chunk
+    fn make_image(&self, uri: &str, chunk: &mut BinaryChunk) -> Result<Value, ErrBox> {
+        match self.texture_mode {
+            TextureMode::Reference => Ok(json!({ "uri": uri })),
+            TextureMode::Embed => {
+                let lower = uri.to_lowercase();
+                let mime_type = if lower.ends_with(".png") {
+                    "image/png"
+                } else if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
+                    "image/jpeg"
+                } else {
+                    return Err(format!("Texture '{}' must be a PNG or JPEG image", uri).into());
+                };
+                let bytes = fs::read(uri)
+                    .map_err(|err| format!("Can't read texture file '{}': {}", uri, err))?;
+                let view = chunk.push_view(&bytes, None);
+                Ok(json!({"bufferView": view, "mimeType": mime_type}))
+            }
+        }
+    }
+}
+
+/// Makes the GLB container of the glTF document and its binary chunk
+fn make_glb(json: &Value, bin: &[u8]) -> Vec<u8> {
+    let mut json_bytes = json.to_string().into_bytes();
+    while !json_bytes.len().is_multiple_of(4) {
+        json_bytes.push(b' ');
+    }
+    let mut bin_bytes = bin.to_vec();
+    while !bin_bytes.len().is_multiple_of(4) {
+        bin_bytes.push(0);
+    }
+    let length = 12
+        + 8
+        + json_bytes.len()
+        + if bin.is_empty() {
+            0
+        } else {
+            8 + bin_bytes.len()
+        };
+    let mut glb = Vec::with_capacity(length);
+    glb.extend_from_slice(&GLB_MAGIC.to_le_bytes());
+    glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+    glb.extend_from_slice(&(length as u32).to_le_bytes());
+    glb.extend_from_slice(&(json_bytes.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+    glb.extend_from_slice(&json_bytes);
+    if !bin.is_empty() {
+        glb.extend_from_slice(&(bin_bytes.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin_bytes);
+    }
+    glb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_glb_t0() {
+        let glb = make_glb(&json!({"asset": {"version": "2.0"}}), &[1, 2, 3, 4, 5]);
+        let word = |i: usize| u32::from_le_bytes(glb[i..i + 4].try_into().unwrap());
+        assert_eq!(word(0), GLB_MAGIC);
+        assert_eq!(word(8) as usize, glb.len());
+        let json_length = word(12) as usize;
+        assert_eq!(json_length % 4, 0);
+        assert_eq!(word(16), CHUNK_JSON);
+        let json: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(json["asset"]["version"], "2.0");
+        assert_eq!(word(20 + json_length), 8);
+        assert_eq!(word(24 + json_length), CHUNK_BIN);
+        assert_eq!(&glb[28 + json_length..], &[1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn binary_chunk_t0() {
+        let mut chunk = BinaryChunk::default();
+        chunk.push_view(&[1, 2], None);
+        let accessor = chunk.push_floats(&[vec![1.0, -2.0], vec![-1.0, 3.0]], "VEC2", true);
+        assert_eq!(chunk.buffer_views[1]["byteOffset"], 4);
+        assert_eq!(chunk.accessors[accessor]["min"], json!([-1.0, -2.0]));
+        assert_eq!(chunk.accessors[accessor]["max"], json!([1.0, 3.0]));
+        let indices = chunk.push_indices(&[[0, 1, 2]]);
+        assert_eq!(chunk.accessors[indices]["count"], 3);
+        assert_eq!(chunk.data.len(), 4 + 16 + 12);
+    }
+}
@@ -0,0 +1,548 @@
+//! # Masks
+//!
+//! This module restricts models to a region given by polygons, so that a single
+//! continent or country can be printed alone or on a flat ocean.
+//!
+//! Polygons are read from a GeoJSON file (FeatureCollection or Feature with
+//! Polygon or MultiPolygon geometries, holes included). Vertices inside of any
+//! polygon are kept as they are, vertices outside of all polygons are masked:
+//!
+//! - **Crop**: vertices outside are dropped. Faces crossing the boundary are cut
+//!   along it: new vertices are placed where their edges cross the boundary, and
+//!   the inner parts of the faces are kept. The mesh is cut before sampling, so
+//!   new vertices get elevations from the DEM
+//! - **Flatten**: elevations outside are set to the base level
+//! - **Recolor**: colors outside are set to the mask color (color models only)
+//!
+//! Elevation grids can't have holes, so they are flattened instead of cropped.
+//!
+//! Insides are tested in longitude and latitude. Rings may cross the antimeridian,
+//! their longitudes are unwrapped, but polygons must not contain poles.
+//!
+//! ## Configuration Parameters
+//!
+//! - `mask_file`: GeoJSON file with polygons
+//! - `mask_mode`: "crop" (default), "flatten" or "recolor"
+//! - `mask_level`: base level of flattened vertices in metres (default: sea level)
+//! - `mask_color`: color of recolored vertices as "r g b" in [0, 1]
+//!   (default: the color of sea level)
+use crate::common::color::*;
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::edits::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+const CROSSING_ITERATIONS: usize = 30;
+
+/// Treatment of vertices outside of the mask
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaskMode {
+    /// Drops vertices and cuts faces along the boundary
+    Crop,
+    /// Sets elevations to the base level (sea level if `None`)
+    Flatten(Option<Height>),
+    /// Sets colors to the color (color of sea level if `None`)
+    Recolor(Option<RGB>),
+}
+
+/// Polygon with its bounding box
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskPolygon {
+    /// Rings of (lon, lat) points, holes included
+    pub rings: Vec<Vec<GeoPoint>>,
+    /// Bounding box (lon_min, lat_min, lon_max, lat_max) in degrees
+    pub bbox: (Coord, Coord, Coord, Coord),
+}
+
+/// Mask options
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskOpts {
+    /// Polygons of the kept region
+    pub polygons: Vec<MaskPolygon>,
+    /// Treatment of vertices outside
+    pub mode: MaskMode,
+}
+
+impl MaskOpts {
+    /// Reads mask options from the settings
+    ///
+    /// Returns `None` if no mask file is given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let path = settings.get_parameter_str("mask_file", "")?;
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let mode = match settings.get_parameter_str("mask_mode", "crop")?.as_str() {
+            "crop" => MaskMode::Crop,
+            "flatten" => {
+                let level = settings.get_parameter_num("mask_level", Height::NAN)?;
+                MaskMode::Flatten(if level.is_nan() { None } else { Some(level) })
+            }
+            "recolor" => match settings.get_parameter_str("mask_color", "")?.as_str() {
+                "" => MaskMode::Recolor(None),
+                s => MaskMode::Recolor(Some(parse_color(s).ok_or(format!(
+                    "Invalid 'mask_color' parameter '{}', must be \"r g b\" in [0, 1]",
+                    s
+                ))?)),
+            },
+            s => return Err(format!("Unknown mask mode '{}'", s).into()),
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|err| format!("Can't read mask file '{}': {}", path, err))?;
+        let polygons =
+            parse_mask(&text).map_err(|err| format!("Invalid mask file '{}': {}", path, err))?;
+        Ok(Some(MaskOpts { polygons, mode }))
+    }
+
+    /// Returns true if the point is inside of the mask
+    pub fn contains(&self, p: &GeoPoint) -> bool {
+        self.polygons.iter().any(|polygon| polygon.contains(p))
+    }
+}
+
+/// Parses a color as "r g b"
+fn parse_color(s: &str) -> Option<RGB> {
+    let values = s
+        .split_whitespace()
+        .map(|c| c.parse::<ColorComponent>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match values[..] {
+        [r, g, b] if values.iter().all(|c| (0.0..=1.0).contains(c)) => Some(RGB(r, g, b)),
+        _ => None,
+    }
+}
+
+/// Parses mask polygons from GeoJSON text
+pub fn parse_mask(text: &str) -> Result<Vec<MaskPolygon>, String> {
+    let json: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let mut polygons = vec![];
+    for (i, feature) in geojson_features(&json)?.iter().enumerate() {
+        match parse_geometry(&feature["geometry"]) {
+            Ok(EditRegion::Polygons(rings)) => {
+                polygons.extend(rings.into_iter().map(MaskPolygon::new))
+            }
+            Ok(EditRegion::Lines(_)) => {
+                return Err(format!("feature {}: mask geometry must be polygons", i));
+            }
+            Err(err) => return Err(format!("feature {}: {}", i, err)),
+        }
+    }
+    if polygons.is_empty() {
+        return Err("no polygons".to_string());
+    }
+    Ok(polygons)
+}
+
+impl MaskPolygon {
+    /// Makes the polygon of rings, unwrapping longitudes across the antimeridian
+    pub fn new(rings: Vec<Vec<GeoPoint>>) -> Self {
+        let mut reference = None;
+        let rings: Vec<Vec<GeoPoint>> = rings
+            .into_iter()
+            .map(|ring| {
+                let mut previous = None;
+                ring.into_iter()
+                    .map(|p| {
+                        // the first point of every ring is near the first point of the outer ring
+                        let near = previous.or(reference).unwrap_or(p.lon);
+                        let lon = near + (p.lon - near + 180.0).rem_euclid(360.0) - 180.0;
+                        previous = Some(lon);
+                        reference = reference.or(Some(lon));
+                        GeoPoint { lon, lat: p.lat }
+                    })
+                    .collect()
+            })
+            .collect();
+        let bbox = rings.iter().flatten().fold(
+            (Coord::MAX, Coord::MAX, Coord::MIN, Coord::MIN),
+            |(lon_min, lat_min, lon_max, lat_max), p| {
+                (
+                    lon_min.min(p.lon),
+                    lat_min.min(p.lat),
+                    lon_max.max(p.lon),
+                    lat_max.max(p.lat),
+                )
+            },
+        );
+        MaskPolygon { rings, bbox }
+    }
+
+    /// Returns true if the point is inside of the polygon (even-odd rule over all rings)
+    pub fn contains(&self, p: &GeoPoint) -> bool {
+        [p.lon - 360.0, p.lon, p.lon + 360.0]
+            .iter()
+            .any(|lon| self.contains_unwrapped(*lon, p.lat))
+    }
+
+    fn contains_unwrapped(&self, lon: Coord, lat: Coord) -> bool {
+        let (lon_min, lat_min, lon_max, lat_max) = self.bbox;
+        if lon < lon_min || lon > lon_max || lat < lat_min || lat > lat_max {
+            return false;
+        }
+        let mut inside = false;
+        for ring in &self.rings {
+            for (i, a) in ring.iter().enumerate() {
+                let b = &ring[(i + 1) % ring.len()];
+                if (a.lat > lat) != (b.lat > lat)
+                    && lon < a.lon + (b.lon - a.lon) * (lat - a.lat) / (b.lat - a.lat)
+                {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+}
+
+fn lerp(a: Point3d, b: Point3d, t: Coord) -> Point3d {
+    let (x, y, z) = (
+        a.0 + t * (b.0 - a.0),
+        a.1 + t * (b.1 - a.1),
+        a.2 + t * (b.2 - a.2),
+    );
+    let r = (x * x + y * y + z * z).sqrt();
+    (x / r, y / r, z / r)
+}
+
+/// Position along the arc from `a` to `b` where the boundary of the mask is crossed
+fn crossing(opts: &MaskOpts, a: Point3d, b: Point3d) -> Coord {
+    let inside_a = opts.contains(&point3d_to_geo(a));
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..CROSSING_ITERATIONS {
+        let mid = (lo + hi) / 2.0;
+        if opts.contains(&point3d_to_geo(lerp(a, b, mid))) == inside_a {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// Crops the tessellation to the mask
+///
+/// Faces entirely outside are dropped, faces crossing the boundary are cut along it.
+pub fn crop_tessellation(
+    data: TessellationData,
+    opts: &MaskOpts,
+) -> Result<TessellationData, ErrBox> {
+    let TessellationData {
+        modeldata: ModelData(vertices, faces, texture_mapping),
+        texture_coordinates,
+        submeshes,
+    } = data;
+    let pmap = texture_mapping.ok_or("Cropping requires a triangulated model")?;
+    if faces.is_empty() {
+        return Err("Cropping requires a triangulated model".into());
+    }
+    if vertices.keys().enumerate().any(|(i, k)| i != *k) {
+        return Err("Model vertices must have contiguous indices for cropping".into());
+    }
+    let vertex = |t: &GeoPointIndex| -> Result<GeoPointIndex, ErrBox> {
+        pmap.get(t)
+            .cloned()
+            .ok_or(format!("Point {} isn't found in points mapping", t).into())
+    };
+
+    let mut positions: Vec<Point3d> = vertices
+        .values()
+        .map(|gp| geo_to_point3d(gp.lon, gp.lat))
+        .collect();
+    let mut geo_points: Vec<GeoPoint> = vertices.into_values().collect();
+    let inside: Vec<bool> = geo_points.iter().map(|gp| opts.contains(gp)).collect();
+    let mut texture_coordinates = texture_coordinates;
+    let mut texture_vertex: Vec<GeoPointIndex> = vec![0; texture_coordinates.len()];
+    for (t, v) in &pmap {
+        *texture_vertex
+            .get_mut(*t)
+            .ok_or(format!("Missed texture coordinates for point {}", t))? = *v;
+    }
+
+    // crossing points are shared by faces on both sides of an edge
+    let mut vertex_crossings: HashMap<(GeoPointIndex, GeoPointIndex), (GeoPointIndex, Coord)> =
+        HashMap::new();
+    let mut texture_crossings: HashMap<(GeoPointIndex, GeoPointIndex), GeoPointIndex> =
+        HashMap::new();
+    let mut cut_point = |ta: GeoPointIndex, tb: GeoPointIndex| -> GeoPointIndex {
+        let (va, vb) = (texture_vertex[ta], texture_vertex[tb]);
+        let (m, t) = *vertex_crossings
+            .entry((va.min(vb), va.max(vb)))
+            .or_insert_with(|| {
+                let (lo, hi) = (va.min(vb), va.max(vb));
+                let t = crossing(opts, positions[lo], positions[hi]);
+                let p = lerp(positions[lo], positions[hi], t);
+                positions.push(p);
+                geo_points.push(point3d_to_geo(p));
+                (positions.len() - 1, t)
+            });
+        *texture_crossings
+            .entry((ta.min(tb), ta.max(tb)))
+            .or_insert_with(|| {
+                let (tl, th) = if texture_vertex[ta] == va.min(vb) {
+                    (ta, tb)
+                } else {
+                    (tb, ta)
+                };
+                let ((ul, vl), (uh, vh)) = (texture_coordinates[tl], texture_coordinates[th]);
+                texture_coordinates.push((ul + t * (uh - ul), vl + t * (vh - vl)));
+                texture_vertex.push(m);
+                texture_coordinates.len() - 1
+            })
+    };
+
+    let mut new_faces: Vec<[GeoPointIndex; 3]> = Vec::with_capacity(faces.len());
+    let mut face_starts = Vec::with_capacity(faces.len() + 1);
+    for (t0, t1, t2) in &faces {
+        face_starts.push(new_faces.len());
+        let tps = [*t0, *t1, *t2];
+        let ins = [
+            inside[vertex(t0)?],
+            inside[vertex(t1)?],
+            inside[vertex(t2)?],
+        ];
+        match ins.iter().filter(|i| **i).count() {
+            0 => continue,
+            3 => {
+                new_faces.push(tps);
+                continue;
+            }
+            _ => (),
+        }
+        let mut polygon = Vec::with_capacity(4);
+        for c in 0..3 {
+            if ins[c] {
+                polygon.push(tps[c]);
+            }
+            if ins[c] != ins[(c + 1) % 3] {
+                polygon.push(cut_point(tps[c], tps[(c + 1) % 3]));
+            }
+        }
+        for i in 1..polygon.len() - 1 {
+            new_faces.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+    }
+    face_starts.push(new_faces.len());
+    if new_faces.is_empty() {
+        return Err("Mask leaves no faces of the model".into());
+    }
+
+    // compaction
+    let mut used_texture_points: Vec<GeoPointIndex> = new_faces.iter().flatten().cloned().collect();
+    used_texture_points.sort_unstable();
+    used_texture_points.dedup();
+    let mut used_vertices: Vec<GeoPointIndex> = used_texture_points
+        .iter()
+        .map(|t| texture_vertex[*t])
+        .collect();
+    used_vertices.sort_unstable();
+    used_vertices.dedup();
+    let vertex_index: HashMap<GeoPointIndex, GeoPointIndex> = used_vertices
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (*v, i))
+        .collect();
+    let texture_index: HashMap<GeoPointIndex, GeoPointIndex> = used_texture_points
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (*t, i))
+        .collect();
+
+    let vertices: Vertices = used_vertices
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i, geo_points[*v]))
+        .collect::<BTreeMap<_, _>>();
+    let texture_mapping: PointsMapping = used_texture_points
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (i, vertex_index[&texture_vertex[*t]]))
+        .collect();
+    let texture_coordinates: TextureCoordinates = used_texture_points
+        .iter()
+        .map(|t| texture_coordinates[*t])
+        .collect();
+    let faces: Faces = new_faces
+        .iter()
+        .map(|tps| {
+            (
+                texture_index[&tps[0]],
+                texture_index[&tps[1]],
+                texture_index[&tps[2]],
+            )
+        })
+        .collect();
+    let submeshes = submeshes.map(|submeshes| {
+        submeshes
+            .into_iter()
+            .map(|(name, range)| (name, face_starts[range.start]..face_starts[range.end]))
+            .collect()
+    });
+
+    Ok(TessellationData {
+        modeldata: ModelData::create(vertices, faces, Some(texture_mapping)),
+        texture_coordinates,
+        submeshes,
+    })
+}
+
+/// Sets elevations of vertices outside of the mask to the level
+pub fn mask_heights(heights: &mut Heights, vertices: &Vertices, opts: &MaskOpts, level: Height) {
+    for (k, gp) in vertices {
+        if !opts.contains(gp) {
+            heights.insert(*k, level);
+        }
+    }
+}
+
+/// Sets colors of vertices outside of the mask to the color
+pub fn mask_colors(colors: &mut Colors, vertices: &Vertices, opts: &MaskOpts, color: RGB) {
+    for (k, gp) in vertices {
+        if !opts.contains(gp) {
+            colors.insert(*k, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &str = r#"{"type": "Feature", "properties": {}, "geometry": {
+        "type": "Polygon",
+        "coordinates": [[[-40, -40], [40, -40], [40, 40], [-40, 40], [-40, -40]],
+                        [[-10, -10], [10, -10], [10, 10], [-10, 10], [-10, -10]]]}}"#;
+
+    fn square_opts(mode: MaskMode) -> MaskOpts {
+        MaskOpts {
+            polygons: parse_mask(SQUARE).unwrap(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn parse_mask_t0() {
+        let opts = square_opts(MaskMode::Crop);
+        assert_eq!(opts.polygons.len(), 1);
+        assert_eq!(opts.polygons[0].bbox, (-40.0, -40.0, 40.0, 40.0));
+        assert!(opts.contains(&GeoPoint {
+            lon: 20.0,
+            lat: 0.0
+        }));
+        assert!(!opts.contains(&GeoPoint { lon: 0.0, lat: 0.0 }));
+        assert!(!opts.contains(&GeoPoint {
+            lon: 60.0,
+            lat: 0.0
+        }));
+        let line = r#"{"type": "Feature", "properties": {},
+            "geometry": {"type": "LineString", "coordinates": [[0, 0], [1, 1]]}}"#;
+        assert!(parse_mask(line).is_err());
+        assert_eq!(parse_color("0 0.5 1"), Some(RGB(0.0, 0.5, 1.0)));
+        assert_eq!(parse_color("0 2 1"), None);
+    }
+
+    #[test]
+    fn contains_t0() {
+        // polygon crossing the antimeridian
+        let opts = MaskOpts {
+            polygons: vec![MaskPolygon::new(vec![vec![
+                GeoPoint {
+                    lon: 170.0,
+                    lat: -10.0,
+                },
+                GeoPoint {
+                    lon: -170.0,
+                    lat: -10.0,
+                },
+                GeoPoint {
+                    lon: -170.0,
+                    lat: 10.0,
+                },
+                GeoPoint {
+                    lon: 170.0,
+                    lat: 10.0,
+                },
+            ]])],
+            mode: MaskMode::Crop,
+        };
+        assert!(opts.contains(&GeoPoint {
+            lon: 179.0,
+            lat: 0.0
+        }));
+        assert!(opts.contains(&GeoPoint {
+            lon: -179.0,
+            lat: 5.0
+        }));
+        assert!(!opts.contains(&GeoPoint { lon: 0.0, lat: 0.0 }));
+    }
+
+    #[test]
+    fn crop_tessellation_t0() {
+        let opts = square_opts(MaskMode::Crop);
+        let data = crop_tessellation(create_icosphere(5), &opts).unwrap();
+        let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+        let pmap = texture_mapping.as_ref().unwrap();
+        assert_eq!(pmap.len(), data.texture_coordinates.len());
+        assert!(vertices.keys().enumerate().all(|(i, k)| i == *k));
+        // all vertices are inside or on the boundary
+        for gp in vertices.values() {
+            let near = |a: Coord, b: Coord| (a.abs() - b).abs() < 1e-6;
+            let on_boundary = (near(gp.lon, 40.0) || near(gp.lat, 40.0))
+                || (gp.lon.abs() <= 10.0 + 1e-6 && near(gp.lat, 10.0))
+                || (gp.lat.abs() <= 10.0 + 1e-6 && near(gp.lon, 10.0));
+            assert!(opts.contains(gp) || on_boundary, "{:?}", gp);
+        }
+        // every face uses existing texture points
+        assert!(
+            faces
+                .iter()
+                .all(|(t0, t1, t2)| [t0, t1, t2].iter().all(|t| pmap.contains_key(t)))
+        );
+        // the area of the cropped surface is close to the area of the region
+        let area: Coord = faces
+            .iter()
+            .map(|(t0, t1, t2)| {
+                let [a, b, c] = [t0, t1, t2].map(|t| {
+                    let gp = &vertices[&pmap[t]];
+                    geo_to_point3d(gp.lon, gp.lat)
+                });
+                let (u, v) = (
+                    (b.0 - a.0, b.1 - a.1, b.2 - a.2),
+                    (c.0 - a.0, c.1 - a.1, c.2 - a.2),
+                );
+                let n = (
+                    u.1 * v.2 - u.2 * v.1,
+                    u.2 * v.0 - u.0 * v.2,
+                    u.0 * v.1 - u.1 * v.0,
+                );
+                (n.0 * n.0 + n.1 * n.1 + n.2 * n.2).sqrt() / 2.0
+            })
+            .sum();
+        let band = |lon: Coord, lat: Coord| 2.0 * lon.to_radians() * 2.0 * lat.to_radians().sin();
+        let expected = band(40.0, 40.0) - band(10.0, 10.0);
+        assert!(
+            (area - expected).abs() < 0.05 * expected,
+            "{} {}",
+            area,
+            expected
+        );
+    }
+
+    #[test]
+    fn mask_heights_t0() {
+        let TessellationData {
+            modeldata: ModelData(vertices, _, _),
+            ..
+        } = create_icosphere(4);
+        let mut heights: Heights = vertices.keys().map(|k| (*k, 100.0)).collect();
+        let opts = square_opts(MaskMode::Flatten(Some(-5.0)));
+        mask_heights(&mut heights, &vertices, &opts, -5.0);
+        for (k, gp) in &vertices {
+            let expected = if opts.contains(gp) { 100.0 } else { -5.0 };
+            assert_eq!(heights[k], expected);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! # Indexed Meshes
+//!
+//! This module converts solids to meshes with a single index per vertex, as
+//! engines and interchange formats (glTF, PLY) expect. OBJ indexes positions, texture
+//! coordinates and normals separately, so a vertex on a texture seam or a crease
+//! is a single position with several texture coordinates or normals. Indexed
+//! meshes have one vertex for every distinct combination of them.
+use crate::common::color::*;
+use crate::common::types::*;
+use crate::model::frame::*;
+use crate::model::normals::*;
+use crate::model::solid::*;
+use crate::model::tessellation::Point3d;
+use crate::model::types::*;
+use std::collections::HashMap;
+
+/// Mesh with one set of attributes per vertex
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexedMesh {
+    /// Positions in output coordinates
+    pub positions: Vec<Point3d>,
+    /// Unit normals in output coordinates (empty without normals)
+    pub normals: Vec<Point3d>,
+    /// Texture coordinates (empty for color models)
+    pub texture_coordinates: TextureCoordinates,
+    /// Colors (empty for texture models)
+    pub colors: Vec<RGB>,
+    /// Vertex of the solid each vertex is made of
+    pub sources: Vec<GeoPointIndex>,
+    /// Triangles grouped by submeshes, the name is `None` for faces out of submeshes
+    pub primitives: Vec<(Option<String>, Vec<[u32; 3]>)>,
+}
+
+impl IndexedMesh {
+    /// Makes the indexed mesh of a solid
+    ///
+    /// `colors` are indexed by model vertices, `origin` and `frame` give output coordinates.
+    pub fn from_solid(
+        solid: &Solid,
+        normals: Option<&SolidNormals>,
+        colors: Option<&Colors>,
+        submeshes: Option<&Submeshes>,
+        frame: &FrameOpts,
+        origin: Point3d,
+    ) -> Result<Self, ErrBox> {
+        let textured = !solid.texture_coordinates.is_empty();
+        let mut mesh = IndexedMesh::default();
+        let mut index: HashMap<(usize, usize, usize), u32> = HashMap::new();
+        let mut primitive_index: HashMap<Option<&String>, usize> = HashMap::new();
+        for (f, (face, face_source)) in solid.faces.iter().zip(&solid.face_sources).enumerate() {
+            let submesh = submeshes.and_then(|submeshes| {
+                submeshes
+                    .iter()
+                    .find(|(_, range)| range.contains(face_source))
+                    .map(|(name, _)| name)
+            });
+            let mut triangle = [0; 3];
+            for c in 0..3 {
+                let (v, t) = face[c];
+                let n = normals.map_or(0, |normals| normals.corners[f][c]);
+                let key = (v, if textured { t } else { 0 }, n);
+                triangle[c] = match index.get(&key) {
+                    Some(i) => *i,
+                    None => {
+                        let i = u32::try_from(mesh.positions.len())
+                            .map_err(|_| "Too many vertices for 32-bit indices")?;
+                        mesh.positions
+                            .push(frame.transform(origin, solid.positions[v]));
+                        if let Some(normals) = normals {
+                            mesh.normals
+                                .push(frame.transform((0.0, 0.0, 0.0), normals.normals[n]));
+                        }
+                        if textured {
+                            mesh.texture_coordinates.push(
+                                *solid
+                                    .texture_coordinates
+                                    .get(t)
+                                    .ok_or(format!("Missed texture coordinates for point {}", t))?,
+                            );
+                        }
+                        let source = solid.sources[v];
+                        if let Some(colors) = colors {
+                            mesh.colors.push(
+                                *colors
+                                    .get(&source)
+                                    .ok_or(format!("Missed color for point {}", source))?,
+                            );
+                        }
+                        mesh.sources.push(source);
+                        index.insert(key, i);
+                        i
+                    }
+                };
+            }
+            let p = *primitive_index.entry(submesh).or_insert_with(|| {
+                mesh.primitives.push((submesh.cloned(), vec![]));
+                mesh.primitives.len() - 1
+            });
+            mesh.primitives[p].1.push(triangle);
+        }
+        Ok(mesh)
+    }
+
+    /// Reverses the winding of all triangles
+    ///
+    /// Right-handed formats need it in left-handed frames, where the transform
+    /// mirrors triangles to clockwise when seen from outside.
+    pub fn reverse_winding(&mut self) {
+        for (_, triangles) in &mut self.primitives {
+            for triangle in triangles {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_solid_t0() {
+        // a vertex on a texture seam is split, a plain vertex is shared
+        let solid = Solid {
+            positions: vec![
+                (0.0, 0.0, 0.0),
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (1.0, 1.0, 0.0),
+            ],
+            sources: vec![0, 1, 2, 3],
+            faces: vec![[(0, 0), (1, 1), (2, 2)], [(1, 1), (3, 3), (2, 4)]],
+            face_sources: vec![0, 1],
+            texture_coordinates: vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.5, 1.0)],
+        };
+        let submeshes = vec![("a".to_string(), 0..1), ("b".to_string(), 1..2)];
+        let mesh = IndexedMesh::from_solid(
+            &solid,
+            None,
+            None,
+            Some(&submeshes),
+            &FrameOpts::default(),
+            (0.0, 0.0, 0.0),
+        )
+        .unwrap();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.sources, vec![0, 1, 2, 3, 2]);
+        assert_eq!(
+            mesh.primitives,
+            vec![
+                (Some("a".to_string()), vec![[0, 1, 2]]),
+                (Some("b".to_string()), vec![[1, 3, 4]])
+            ]
+        );
+        assert!(mesh.normals.is_empty() && mesh.colors.is_empty());
+    }
+
+    #[test]
+    fn reverse_winding_t0() {
+        let mut mesh = IndexedMesh {
+            primitives: vec![(None, vec![[0, 1, 2], [1, 3, 2]])],
+            ..Default::default()
+        };
+        mesh.reverse_winding();
+        assert_eq!(mesh.primitives, vec![(None, vec![[0, 2, 1], [1, 2, 3]])]);
+    }
+}
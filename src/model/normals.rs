@@ -0,0 +1,235 @@
+//! # Vertex Normals
+//!
+//! This module computes smooth normals of the displaced surface, so that viewers
+//! don't have to guess them from a crease angle.
+//!
+//! ## Solid Meshes
+//!
+//! Normals of solids are computed per face corner. The normal of a corner is the
+//! sum of the normals of the faces around its vertex, weighted by their angles at
+//! the vertex. Only faces bent less than the crease angle from the face of the
+//! corner take part, so cut faces and walls of pin holes keep sharp edges while
+//! the planet surface is smooth. Vertices are shared across texture seams and at
+//! the poles, so their normals are continuous there.
+//!
+//! ## Elevation Grids
+//!
+//! Normals of elevation grids are computed by central differences of neighbouring
+//! grid points. The grid wraps around the antimeridian, where the first and the
+//! last columns coincide, and the normals of pole rows point along the axis.
+//!
+//! ## Configuration Parameters
+//!
+//! - `normals`: "smooth" (default) or "none"
+//! - `crease_angle`: angle between faces in degrees above which edges stay sharp (default: 60.0)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::solid::*;
+use crate::model::tessellation::Point3d;
+use crate::model::vector::*;
+use std::collections::HashMap;
+
+const DEFAULT_CREASE_ANGLE: f64 = 60.0;
+
+/// Options of normals
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalOpts {
+    /// Angle between faces in degrees above which edges stay sharp
+    pub crease_angle: Coord,
+}
+
+impl NormalOpts {
+    /// Reads options of normals from the settings
+    ///
+    /// Returns `None` if normals aren't written.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        match settings.get_parameter_str("normals", "smooth")?.as_str() {
+            "smooth" => Ok(Some(NormalOpts {
+                crease_angle: settings.get_parameter_num("crease_angle", DEFAULT_CREASE_ANGLE)?,
+            })),
+            "none" => Ok(None),
+            s => Err(format!("Unknown normals '{}'", s).into()),
+        }
+    }
+}
+
+/// Normals of a solid
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolidNormals {
+    /// Unit normals
+    pub normals: Vec<Point3d>,
+    /// Normal of each face corner
+    pub corners: Vec<[usize; 3]>,
+}
+
+fn normalized(a: Point3d) -> Option<Point3d> {
+    let l = length(a);
+    if l > 0.0 && l.is_finite() {
+        Some(scaled(a, 1.0 / l))
+    } else {
+        None
+    }
+}
+
+/// Angle of the triangle at `a`
+fn corner_angle(a: Point3d, b: Point3d, c: Point3d) -> Coord {
+    let (u, v) = (sub(b, a), sub(c, a));
+    dot(cross(u, v), cross(u, v)).sqrt().atan2(dot(u, v))
+}
+
+impl Solid {
+    /// Computes smooth normals of face corners, keeping edges sharper than the crease angle
+    pub fn normals(&self, opts: &NormalOpts) -> SolidNormals {
+        let cos_crease = (opts.crease_angle.to_radians()).cos();
+        let face_normals: Vec<Option<Point3d>> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let [a, b, c] = face.map(|(v, _)| self.positions[v]);
+                normalized(cross(sub(b, a), sub(c, a)))
+            })
+            .collect();
+
+        // angle-weighted normals of faces around each vertex
+        let mut around: Vec<Vec<(usize, Point3d)>> = vec![vec![]; self.positions.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            let Some(n) = face_normals[f] else {
+                continue;
+            };
+            for c in 0..3 {
+                let p = face.map(|(v, _)| self.positions[v]);
+                let w = corner_angle(p[c], p[(c + 1) % 3], p[(c + 2) % 3]);
+                around[face[c].0].push((f, (n.0 * w, n.1 * w, n.2 * w)));
+            }
+        }
+
+        let mut normals = vec![];
+        let mut index: HashMap<(GeoPointIndex, [u64; 3]), usize> = HashMap::new();
+        let mut corners = Vec::with_capacity(self.faces.len());
+        for (f, face) in self.faces.iter().enumerate() {
+            let corner = face.map(|(v, _)| {
+                let sum = around[v]
+                    .iter()
+                    .filter(|(g, _)| match (face_normals[f], face_normals[*g]) {
+                        (Some(nf), Some(ng)) => dot(nf, ng) >= cos_crease,
+                        _ => true,
+                    })
+                    .fold((0.0, 0.0, 0.0), |s, (_, n)| {
+                        (s.0 + n.0, s.1 + n.1, s.2 + n.2)
+                    });
+                let n = normalized(sum)
+                    .or_else(|| normalized(self.positions[v]))
+                    .unwrap_or((0.0, 0.0, 1.0));
+                *index
+                    .entry((v, [n.0.to_bits(), n.1.to_bits(), n.2.to_bits()]))
+                    .or_insert_with(|| {
+                        normals.push(n);
+                        normals.len() - 1
+                    })
+            });
+            corners.push(corner);
+        }
+        SolidNormals { normals, corners }
+    }
+}
+
+/// Computes normals of an elevation grid around the globe
+///
+/// `positions` are grid points by rows of `x_dimension` points from the south
+/// pole to the north pole. The first and the last points of every row coincide.
+pub fn grid_normals(positions: &[Point3d], x_dimension: usize) -> Vec<Point3d> {
+    let z_dimension = positions.len() / x_dimension;
+    let mut normals = Vec::with_capacity(positions.len());
+    for j in 0..z_dimension {
+        for i in 0..x_dimension {
+            if j == 0 || j == z_dimension - 1 {
+                normals.push((0.0, 0.0, if j == 0 { -1.0 } else { 1.0 }));
+                continue;
+            }
+            let column = |i: usize, j: usize| positions[j * x_dimension + i];
+            let (west, east) = match i {
+                0 => (x_dimension - 2, 1),
+                i if i == x_dimension - 1 => (i - 1, 1),
+                i => (i - 1, i + 1),
+            };
+            let east_west = sub(column(east, j), column(west, j));
+            let north_south = sub(column(i, j + 1), column(i, j - 1));
+            let n = normalized(cross(east_west, north_south))
+                .or_else(|| normalized(column(i, j)))
+                .unwrap_or((0.0, 0.0, 1.0));
+            normals.push(n);
+        }
+    }
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tessellation::*;
+    use crate::model::types::*;
+
+    #[test]
+    fn normals_t0() {
+        let TessellationData {
+            modeldata: ModelData(vertices, faces, pmap),
+            texture_coordinates,
+            ..
+        } = create_icosphere(2);
+        let positions = vertices
+            .values()
+            .map(|gp| geo_to_point3d(gp.lon, gp.lat))
+            .collect();
+        let solid =
+            Solid::from_surface(positions, &faces, &pmap.unwrap(), texture_coordinates).unwrap();
+        let SolidNormals { normals, corners } = solid.normals(&NormalOpts { crease_angle: 60.0 });
+        // one normal per vertex, even on texture seams, pointing outwards
+        assert_eq!(normals.len(), solid.positions.len());
+        for (face, corner) in solid.faces.iter().zip(&corners) {
+            for c in 0..3 {
+                assert!(dot(normals[corner[c]], solid.positions[face[c].0]) > 0.99);
+            }
+        }
+    }
+
+    #[test]
+    fn normals_t1() {
+        // two faces of a cube edge keep separate normals
+        let solid = Solid {
+            positions: vec![
+                (0.0, 0.0, 0.0),
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (0.0, 0.0, 1.0),
+            ],
+            sources: vec![0, 1, 2, 3],
+            faces: vec![[(0, 0), (2, 0), (1, 0)], [(0, 0), (1, 0), (3, 0)]],
+            face_sources: vec![0, 1],
+            texture_coordinates: vec![],
+        };
+        let SolidNormals { normals, corners } = solid.normals(&NormalOpts { crease_angle: 60.0 });
+        assert_eq!(normals[corners[0][0]], (0.0, 0.0, -1.0));
+        assert_eq!(normals[corners[1][0]], (0.0, -1.0, 0.0));
+        let smooth = solid.normals(&NormalOpts {
+            crease_angle: 100.0,
+        });
+        assert_eq!(smooth.corners[0][0], smooth.corners[1][0]);
+    }
+
+    #[test]
+    fn grid_normals_t0() {
+        let (x_dimension, z_dimension) = (9, 5);
+        let positions: Vec<Point3d> = (0..z_dimension)
+            .flat_map(|j| {
+                (0..x_dimension).map(move |i| {
+                    geo_to_point3d(-180.0 + 45.0 * i as Coord, -90.0 + 45.0 * j as Coord)
+                })
+            })
+            .collect();
+        let normals = grid_normals(&positions, x_dimension);
+        for (p, n) in positions.iter().zip(&normals) {
+            assert!(dot(*p, *n) > 0.9);
+        }
+        assert_eq!(normals[x_dimension * 2], normals[x_dimension * 3 - 1]);
+    }
+}
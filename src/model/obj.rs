@@ -10,7 +10,8 @@
 //! - **Texture Coordinate Mapping**: Generates UV coordinates for proper texture mapping
 //! - **Color Support**: Supports per-vertex color mapping with color quantization
 //! - **OBJ File Output**: Generates complete OBJ files with proper formatting
-//! - **Memory Efficient**: Uses buffered I/O for handling large meshes
+//! - **Memory Efficient**: Uses buffered I/O for handling large meshes, optionally
+//!   through a gzip or zstd encoder (see `compression`)
 //!
 //! ## Key Components
 //!
@@ -46,6 +47,13 @@ use crate::common::color::*;
 use crate::common::settings::*;
 use crate::common::types::*;
 use crate::common::util::*;
+use crate::model::compression::*;
+use crate::model::mask::*;
+use crate::model::normals::*;
+use crate::model::solid::*;
+use crate::model::split::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
 use crate::model::types::*;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
@@ -58,8 +66,6 @@ const DEFAULT_MODEL_SIZE: usize = 16;
 const DEFAULT_TEMPLATE_FILE_OBJ: &str = "./obj.template";
 const DEFAULT_TEMPLATE_FILE_MTL: &str = "./mtl.template";
 const DEFAULT_TEXTURE_URI: &str = "texture.png";
-const DEFAULT_RADIUS: f64 = 6378000.0;
-const DEFAULT_SCALE: f64 = 1.0;
 const DEFAULT_COLOR_PRECISION: i64 = 0;
 const FRACTION_LENGHT: usize = 5;
 const WRITER_BUF_STRINGS: usize = 1000;
@@ -76,10 +82,13 @@ pub struct Obj<'a> {
     model_type: ModelType,
     model_size: GeoPointIndex,
     settings: &'a Settings<'a>,
-    scale: Height,
-    radius: Height,
+    surface: Surface,
     color_precision: ColorPrecision,
     texture_uri: String,
+    print_opts: Option<PrintOpts>,
+    split_opts: Option<SplitOpts>,
+    normal_opts: Option<NormalOpts>,
+    compression: Compression,
     components: ModelComponents,
     template_file_mtl: PathBuf,
     template_file_obj: PathBuf,
@@ -359,21 +368,38 @@ impl<'a> Model<'a> for Obj<'a> {
         let str = settings.get_parameter_str("template_file_mtl", DEFAULT_TEMPLATE_FILE_MTL)?;
         let template_file_mtl = Path::new(&str).to_owned();
 
-        let scale = settings.get_parameter_num("scale", DEFAULT_SCALE)? as Height;
-        let radius = settings.get_parameter_num("radius", DEFAULT_RADIUS)? as Height;
+        let surface = Surface::from_settings(settings, &components)?;
         let color_precision = settings
             .get_parameter_num("color_precision", DEFAULT_COLOR_PRECISION)?
             as ColorPrecision;
         let texture_uri = settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?;
+        let print_opts = PrintOpts::from_settings(settings)?;
+        let split_opts = SplitOpts::from_settings(settings)?;
+        let normal_opts = NormalOpts::from_settings(settings)?;
+        let compression = Compression::from_settings(settings)?;
+        if let (
+            Some(_),
+            Some(MaskOpts {
+                mode: MaskMode::Crop,
+                ..
+            }),
+        ) = (&print_opts, MaskOpts::from_settings(settings)?)
+        {
+            return Err("Cropped models are open surfaces and can't be hollowed".into());
+        }
+        surface.check_print_opts(print_opts.as_ref(), split_opts.as_ref())?;
 
         return Ok(Obj {
             model_type,
             model_size,
             settings,
-            scale,
-            radius,
+            surface,
             color_precision,
             texture_uri,
+            print_opts,
+            split_opts,
+            normal_opts,
+            compression,
             components,
             template_file_mtl,
             template_file_obj,
@@ -432,10 +458,23 @@ impl<'a> Model<'a> for Obj<'a> {
                     self.template_file_mtl, err
                 )
             })?;
+            let material = material_definition(&data).to_string();
             if let ModelType::Texture = &self.model_type {
                 data.push_str(&format!("map_Kd {}\n", self.texture_uri))
             }
             data.push_str("\n");
+            if let (ModelType::Texture, Some(submeshes)) =
+                (&self.model_type, &self.components.submeshes)
+            {
+                for (name, _) in submeshes {
+                    data.push_str(&format!("newmtl Material_{}\n", name));
+                    data.push_str(&material);
+                    data.push_str(&format!(
+                        "map_Kd {}\n\n",
+                        make_tile_uri(&self.texture_uri, name)
+                    ));
+                }
+            }
             f_mtl
                 .write_all(data.as_bytes())
                 .map_err(|err| format!("Can't write to mtl file {}: {}", &mtl_path, err))?;
@@ -466,7 +505,10 @@ impl<'a> Model<'a> for Obj<'a> {
         };
 
         // obj file
-        let create_obj = || -> Result<(), ErrBox> {
+        let create_obj = |result_path_opt: PathBuf,
+                          solid: &Solid,
+                          origin: Point3d|
+         -> Result<(), ErrBox> {
             let mut data = match &self.model_type {
                 ModelType::Color if self.color_precision == 0 => {
                     String::with_capacity((3 * (FRACTION_LENGHT + 4 + 6) + 1) * WRITER_BUF_STRINGS)
@@ -479,9 +521,6 @@ impl<'a> Model<'a> for Obj<'a> {
                 }
             };
 
-            let result_path_opt = Path::new(&output_path)
-                .join(&planet_name)
-                .with_extension("obj");
             let result_path = match result_path_opt.to_str() {
                 Some(fp) => fp,
                 None => {
@@ -492,9 +531,10 @@ impl<'a> Model<'a> for Obj<'a> {
                     .into());
                 }
             };
-            let f_obj = File::create(&result_path)
+            let mut f_obj = self
+                .compression
+                .create(&result_path_opt)
                 .map_err(|err| format!("Can't create obj file {}: {}", &result_path, err))?;
-            let mut f_obj = BufWriter::new(f_obj);
 
             // header
             data.clear();
@@ -520,22 +560,16 @@ impl<'a> Model<'a> for Obj<'a> {
 
             // vertices
             data.clear();
-            let gps = &self.components.get_vertices()?;
             let mut vertex_count = 0;
-            for (i, gp) in gps.iter() {
-                let GeoPoint { lon, lat } = *gp;
-                let height = match self.components.heights.get(i) {
-                    None => 0.0,
-                    Some(h) => *h,
-                };
-                let (x, y, z) = calc_point3d(self.radius, self.scale, height, lon, lat);
+            for (i, (p, source)) in solid.positions.iter().zip(&solid.sources).enumerate() {
+                let (x, y, z) = self.surface.frame.transform(origin, *p);
                 match &self.model_type {
                     ModelType::Color if self.color_precision == 0 => {
                         let rgb = self
                             .components
                             .get_colors()?
-                            .get(i)
-                            .ok_or(format!("Missed color for point {}", i))?;
+                            .get(source)
+                            .ok_or(format!("Missed color for point {}", source))?;
                         data.push_str(format!("v {:.5} {:.5} {:.5} {}\n", x, y, z, rgb).as_str())
                     }
                     _ => data.push_str(format!("v {:.5} {:.5} {:.5}\n", x, y, z).as_str()), // XXX: FRACTION_LENGHT=5
@@ -561,7 +595,7 @@ impl<'a> Model<'a> for Obj<'a> {
             if let ModelType::Texture = &self.model_type {
                 data.clear();
                 let mut coord_count = 0;
-                let texture_coordinates = self.components.get_texture_coordinates()?;
+                let texture_coordinates = &solid.texture_coordinates;
                 for (u, v) in texture_coordinates.iter() {
                     data.push_str(format!("vt {:.6} {:.6}\n", u, v).as_str());
 
@@ -588,69 +622,78 @@ impl<'a> Model<'a> for Obj<'a> {
                 })?;
             };
 
+            // normals
+            let normals = self.normal_opts.as_ref().map(|opts| solid.normals(opts));
+            if let Some(SolidNormals { normals, .. }) = &normals {
+                data.clear();
+                for (i, n) in normals.iter().enumerate() {
+                    let (x, y, z) = self.surface.frame.transform((0.0, 0.0, 0.0), *n);
+                    data.push_str(format!("vn {:.5} {:.5} {:.5}\n", x, y, z).as_str());
+                    if i % WRITER_BUF_STRINGS == WRITER_BUF_STRINGS - 1 {
+                        f_obj.write_all(data.as_bytes()).map_err(|err| {
+                            format!(
+                                "Can't write chunk of normals to obj file {}: {}",
+                                &result_path, err
+                            )
+                        })?;
+                        data.clear();
+                    }
+                }
+                data.push_str(format!("# {} normals\n\n", normals.len()).as_str());
+                f_obj.write_all(data.as_bytes()).map_err(|err| {
+                    format!("Can't write normals to obj file {}: {}", &result_path, err)
+                })?;
+            }
+
             // faces
             data.clear();
             data.push_str("usemtl Material\n");
-            let pmap = self.components.get_texture_mapping()?;
             let allowed_color_func = make_allowed_color_function(self.color_precision);
             let mut prev_color_id = None;
             let mut faces_count = 1;
-            for (tvt0, tvt1, tvt2) in self.components.get_faces()?.iter() {
-                let vt0 = match pmap.get(tvt0) {
-                    Some(vt) => vt,
-                    None => {
-                        return Err(
-                            format!("Point tv0={} isn't found in points mapping", tvt0).into()
-                        );
+            let face_submesh = |face_index: usize| match &self.components.submeshes {
+                Some(submeshes) => submeshes
+                    .iter()
+                    .find(|(_, range)| range.contains(&face_index))
+                    .map(|(name, _)| name),
+                None => None,
+            };
+            let mut prev_submesh = None;
+            for (f, (face, face_index)) in solid.faces.iter().zip(&solid.face_sources).enumerate() {
+                let submesh = face_submesh(*face_index);
+                if submesh != prev_submesh {
+                    if let Some(name) = submesh {
+                        data.push_str(format!("g {}\n", name).as_str());
+                        if let ModelType::Texture = &self.model_type {
+                            data.push_str(format!("usemtl Material_{}\n", name).as_str());
+                        }
                     }
-                };
-                let vt1 = match pmap.get(tvt1) {
-                    Some(vt) => vt,
-                    None => {
-                        return Err(
-                            format!("Point tv1={} isn't found in points mapping", tvt1).into()
-                        );
+                    prev_submesh = submesh;
+                }
+                let corner = |c: usize| {
+                    let (vt, tvt) = face[c];
+                    let vn = normals.as_ref().map(|normals| normals.corners[f][c] + 1);
+                    match (&self.model_type, vn) {
+                        (ModelType::Texture, Some(vn)) => format!("{}/{}/{}", vt + 1, tvt + 1, vn),
+                        (ModelType::Texture, None) => format!("{}/{}", vt + 1, tvt + 1),
+                        (ModelType::Color, Some(vn)) => format!("{}//{}", vt + 1, vn),
+                        (ModelType::Color, None) => format!("{}", vt + 1),
                     }
                 };
-                let vt2 = match pmap.get(tvt2) {
-                    Some(vt) => vt,
-                    None => {
-                        return Err(
-                            format!("Point tv2={} isn't found in points mapping", tvt2).into()
-                        );
-                    }
-                };
-
-                match &self.model_type {
-                    ModelType::Texture => data.push_str(
-                        format!(
-                            "f {}/{} {}/{} {}/{}\n",
-                            vt0 + 1,
-                            tvt0 + 1,
-                            vt1 + 1,
-                            tvt1 + 1,
-                            vt2 + 1,
-                            tvt2 + 1
-                        )
-                        .as_str(),
-                    ),
-                    ModelType::Color if self.color_precision == 0 => {
-                        data.push_str(format!("f {} {} {}\n", vt0 + 1, vt1 + 1, vt2 + 1).as_str())
-                    }
-                    ModelType::Color => {
-                        let color = self
-                            .components
-                            .get_colors()?
-                            .get(vt0)
-                            .ok_or(format!("Missed color for vertex {}", vt0))?;
-                        let (_, color_id @ (r_k, g_k, b_k)) = allowed_color_func(*color);
-                        if prev_color_id.is_none() || Some(color_id) != prev_color_id {
-                            data.push_str(format!("usemtl c_{}_{}_{}\n", r_k, g_k, b_k).as_str());
-                            prev_color_id = Some(color_id);
-                        }
-                        data.push_str(format!("f {} {} {}\n", vt0 + 1, vt1 + 1, vt2 + 1).as_str());
+                if matches!(self.model_type, ModelType::Color) && self.color_precision != 0 {
+                    let source = solid.sources[face[0].0];
+                    let color = self
+                        .components
+                        .get_colors()?
+                        .get(&source)
+                        .ok_or(format!("Missed color for vertex {}", source))?;
+                    let (_, color_id @ (r_k, g_k, b_k)) = allowed_color_func(*color);
+                    if prev_color_id.is_none() || Some(color_id) != prev_color_id {
+                        data.push_str(format!("usemtl c_{}_{}_{}\n", r_k, g_k, b_k).as_str());
+                        prev_color_id = Some(color_id);
                     }
                 }
+                data.push_str(format!("f {} {} {}\n", corner(0), corner(1), corner(2)).as_str());
 
                 if faces_count % WRITER_BUF_STRINGS == WRITER_BUF_STRINGS - 1 {
                     f_obj.write_all(data.as_bytes()).map_err(|err| {
@@ -664,18 +707,81 @@ impl<'a> Model<'a> for Obj<'a> {
                 faces_count += 1;
             }
 
-            data.push_str(format!("# {} faces\n\n", self.components.get_faces()?.len()).as_str());
+            data.push_str(format!("# {} faces\n\n", solid.faces.len()).as_str());
             f_obj.write_all(data.as_bytes()).map_err(|err| {
                 format!("Can't write faces to obj file {}: {}", &result_path, err)
             })?;
 
             f_obj
-                .flush()
+                .finish()
                 .map_err(|err| format!("Can't flush obj file {}: {}", &result_path, err).into())
         };
 
         create_mtl()?;
-        create_obj()
+        let solid = self.make_solid()?;
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        match &self.split_opts {
+            Some(split_opts) => {
+                for (n, part) in split(&solid, split_opts)?.iter().enumerate() {
+                    let part_path = Path::new(&output_path)
+                        .join(format!("{}_part{}", planet_name, n + 1))
+                        .with_extension(self.compression.extension("obj"));
+                    create_obj(part_path, part, origin)?;
+                }
+                Ok(())
+            }
+            None => create_obj(
+                Path::new(&output_path)
+                    .join(&planet_name)
+                    .with_extension(self.compression.extension("obj")),
+                &solid,
+                origin,
+            ),
+        }
+    }
+}
+
+impl<'a> Obj<'a> {
+    /// Makes the solid written to the obj file, hollowed in the printing mode
+    fn make_solid(&self) -> Result<Solid, ErrBox> {
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        match &self.print_opts {
+            Some(print_opts) => solid.hollow(print_opts),
+            None => Ok(solid),
+        }
+    }
+}
+
+/// Returns the material properties following `newmtl` in the mtl template
+fn material_definition(template: &str) -> &str {
+    match template.find("newmtl ") {
+        Some(start) => match template[start..].find('\n') {
+            Some(end) => &template[start + end + 1..],
+            None => "",
+        },
+        None => "",
+    }
+}
+
+/// Makes texture URI of a submesh tile
+///
+/// The `{face}` placeholder in the texture URI is replaced by the submesh name,
+/// otherwise the name is appended to the file stem (`image.png` -> `image_px.png`).
+pub fn make_tile_uri(texture_uri: &str, name: &str) -> String {
+    if texture_uri.contains("{face}") {
+        return texture_uri.replace("{face}", name);
+    }
+    let stem_start = texture_uri.rfind('/').map_or(0, |i| i + 1);
+    match texture_uri[stem_start..].rfind('.') {
+        Some(i) => format!(
+            "{}_{}{}",
+            &texture_uri[..stem_start + i],
+            name,
+            &texture_uri[stem_start + i..]
+        ),
+        None => format!("{}_{}", texture_uri, name),
     }
 }
 
@@ -954,4 +1060,20 @@ mod tests {
         ];
         assert_eq!(tcs, tcs_res);
     }
+
+    #[test]
+    fn make_tile_uri_t0() {
+        assert_eq!(make_tile_uri("image.png", "px"), "image_px.png");
+        assert_eq!(
+            make_tile_uri("tiles/image-{face}.png", "nz"),
+            "tiles/image-nz.png"
+        );
+        assert_eq!(make_tile_uri("./a.b/image", "py"), "./a.b/image_py");
+    }
+
+    #[test]
+    fn material_definition_t0() {
+        let template = "# header\n\nnewmtl Material\nKd 0.8 0.8 0.8\nillum 1\n";
+        assert_eq!(material_definition(template), "Kd 0.8 0.8 0.8\nillum 1\n");
+    }
 }
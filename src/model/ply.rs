@@ -0,0 +1,369 @@
+//! # PLY Model Generation Module
+//!
+//! This module writes models as PLY (Polygon File Format) files for point cloud
+//! and mesh tools (MeshLab, CloudCompare, Open3D).
+//!
+//! Vertices have one set of attributes each (see `mesh`) with the properties:
+//!
+//! - `x`, `y`, `z`: position, placed by the same parameters as OBJ vertices (see `surface`)
+//! - `nx`, `ny`, `nz`: unit normal, if `normals` are enabled
+//! - `red`, `green`, `blue`: color of **Color** models, or `s`, `t` texture
+//!   coordinates of **Texture** models
+//! - `elevation`: elevation in metres after edits, filters and erosion, without
+//!   `scale` and relief curves
+//! - `latitude`, `longitude`: geographic coordinates in degrees
+//! - `had_data`: 1 if the elevation was sampled from source data, 0 if it was
+//!   filled with the sea level (nodata cells and missing tiles)
+//!
+//! Faces are triangles with 32-bit vertex indices.
+//!
+//! ## Configuration Parameters
+//!
+//! - `ply_format`: "binary" (default) for binary little-endian, or "ascii"
+//! - `normals`, `crease_angle`: see `normals`
+use crate::common::color::*;
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::mesh::*;
+use crate::model::normals::*;
+use crate::model::surface::*;
+use crate::model::types::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Encoding of PLY files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+impl PlyFormat {
+    /// Reads the PLY encoding from the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings.get_parameter_str("ply_format", "binary")?.as_str() {
+            "binary" => Ok(PlyFormat::BinaryLittleEndian),
+            "ascii" => Ok(PlyFormat::Ascii),
+            s => Err(format!("Unknown PLY format '{}'", s).into()),
+        }
+    }
+
+    /// Name of the format in the PLY header
+    fn name(&self) -> &'static str {
+        match self {
+            PlyFormat::Ascii => "ascii",
+            PlyFormat::BinaryLittleEndian => "binary_little_endian",
+        }
+    }
+}
+
+/// Value of a PLY property
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyValue {
+    UChar(u8),
+    UInt(u32),
+    Float(f32),
+    Double(f64),
+}
+
+/// Geographic attributes of a PLY vertex
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct VertexAttributes {
+    elevation: Height,
+    latitude: Coord,
+    longitude: Coord,
+    had_data: bool,
+}
+
+/// PLY model structure
+///
+/// This struct represents a 3D geospatial model in PLY format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct Ply<'a> {
+    model_type: ModelType,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    format: PlyFormat,
+    normal_opts: Option<NormalOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for Ply<'a> {
+    /// Checks the PLY encoding
+    fn options_check(settings: &'a Settings) -> Result<(), ErrBox> {
+        PlyFormat::from_settings(settings).map(|_| ())
+    }
+
+    /// Builds and constructs a PLY model instance
+    fn build_model(
+        model_type: ModelType,
+        _model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        Ok(Ply {
+            model_type,
+            settings,
+            surface: Surface::from_settings(settings, &components)?,
+            format: PlyFormat::from_settings(settings)?,
+            normal_opts: NormalOpts::from_settings(settings)?,
+            components,
+        })
+    }
+
+    /// Saves the model to the PLY file
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        let normals = self.normal_opts.as_ref().map(|opts| solid.normals(opts));
+        let mesh = IndexedMesh::from_solid(
+            &solid,
+            normals.as_ref(),
+            match self.model_type {
+                ModelType::Texture => None,
+                ModelType::Color => Some(self.components.get_colors()?),
+            },
+            None,
+            &self.surface.frame,
+            origin,
+        )?;
+        let vertices = self.components.get_vertices()?;
+        let attributes = mesh
+            .sources
+            .iter()
+            .map(|source| {
+                let GeoPoint { lon, lat } = vertices
+                    .get(source)
+                    .ok_or(format!("Missed geographic point {}", source))?;
+                Ok(VertexAttributes {
+                    elevation: self.components.heights.get(source).cloned().unwrap_or(0.0),
+                    latitude: *lat,
+                    longitude: *lon,
+                    had_data: self.components.sampled.contains(source),
+                })
+            })
+            .collect::<Result<Vec<_>, ErrBox>>()?;
+
+        let result_path = Path::new(&output_path)
+            .join(planet_name)
+            .with_extension("ply");
+        let file = File::create(&result_path)
+            .map_err(|err| format!("Can't create {:?}: {}", result_path, err))?;
+        let mut writer = BufWriter::new(file);
+        let comments = [
+            format!("planet {}", planet_name),
+            format!("data_source {:?}", settings.data_source),
+        ];
+        write_ply(&mut writer, self.format, &comments, &mesh, &attributes)
+            .and_then(|_| writer.flush())
+            .map_err(|err| format!("Can't write ply file {:?}: {}", result_path, err).into())
+    }
+}
+
+/// Writes the PLY header and elements of the mesh
+fn write_ply(
+    writer: &mut impl Write,
+    format: PlyFormat,
+    comments: &[String],
+    mesh: &IndexedMesh,
+    attributes: &[VertexAttributes],
+) -> std::io::Result<()> {
+    let faces: Vec<&[u32; 3]> = mesh
+        .primitives
+        .iter()
+        .flat_map(|(_, triangles)| triangles)
+        .collect();
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format {} 1.0", format.name())?;
+    for comment in comments {
+        writeln!(writer, "comment {}", comment)?;
+    }
+    writeln!(writer, "element vertex {}", mesh.positions.len())?;
+    let mut properties = vec!["float x", "float y", "float z"];
+    if !mesh.normals.is_empty() {
+        properties.extend(["float nx", "float ny", "float nz"]);
+    }
+    if !mesh.colors.is_empty() {
+        properties.extend(["uchar red", "uchar green", "uchar blue"]);
+    }
+    if !mesh.texture_coordinates.is_empty() {
+        properties.extend(["float s", "float t"]);
+    }
+    properties.extend([
+        "float elevation",
+        "double latitude",
+        "double longitude",
+        "uchar had_data",
+    ]);
+    for property in properties {
+        writeln!(writer, "property {}", property)?;
+    }
+    writeln!(writer, "element face {}", faces.len())?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    let mut values = vec![];
+    for (i, attributes) in attributes.iter().enumerate() {
+        values.clear();
+        let (x, y, z) = mesh.positions[i];
+        values.extend([x, y, z].map(|c| PlyValue::Float(c as f32)));
+        if let Some((nx, ny, nz)) = mesh.normals.get(i) {
+            values.extend([nx, ny, nz].map(|c| PlyValue::Float(*c as f32)));
+        }
+        if let Some(RGB(r, g, b)) = mesh.colors.get(i) {
+            values.extend([r, g, b].map(|c| PlyValue::UChar(to_uchar(*c))));
+        }
+        if let Some((s, t)) = mesh.texture_coordinates.get(i) {
+            values.extend([s, t].map(|c| PlyValue::Float(*c as f32)));
+        }
+        values.extend([
+            PlyValue::Float(attributes.elevation as f32),
+            PlyValue::Double(attributes.latitude),
+            PlyValue::Double(attributes.longitude),
+            PlyValue::UChar(attributes.had_data as u8),
+        ]);
+        write_values(writer, format, &values)?;
+    }
+    for face in faces {
+        values.clear();
+        values.push(PlyValue::UChar(3));
+        values.extend(face.map(PlyValue::UInt));
+        write_values(writer, format, &values)?;
+    }
+    Ok(())
+}
+
+/// Converts a color component from [0, 1] to [0, 255]
+fn to_uchar(c: ColorComponent) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Writes property values of an element
+fn write_values(
+    writer: &mut impl Write,
+    format: PlyFormat,
+    values: &[PlyValue],
+) -> std::io::Result<()> {
+    match format {
+        PlyFormat::Ascii => {
+            let line: Vec<String> = values
+                .iter()
+                .map(|value| match value {
+                    PlyValue::UChar(v) => v.to_string(),
+                    PlyValue::UInt(v) => v.to_string(),
+                    PlyValue::Float(v) => v.to_string(),
+                    PlyValue::Double(v) => v.to_string(),
+                })
+                .collect();
+            writeln!(writer, "{}", line.join(" "))
+        }
+        PlyFormat::BinaryLittleEndian => {
+            for value in values {
+                match value {
+                    PlyValue::UChar(v) => writer.write_all(&[*v])?,
+                    PlyValue::UInt(v) => writer.write_all(&v.to_le_bytes())?,
+                    PlyValue::Float(v) => writer.write_all(&v.to_le_bytes())?,
+                    PlyValue::Double(v) => writer.write_all(&v.to_le_bytes())?,
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh() -> (IndexedMesh, Vec<VertexAttributes>) {
+        let mesh = IndexedMesh {
+            positions: vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+            colors: vec![RGB(0.0, 0.5, 1.0), RGB(1.0, 0.0, 0.0), RGB(0.2, 0.4, 0.6)],
+            sources: vec![0, 1, 2],
+            primitives: vec![(None, vec![[0, 1, 2]])],
+            ..Default::default()
+        };
+        let attributes = (0..3)
+            .map(|i| VertexAttributes {
+                elevation: 100.0 * i as Height,
+                latitude: 10.0,
+                longitude: -20.5,
+                had_data: i > 0,
+            })
+            .collect();
+        (mesh, attributes)
+    }
+
+    #[test]
+    fn write_ply_ascii_t0() {
+        let (mesh, attributes) = mesh();
+        let mut bytes = vec![];
+        write_ply(
+            &mut bytes,
+            PlyFormat::Ascii,
+            &["planet test".to_string()],
+            &mesh,
+            &attributes,
+        )
+        .unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let (header, body) = text.split_once("end_header\n").unwrap();
+        assert!(header.starts_with("ply\nformat ascii 1.0\ncomment planet test\n"));
+        assert!(header.contains("element vertex 3\nproperty float x\n"));
+        assert!(header.contains("property uchar blue\nproperty float elevation\n"));
+        assert!(!header.contains("property float nx"));
+        assert!(header.ends_with("property list uchar uint vertex_indices\n"));
+        assert_eq!(
+            body.lines().collect::<Vec<_>>(),
+            vec![
+                "0 0 0 0 128 255 0 10 -20.5 0",
+                "1 0 0 255 0 0 100 10 -20.5 1",
+                "0 1 0 51 102 153 200 10 -20.5 1",
+                "3 0 1 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn write_ply_binary_t0() {
+        let (mesh, attributes) = mesh();
+        let mut bytes = vec![];
+        write_ply(
+            &mut bytes,
+            PlyFormat::BinaryLittleEndian,
+            &[],
+            &mesh,
+            &attributes,
+        )
+        .unwrap();
+        let end = b"end_header\n";
+        let start = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        // 3 floats, 3 uchars, a float, 2 doubles and a uchar per vertex
+        let vertex_size = 12 + 3 + 4 + 16 + 1;
+        assert_eq!(bytes.len(), start + 3 * vertex_size + 1 + 12);
+        let vertex = &bytes[start + vertex_size..start + 2 * vertex_size];
+        assert_eq!(f32::from_le_bytes(vertex[0..4].try_into().unwrap()), 1.0);
+        assert_eq!(&vertex[12..15], &[255, 0, 0]);
+        assert_eq!(
+            f32::from_le_bytes(vertex[15..19].try_into().unwrap()),
+            100.0
+        );
+        assert_eq!(
+            f64::from_le_bytes(vertex[27..35].try_into().unwrap()),
+            -20.5
+        );
+        assert_eq!(vertex[35], 1);
+        assert_eq!(bytes[start + 3 * vertex_size], 3);
+    }
+}
@@ -0,0 +1,647 @@
+//! # Focus Region Refinement
+//!
+//! This module refines a global tessellation around a focus region, so that a
+//! single country or area gets a detailed mesh while the rest of the planet
+//! stays coarse.
+//!
+//! ## Algorithm
+//!
+//! Triangles touching the region are refined by longest-edge bisection (Rivara):
+//! the longest edge of a triangle is split only when it is also the longest edge
+//! of the neighbouring triangle, otherwise the neighbour is refined first. Both
+//! triangles sharing an edge are always split together, which gives a conforming
+//! mesh (no T-junctions, no cracks) with a graded transition between fine and
+//! coarse triangles.
+//!
+//! New vertices are projected to the sphere and get texture coordinates of their
+//! own positions: equirectangular ones, or ones of the cube face tile for
+//! cube-sphere tessellations. Edges on texture seams get separate texture points
+//! on each side, so texture coordinates stay correct.
+//!
+//! ## Configuration Parameters
+//!
+//! - `focus_bbox`: bounding box as "lon_min lat_min lon_max lat_max" in degrees
+//! - `focus_lon`, `focus_lat`, `focus_radius`: centre in degrees and radius in km
+//! - `focus_levels`: number of times the edges inside the region are halved (default: 3)
+//! - `radius`: planet radius in metres (default: 6378000.0)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::surface::DEFAULT_RADIUS;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::collections::{BTreeMap, HashMap};
+
+const DEFAULT_FOCUS_LEVELS: usize = 3;
+
+/// Texture mapping of the refined mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UvMapping {
+    /// Equirectangular texture of the whole planet
+    Equirectangular,
+    /// Cube face tiles of the submeshes, with optional equal-angle projection
+    CubeFaces(bool),
+}
+
+/// Focus region on the planet surface
+#[derive(Debug, Clone, PartialEq)]
+pub enum FocusRegion {
+    /// Bounding box (lon_min, lat_min, lon_max, lat_max) in degrees,
+    /// lon_min > lon_max for boxes crossing the antimeridian
+    BBox(Coord, Coord, Coord, Coord),
+    /// Centre (lon, lat) in degrees and radius in radians
+    Circle(Coord, Coord, Coord),
+}
+
+/// Options of the focus region refinement
+#[derive(Debug, Clone, PartialEq)]
+pub struct FocusOpts {
+    /// Region to refine
+    pub region: FocusRegion,
+    /// Number of times the edges inside the region are halved
+    pub levels: usize,
+}
+
+impl FocusOpts {
+    /// Reads focus region options from the settings
+    ///
+    /// Returns `None` if no focus region is given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let levels = settings.get_parameter_num("focus_levels", DEFAULT_FOCUS_LEVELS)?;
+        let bbox = settings.get_parameter_str("focus_bbox", "")?;
+        if !bbox.is_empty() {
+            let values = bbox
+                .split_whitespace()
+                .map(|s| s.parse::<Coord>())
+                .collect::<Result<Vec<Coord>, _>>()
+                .map_err(|err| format!("Invalid 'focus_bbox' parameter: {}", err))?;
+            let [lon_min, lat_min, lon_max, lat_max] = values[..] else {
+                return Err(
+                    "'focus_bbox' parameter must be 'lon_min lat_min lon_max lat_max'".into(),
+                );
+            };
+            let region = FocusRegion::BBox(lon_min, lat_min, lon_max, lat_max);
+            region.validate()?;
+            return Ok(Some(FocusOpts { region, levels }));
+        }
+        let focus_radius = settings.get_parameter_num("focus_radius", 0.0)?;
+        if focus_radius > 0.0 {
+            let radius = settings.get_parameter_num("radius", DEFAULT_RADIUS)?;
+            let region = FocusRegion::Circle(
+                settings.get_parameter_num("focus_lon", 0.0)?,
+                settings.get_parameter_num("focus_lat", 0.0)?,
+                1000.0 * focus_radius / radius,
+            );
+            region.validate()?;
+            return Ok(Some(FocusOpts { region, levels }));
+        }
+        Ok(None)
+    }
+}
+
+/// Angle between two unit vectors in radians
+fn angle(a: Point3d, b: Point3d) -> Coord {
+    2.0 * (length(sub(a, b)) / 2.0).min(1.0).asin()
+}
+
+impl FocusRegion {
+    /// Checks that coordinates are finite and within their ranges
+    ///
+    /// Longitudes of a bounding box may be inverted (crossing the antimeridian),
+    /// latitudes may not.
+    fn validate(&self) -> Result<(), ErrBox> {
+        let (lons, lats, name) = match *self {
+            FocusRegion::BBox(lon_min, lat_min, lon_max, lat_max) => {
+                if lat_min > lat_max {
+                    return Err(format!(
+                        "'focus_bbox' lat_min {} is greater than lat_max {}",
+                        lat_min, lat_max
+                    )
+                    .into());
+                }
+                (vec![lon_min, lon_max], vec![lat_min, lat_max], "focus_bbox")
+            }
+            FocusRegion::Circle(lon, lat, radius) => {
+                if !radius.is_finite() {
+                    return Err(format!("Invalid 'focus_radius' parameter: {}", radius).into());
+                }
+                (vec![lon], vec![lat], "focus_lon/focus_lat")
+            }
+        };
+        if lons.iter().any(|lon| !(-180.0..=180.0).contains(lon)) {
+            return Err(format!("'{}' longitudes {:?} aren't in -180..180", name, lons).into());
+        }
+        if lats.iter().any(|lat| !(-90.0..=90.0).contains(lat)) {
+            return Err(format!("'{}' latitudes {:?} aren't in -90..90", name, lats).into());
+        }
+        Ok(())
+    }
+
+    /// Angular distance from a point to the region, zero inside of the region
+    fn distance(&self, p: Point3d) -> Coord {
+        match *self {
+            FocusRegion::Circle(lon, lat, radius) => {
+                (angle(p, geo_to_point3d(lon, lat)) - radius).max(0.0)
+            }
+            FocusRegion::BBox(lon_min, lat_min, lon_max, lat_max) => {
+                let gp = point3d_to_geo(p);
+                let lat = gp.lat.clamp(lat_min, lat_max);
+                let in_lon = if lon_min <= lon_max {
+                    gp.lon >= lon_min && gp.lon <= lon_max
+                } else {
+                    gp.lon >= lon_min || gp.lon <= lon_max
+                };
+                let lon = if in_lon {
+                    gp.lon
+                } else {
+                    let to_min = (gp.lon - lon_min)
+                        .rem_euclid(360.0)
+                        .min((lon_min - gp.lon).rem_euclid(360.0));
+                    let to_max = (gp.lon - lon_max)
+                        .rem_euclid(360.0)
+                        .min((lon_max - gp.lon).rem_euclid(360.0));
+                    if to_min < to_max { lon_min } else { lon_max }
+                };
+                angle(p, geo_to_point3d(lon, lat))
+            }
+        }
+    }
+}
+
+/// Mesh being refined, with triangles given by texture points
+struct Refiner {
+    positions: Vec<Point3d>,
+    vertices: Vec<GeoPoint>,
+    texture_coordinates: TextureCoordinates,
+    texture_vertex: Vec<GeoPointIndex>,
+    uv_mapping: UvMapping,
+    /// Cube face indices of the submeshes (cube face tiles only)
+    submesh_faces: Vec<usize>,
+    triangles: Vec<[GeoPointIndex; 3]>,
+    submesh: Vec<usize>,
+    alive: Vec<bool>,
+    edge_triangles: HashMap<(GeoPointIndex, GeoPointIndex), Vec<usize>>,
+    vertex_midpoints: HashMap<(GeoPointIndex, GeoPointIndex), GeoPointIndex>,
+    texture_midpoints: HashMap<(GeoPointIndex, GeoPointIndex), GeoPointIndex>,
+}
+
+/// Edge key independent of the direction
+fn edge_key(a: GeoPointIndex, b: GeoPointIndex) -> (GeoPointIndex, GeoPointIndex) {
+    (a.min(b), a.max(b))
+}
+
+impl Refiner {
+    fn vertex(&self, t: GeoPointIndex) -> GeoPointIndex {
+        self.texture_vertex[t]
+    }
+
+    fn edge_length(&self, (a, b): (GeoPointIndex, GeoPointIndex)) -> Coord {
+        angle(self.positions[a], self.positions[b])
+    }
+
+    /// Longest edge of a triangle (ties are broken by vertex indices)
+    fn longest_edge(&self, tri: usize) -> (GeoPointIndex, GeoPointIndex) {
+        let [t0, t1, t2] = self.triangles[tri];
+        let (v0, v1, v2) = (self.vertex(t0), self.vertex(t1), self.vertex(t2));
+        [edge_key(v0, v1), edge_key(v1, v2), edge_key(v2, v0)]
+            .into_iter()
+            .max_by(|a, b| {
+                self.edge_length(*a)
+                    .total_cmp(&self.edge_length(*b))
+                    .then_with(|| a.cmp(b))
+            })
+            .unwrap()
+    }
+
+    fn add_triangle(&mut self, triangle: [GeoPointIndex; 3], submesh: usize) {
+        let tri = self.triangles.len();
+        for c in 0..3 {
+            let key = edge_key(self.vertex(triangle[c]), self.vertex(triangle[(c + 1) % 3]));
+            self.edge_triangles.entry(key).or_default().push(tri);
+        }
+        self.triangles.push(triangle);
+        self.submesh.push(submesh);
+        self.alive.push(true);
+    }
+
+    fn remove_triangle(&mut self, tri: usize) {
+        self.alive[tri] = false;
+        let triangle = self.triangles[tri];
+        for c in 0..3 {
+            let key = edge_key(self.vertex(triangle[c]), self.vertex(triangle[(c + 1) % 3]));
+            if let Some(tris) = self.edge_triangles.get_mut(&key) {
+                tris.retain(|t| *t != tri);
+            }
+        }
+    }
+
+    /// Texture coordinates of the vertex `m` splitting the texture edge (ta, tb)
+    fn midpoint_uv(
+        &self,
+        (ta, tb): (GeoPointIndex, GeoPointIndex),
+        m: GeoPointIndex,
+        submesh: usize,
+    ) -> (TextureCoordinate, TextureCoordinate) {
+        match self.uv_mapping {
+            UvMapping::CubeFaces(equal_angle) => {
+                cube_face_uv(self.submesh_faces[submesh], self.positions[m], equal_angle)
+            }
+            UvMapping::Equirectangular => {
+                let gp = &self.vertices[m];
+                let (u, v) = equirectangular_uv(gp);
+                // `u` of the poles is undefined, the other ends give the side of the seam
+                let us: Vec<TextureCoordinate> = [ta, tb]
+                    .into_iter()
+                    .filter(|t| !is_pole(&self.vertices[self.vertex(*t)]))
+                    .map(|t| self.texture_coordinates[t].0)
+                    .collect();
+                if us.is_empty() {
+                    return (u, v);
+                }
+                let u_side = us.iter().sum::<TextureCoordinate>() / us.len() as TextureCoordinate;
+                if is_pole(gp) {
+                    (u_side, v)
+                } else {
+                    (u + (u_side - u).round(), v)
+                }
+            }
+        }
+    }
+
+    /// Splits an edge in its middle together with the triangles sharing it
+    fn split_edge(&mut self, (a, b): (GeoPointIndex, GeoPointIndex)) {
+        let m = match self.vertex_midpoints.get(&(a, b)) {
+            Some(m) => *m,
+            None => {
+                let (pa, pb) = (self.positions[a], self.positions[b]);
+                let p = normalize(add(pa, pb));
+                self.positions.push(p);
+                self.vertices.push(point3d_to_geo(p));
+                self.vertex_midpoints
+                    .insert((a, b), self.positions.len() - 1);
+                self.positions.len() - 1
+            }
+        };
+        let tris = self
+            .edge_triangles
+            .get(&(a, b))
+            .cloned()
+            .unwrap_or_default();
+        for tri in tris {
+            let triangle = self.triangles[tri];
+            let Some(c) = (0..3).find(|c| {
+                edge_key(
+                    self.vertex(triangle[*c]),
+                    self.vertex(triangle[(c + 1) % 3]),
+                ) == (a, b)
+            }) else {
+                continue;
+            };
+            let (ta, tb, tc) = (triangle[c], triangle[(c + 1) % 3], triangle[(c + 2) % 3]);
+            let submesh = self.submesh[tri];
+            let tm = match self.texture_midpoints.get(&edge_key(ta, tb)) {
+                Some(tm) => *tm,
+                None => {
+                    let uv = self.midpoint_uv((ta, tb), m, submesh);
+                    self.texture_coordinates.push(uv);
+                    self.texture_vertex.push(m);
+                    let tm = self.texture_coordinates.len() - 1;
+                    self.texture_midpoints.insert(edge_key(ta, tb), tm);
+                    tm
+                }
+            };
+            self.remove_triangle(tri);
+            self.add_triangle([ta, tm, tc], submesh);
+            self.add_triangle([tm, tb, tc], submesh);
+        }
+    }
+
+    /// Bisects the triangle, refining neighbours along its longest edge propagation path
+    fn bisect(&mut self, tri: usize) {
+        while self.alive[tri] {
+            let mut t = tri;
+            loop {
+                let edge = self.longest_edge(t);
+                let neighbour = self.edge_triangles[&edge]
+                    .iter()
+                    .find(|n| **n != t)
+                    .cloned();
+                match neighbour {
+                    Some(n) if self.longest_edge(n) != edge => t = n,
+                    _ => {
+                        self.split_edge(edge);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks whether the triangle touches the region and is longer than the target length
+    fn needs_refinement(&self, tri: usize, region: &FocusRegion, target: Coord) -> bool {
+        let ps = self.triangles[tri].map(|t| self.positions[self.vertex(t)]);
+        let centroid = normalize(add(add(ps[0], ps[1]), ps[2]));
+        let size = ps.iter().map(|p| angle(centroid, *p)).fold(0.0, Coord::max);
+        self.edge_length(self.longest_edge(tri)) > target && region.distance(centroid) <= size
+    }
+}
+
+/// Refines the tessellation around the focus region
+///
+/// Triangles touching the region are bisected until their edges get `2^levels`
+/// times shorter than the mean edge of the original mesh. The tessellation gives
+/// the texture mapping of new vertices.
+pub fn refine_focus(
+    data: TessellationData,
+    tessellation: &Tessellation,
+    opts: &FocusOpts,
+) -> Result<TessellationData, ErrBox> {
+    let TessellationData {
+        modeldata: ModelData(vertices, faces, texture_mapping),
+        texture_coordinates,
+        submeshes,
+    } = data;
+    let pmap = texture_mapping.ok_or("Focus region requires a triangulated model")?;
+    if faces.is_empty() {
+        return Err("Focus region requires a triangulated model".into());
+    }
+    if vertices.keys().enumerate().any(|(i, k)| i != *k) {
+        return Err("Model vertices must have contiguous indices for refinement".into());
+    }
+
+    let submesh_names: Vec<String> = submeshes
+        .iter()
+        .flatten()
+        .map(|(name, _)| name.clone())
+        .collect();
+    let (uv_mapping, submesh_faces) = match tessellation {
+        Tessellation::CubeSphere(_, equal_angle) => (
+            UvMapping::CubeFaces(*equal_angle),
+            submesh_names
+                .iter()
+                .map(|name| {
+                    CUBE_FACE_NAMES
+                        .iter()
+                        .position(|face| face == name)
+                        .ok_or(format!("Unknown cube face '{}'", name))
+                })
+                .collect::<Result<Vec<usize>, _>>()?,
+        ),
+        _ => (UvMapping::Equirectangular, vec![]),
+    };
+
+    let mut texture_vertex = vec![0; texture_coordinates.len()];
+    for (t, v) in &pmap {
+        *texture_vertex
+            .get_mut(*t)
+            .ok_or(format!("Missed texture coordinates for point {}", t))? = *v;
+    }
+    let mut refiner = Refiner {
+        positions: vertices
+            .values()
+            .map(|gp| geo_to_point3d(gp.lon, gp.lat))
+            .collect(),
+        vertices: vertices.into_values().collect(),
+        texture_coordinates,
+        texture_vertex,
+        uv_mapping,
+        submesh_faces,
+        triangles: Vec::with_capacity(faces.len()),
+        submesh: Vec::with_capacity(faces.len()),
+        alive: Vec::with_capacity(faces.len()),
+        edge_triangles: HashMap::new(),
+        vertex_midpoints: HashMap::new(),
+        texture_midpoints: HashMap::new(),
+    };
+    for (f, (t0, t1, t2)) in faces.iter().enumerate() {
+        let submesh = submeshes
+            .iter()
+            .flatten()
+            .position(|(_, range)| range.contains(&f))
+            .unwrap_or(0);
+        refiner.add_triangle([*t0, *t1, *t2], submesh);
+    }
+
+    let mean_edge = refiner
+        .edge_triangles
+        .keys()
+        .map(|e| refiner.edge_length(*e))
+        .sum::<Coord>()
+        / refiner.edge_triangles.len() as Coord;
+    let target = mean_edge / 2f64.powi(opts.levels as i32);
+    loop {
+        let candidates: Vec<usize> = (0..refiner.triangles.len())
+            .filter(|tri| {
+                refiner.alive[*tri] && refiner.needs_refinement(*tri, &opts.region, target)
+            })
+            .collect();
+        if candidates.is_empty() {
+            break;
+        }
+        for tri in candidates {
+            refiner.bisect(tri);
+        }
+    }
+
+    // faces are grouped by submeshes in the original order
+    let mut alive_triangles: Vec<usize> = (0..refiner.triangles.len())
+        .filter(|tri| refiner.alive[*tri])
+        .collect();
+    alive_triangles.sort_by_key(|tri| refiner.submesh[*tri]);
+    let faces: Faces = alive_triangles
+        .iter()
+        .map(|tri| {
+            let [t0, t1, t2] = refiner.triangles[*tri];
+            (t0, t1, t2)
+        })
+        .collect();
+    let submeshes = submeshes.map(|_| {
+        submesh_names
+            .iter()
+            .enumerate()
+            .map(|(s, name)| {
+                let start = alive_triangles.partition_point(|tri| refiner.submesh[*tri] < s);
+                let end = alive_triangles.partition_point(|tri| refiner.submesh[*tri] <= s);
+                (name.clone(), start..end)
+            })
+            .collect()
+    });
+
+    let texture_mapping: PointsMapping =
+        refiner.texture_vertex.iter().cloned().enumerate().collect();
+    let vertices: Vertices = refiner
+        .vertices
+        .into_iter()
+        .enumerate()
+        .collect::<BTreeMap<_, _>>();
+
+    Ok(TessellationData {
+        modeldata: ModelData::create(vertices, faces, Some(texture_mapping)),
+        texture_coordinates: refiner.texture_coordinates,
+        submeshes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing::*;
+
+    /// Mean length of edges of faces with centroids near the point
+    fn mean_edge_near(data: &TessellationData, lon: Coord, lat: Coord, max_angle: Coord) -> Coord {
+        let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+        let pmap = texture_mapping.as_ref().unwrap();
+        let p = geo_to_point3d(lon, lat);
+        let mut lengths = vec![];
+        for (t0, t1, t2) in faces {
+            let ps = [t0, t1, t2].map(|t| {
+                let gp = &vertices[&pmap[t]];
+                geo_to_point3d(gp.lon, gp.lat)
+            });
+            if ps.iter().all(|q| angle(p, *q) < max_angle) {
+                lengths.push(angle(ps[0], ps[1]));
+            }
+        }
+        lengths.iter().sum::<Coord>() / lengths.len() as Coord
+    }
+
+    #[test]
+    fn refine_focus_circle() {
+        let data = create_icosphere(2);
+        let num_faces = data.modeldata.1.len();
+        let opts = FocusOpts {
+            region: FocusRegion::Circle(10.0, 50.0, 0.05),
+            levels: 3,
+        };
+        let refined = refine_focus(data, &Tessellation::Icosphere(2), &opts).unwrap();
+        assert!(refined.modeldata.1.len() > num_faces);
+        let ModelData(_, faces, texture_mapping) = &refined.modeldata;
+        assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+        let near = mean_edge_near(&refined, 10.0, 50.0, 0.1);
+        let far = mean_edge_near(&refined, -170.0, -50.0, 0.5);
+        assert!(far > 4.0 * near);
+        let ModelData(_, _, texture_mapping) = &refined.modeldata;
+        assert_eq!(
+            texture_mapping.as_ref().unwrap().len(),
+            refined.texture_coordinates.len()
+        );
+    }
+
+    #[test]
+    fn refine_focus_bbox_on_seam() {
+        let data = create_icosphere(2);
+        let opts = FocusOpts {
+            region: FocusRegion::BBox(170.0, -10.0, -170.0, 10.0),
+            levels: 2,
+        };
+        let refined = refine_focus(data, &Tessellation::Icosphere(2), &opts).unwrap();
+        let ModelData(_, faces, texture_mapping) = &refined.modeldata;
+        assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+        let ModelData(_, faces, _) = &refined.modeldata;
+        let tcs = &refined.texture_coordinates;
+        for (t0, t1, t2) in faces {
+            let us = [tcs[*t0].0, tcs[*t1].0, tcs[*t2].0];
+            let u_min = us.iter().cloned().fold(f64::INFINITY, f64::min);
+            let u_max = us.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            assert!(u_max - u_min < 0.5);
+        }
+    }
+
+    #[test]
+    fn refine_focus_cubesphere() {
+        let data = create_cubesphere(2, false);
+        let opts = FocusOpts {
+            region: FocusRegion::Circle(0.0, 0.0, 0.2),
+            levels: 2,
+        };
+        let refined = refine_focus(data, &Tessellation::CubeSphere(2, false), &opts).unwrap();
+        let ModelData(_, faces, texture_mapping) = &refined.modeldata;
+        assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+        let submeshes = refined.submeshes.unwrap();
+        assert_eq!(submeshes.len(), 6);
+        assert_eq!(submeshes[5].1.end, refined.modeldata.1.len());
+    }
+
+    #[test]
+    fn refine_focus_texture_coordinates() {
+        // texture coordinates of all texture points match their positions
+        let check = |refined: &TessellationData,
+                     uv: &dyn Fn(usize, &GeoPoint) -> (Coord, Coord)| {
+            let ModelData(vertices, faces, texture_mapping) = &refined.modeldata;
+            let pmap = texture_mapping.as_ref().unwrap();
+            let face_submesh = |f: usize| {
+                refined
+                    .submeshes
+                    .iter()
+                    .flatten()
+                    .position(|(_, range)| range.contains(&f))
+                    .unwrap_or(0)
+            };
+            for (f, (t0, t1, t2)) in faces.iter().enumerate() {
+                for t in [t0, t1, t2] {
+                    let gp = &vertices[&pmap[t]];
+                    let (u, v) = uv(face_submesh(f), gp);
+                    let (tu, tv) = refined.texture_coordinates[*t];
+                    assert!((tv - v).abs() < 1e-9, "{:?}: v {} != {}", gp, tv, v);
+                    // u of the seam points may differ by a whole turn
+                    let du = tu - u;
+                    if !is_pole(gp) {
+                        assert!(
+                            (du - du.round()).abs() < 1e-9,
+                            "{:?}: u {} != {}",
+                            gp,
+                            tu,
+                            u
+                        );
+                    }
+                }
+            }
+        };
+
+        for region in [
+            FocusRegion::Circle(10.0, 40.0, 0.1),
+            FocusRegion::BBox(170.0, 60.0, -170.0, 90.0),
+        ] {
+            let opts = FocusOpts { region, levels: 3 };
+            let tessellation = Tessellation::Icosphere(2);
+            let refined = refine_focus(create_icosphere(2), &tessellation, &opts).unwrap();
+            check(&refined, &|_, gp| equirectangular_uv(gp));
+        }
+
+        let tessellation = Tessellation::CubeSphere(2, true);
+        let opts = FocusOpts {
+            region: FocusRegion::Circle(30.0, 20.0, 0.3),
+            levels: 3,
+        };
+        let refined = refine_focus(create_cubesphere(2, true), &tessellation, &opts).unwrap();
+        check(&refined, &|face, gp| {
+            cube_face_uv(face, geo_to_point3d(gp.lon, gp.lat), true)
+        });
+    }
+
+    #[test]
+    fn focus_region_validate_t0() {
+        assert!(
+            FocusRegion::BBox(170.0, -10.0, -170.0, 10.0)
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            FocusRegion::BBox(0.0, 10.0, 20.0, -10.0)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            FocusRegion::BBox(0.0, Coord::NAN, 20.0, 10.0)
+                .validate()
+                .is_err()
+        );
+        assert!(FocusRegion::BBox(0.0, 0.0, 200.0, 10.0).validate().is_err());
+        assert!(FocusRegion::Circle(10.0, 50.0, 0.05).validate().is_ok());
+        assert!(
+            FocusRegion::Circle(10.0, 50.0, Coord::INFINITY)
+                .validate()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! # Relief Curves
+//!
+//! This module maps elevations through nonlinear transfer functions before they
+//! are exaggerated by `scale`. With a strong linear exaggeration high mountains
+//! look absurd while lowlands stay flat; a compressing curve lifts the lowlands
+//! and tames the peaks.
+//!
+//! Curves work on the distance from sea level, so elevations below sea level are
+//! mapped by their own curve and stay negative.
+//!
+//! ## Configuration Parameters
+//!
+//! - `relief_curve`: "linear" (default), "power", "log" or "file"
+//! - `relief_gamma`: positive exponent of the "power" curve (default: 0.5)
+//! - `relief_reference`: elevation in metres kept unchanged by "power" and "log" curves (default: 1000.0)
+//! - `relief_curve_file`: file of "elevation mapped_elevation" lines in metres for the "file" curve
+//!
+//! The same parameters with the `_below` suffix (`relief_curve_below`, ...) set
+//! the curve below sea level. Each of them defaults to its counterpart above sea level.
+use crate::common::settings::*;
+use crate::common::types::*;
+use std::fs;
+
+const DEFAULT_GAMMA: f64 = 0.5;
+const DEFAULT_REFERENCE: f64 = 1000.0;
+
+/// Transfer function of elevations
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReliefCurve {
+    /// Elevations are unchanged
+    Linear,
+    /// `reference * (h / reference)^gamma`
+    Power { gamma: Height, reference: Height },
+    /// `reference * ln(1 + h / reference) / ln 2`
+    Log { reference: Height },
+    /// Piecewise-linear curve through sorted points, extended by the end segments
+    Piecewise(Vec<(Height, Height)>),
+}
+
+impl ReliefCurve {
+    /// Reads a curve from parameters with the given suffix
+    ///
+    /// Missed suffixed parameters default to the parameters without the suffix.
+    fn from_settings(settings: &Settings, suffix: &str) -> Result<Self, ErrBox> {
+        let num = |name: &str, default: Height| -> Result<Height, ErrBox> {
+            let default = settings.get_parameter_num(name, default)?;
+            settings.get_parameter_num(&format!("{}{}", name, suffix), default)
+        };
+        let str = |name: &str, default: &str| -> Result<String, ErrBox> {
+            let default = settings.get_parameter_str(name, default)?;
+            settings.get_parameter_str(&format!("{}{}", name, suffix), &default)
+        };
+        let reference = num("relief_reference", DEFAULT_REFERENCE)?;
+        if reference <= 0.0 {
+            return Err("Relief reference must be positive".into());
+        }
+        match str("relief_curve", "linear")?.as_str() {
+            "linear" => Ok(ReliefCurve::Linear),
+            "power" => {
+                let gamma = num("relief_gamma", DEFAULT_GAMMA)?;
+                if !(gamma > 0.0 && gamma.is_finite()) {
+                    return Err("Relief gamma must be positive and finite".into());
+                }
+                Ok(ReliefCurve::Power { gamma, reference })
+            }
+            "log" => Ok(ReliefCurve::Log { reference }),
+            "file" => {
+                let path = str("relief_curve_file", "")?;
+                let text = fs::read_to_string(&path)
+                    .map_err(|err| format!("Can't read relief curve file '{}': {}", path, err))?;
+                Self::parse_points(&text)
+                    .map_err(|err| format!("Invalid relief curve file '{}': {}", path, err).into())
+            }
+            s => Err(format!("Unknown relief curve '{}'", s).into()),
+        }
+    }
+
+    /// Parses a piecewise-linear curve from lines of "elevation mapped_elevation"
+    ///
+    /// Empty lines and lines starting with '#' are skipped.
+    pub fn parse_points(text: &str) -> Result<Self, String> {
+        let mut points = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|s| s.parse::<Height>())
+                .collect::<Result<Vec<Height>, _>>()
+                .map_err(|err| format!("{} in line '{}'", err, line))?;
+            let [h, mapped] = values[..] else {
+                return Err(format!("line '{}' must have two values", line));
+            };
+            points.push((h, mapped));
+        }
+        if points.len() < 2 {
+            return Err("at least two points are needed".to_string());
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if points.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err("elevations of points must be distinct".to_string());
+        }
+        Ok(ReliefCurve::Piecewise(points))
+    }
+
+    /// Maps a non-negative distance from sea level
+    fn map(&self, h: Height) -> Height {
+        match self {
+            ReliefCurve::Linear => h,
+            ReliefCurve::Power { gamma, reference } => reference * (h / reference).powf(*gamma),
+            ReliefCurve::Log { reference } => {
+                reference * (h / reference).ln_1p() / std::f64::consts::LN_2
+            }
+            ReliefCurve::Piecewise(points) => {
+                let k = points
+                    .partition_point(|p| p.0 < h)
+                    .clamp(1, points.len() - 1);
+                let (h0, m0) = points[k - 1];
+                let (h1, m1) = points[k];
+                m0 + (m1 - m0) * (h - h0) / (h1 - h0)
+            }
+        }
+    }
+}
+
+/// Relief curves above and below sea level
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReliefOpts {
+    /// Curve above sea level
+    pub above: ReliefCurve,
+    /// Curve below sea level, applied to depths
+    pub below: ReliefCurve,
+}
+
+impl Default for ReliefOpts {
+    fn default() -> Self {
+        ReliefOpts {
+            above: ReliefCurve::Linear,
+            below: ReliefCurve::Linear,
+        }
+    }
+}
+
+impl ReliefOpts {
+    /// Reads relief curves from the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        Ok(ReliefOpts {
+            above: ReliefCurve::from_settings(settings, "")?,
+            below: ReliefCurve::from_settings(settings, "_below")?,
+        })
+    }
+
+    /// Maps an elevation in metres
+    pub fn apply(&self, h: Height) -> Height {
+        if h >= 0.0 {
+            self.above.map(h)
+        } else {
+            -self.below.map(-h)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_t0() {
+        let relief = ReliefOpts {
+            above: ReliefCurve::Power {
+                gamma: 0.5,
+                reference: 1000.0,
+            },
+            below: ReliefCurve::Log { reference: 1000.0 },
+        };
+        assert_eq!(relief.apply(0.0), 0.0);
+        assert!((relief.apply(1000.0) - 1000.0).abs() < 1e-9);
+        assert!((relief.apply(9000.0) - 3000.0).abs() < 1e-9);
+        assert!((relief.apply(-1000.0) + 1000.0).abs() < 1e-9);
+        assert!((relief.apply(-3000.0) + 2000.0).abs() < 1e-9);
+        assert_eq!(ReliefOpts::default().apply(-123.0), -123.0);
+    }
+
+    #[test]
+    fn parse_points_t0() {
+        let curve = ReliefCurve::parse_points("# curve\n0 0\n\n4000 2000\n1000 1000\n").unwrap();
+        assert_eq!(curve.map(500.0), 500.0);
+        assert_eq!(curve.map(2500.0), 1500.0);
+        assert_eq!(curve.map(7000.0), 3000.0);
+        assert!(ReliefCurve::parse_points("0 0").is_err());
+        assert!(ReliefCurve::parse_points("0 0\n1 x").is_err());
+        assert!(ReliefCurve::parse_points("0 0\n0 1").is_err());
+    }
+}
@@ -0,0 +1,600 @@
+//! # Mesh Simplification
+//!
+//! This module reduces the number of triangles of a model after elevations are
+//! sampled. Flat areas (oceans, plains) are collapsed to a few large triangles,
+//! while rough terrain keeps its detail.
+//!
+//! ## Algorithm
+//!
+//! Quadric error metrics (Garland-Heckbert) with half-edge collapses: a vertex
+//! is merged into one of its neighbours, which keeps its geographic position,
+//! elevation, color and texture coordinates. Every vertex accumulates the planes
+//! of its faces weighted by their areas, and the collapse with the smallest mean
+//! squared distance to these planes is taken first. Distances are measured in
+//! metres on the real (not exaggerated) surface of radius `radius`.
+//!
+//! ## Preserved Features
+//!
+//! - Vertices with several texture points (texture seams, cube-sphere face
+//!   boundaries) and the poles are never moved
+//! - Collapses that would make the mesh non-manifold or flip a face are rejected,
+//!   so the mesh stays closed
+//!
+//! ## Configuration Parameters
+//!
+//! - `simplify_target_faces`: number of faces to stop at
+//! - `simplify_max_error`: quadric error threshold in metres, the root mean square
+//!   distance of a merged vertex to the planes of the original faces around it.
+//!   It's an approximate error, not a bound of the distance to the original surface
+//! - `radius`: planet radius in metres (default: 6378000.0)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::surface::DEFAULT_RADIUS;
+use crate::model::tessellation::{Point3d, geo_to_point3d};
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+/// Tolerance used for pole detection, in degrees
+const POLE_EPSILON: Coord = 1e-9;
+
+/// Options of the simplification pass
+#[derive(Debug, PartialEq)]
+pub struct SimplifyOpts {
+    /// Number of faces to stop at
+    pub target_faces: Option<usize>,
+    /// Quadric error threshold in metres (root mean square distance to the planes)
+    pub max_error: Option<Height>,
+    /// Planet radius in metres
+    pub radius: Height,
+}
+
+impl SimplifyOpts {
+    /// Reads simplification options from the settings
+    ///
+    /// Returns `None` if neither a target number of faces nor a maximum error is given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let target_faces = settings.get_parameter_num("simplify_target_faces", 0usize)?;
+        let max_error = settings.get_parameter_num("simplify_max_error", 0.0)?;
+        if target_faces == 0 && max_error <= 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(SimplifyOpts {
+            target_faces: (target_faces != 0).then_some(target_faces),
+            max_error: (max_error > 0.0).then_some(max_error),
+            radius: settings.get_parameter_num("radius", DEFAULT_RADIUS)?,
+        }))
+    }
+}
+
+/// Symmetric 4x4 matrix of a quadric error function with the total weight of its planes
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10], f64);
+
+impl Quadric {
+    /// Quadric of squared distance to the plane `n * x + d = 0` with the given weight
+    fn from_plane(n: Point3d, d: f64, weight: f64) -> Self {
+        let (a, b, c) = scaled(n, weight.sqrt());
+        let d = d * weight.sqrt();
+        Quadric(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ],
+            weight,
+        )
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut q = self.0;
+        for (x, y) in q.iter_mut().zip(other.0.iter()) {
+            *x += y;
+        }
+        Quadric(q, self.1 + other.1)
+    }
+
+    /// Weighted mean of squared distances from the point to the planes
+    fn eval(&self, (x, y, z): Point3d) -> f64 {
+        if self.1 <= 0.0 {
+            return 0.0;
+        }
+        let q = &self.0;
+        let e = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        e.max(0.0) / self.1
+    }
+}
+
+/// Half-edge collapse of vertex `from` into vertex `to`
+#[derive(Debug, PartialEq)]
+struct Candidate {
+    cost: f64,
+    from: GeoPointIndex,
+    to: GeoPointIndex,
+    stamps: (usize, usize),
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    /// Reversed order, so that `BinaryHeap` pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.from.cmp(&self.from))
+            .then_with(|| other.to.cmp(&self.to))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Non-normalized normal of a triangle
+fn triangle_normal(positions: &[Point3d], [a, b, c]: [GeoPointIndex; 3]) -> Point3d {
+    cross(
+        sub(positions[b], positions[a]),
+        sub(positions[c], positions[a]),
+    )
+}
+
+/// Mesh being simplified, with vertices and texture points of every face
+struct Decimator<'a> {
+    positions: &'a [Point3d],
+    face_vertices: Vec<[GeoPointIndex; 3]>,
+    face_texture_points: Vec<[GeoPointIndex; 3]>,
+    face_alive: Vec<bool>,
+    vertex_faces: Vec<Vec<usize>>,
+    vertex_alive: Vec<bool>,
+    texture_points: Vec<Vec<GeoPointIndex>>,
+    locked: Vec<bool>,
+    quadrics: Vec<Quadric>,
+    stamps: Vec<usize>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Decimator<'_> {
+    /// Vertices sharing a face with the vertex
+    fn neighbours(&self, v: GeoPointIndex) -> Vec<GeoPointIndex> {
+        let mut ns: Vec<GeoPointIndex> = self.vertex_faces[v]
+            .iter()
+            .flat_map(|f| self.face_vertices[*f])
+            .filter(|w| *w != v)
+            .collect();
+        ns.sort_unstable();
+        ns.dedup();
+        ns
+    }
+
+    fn push_candidate(&mut self, from: GeoPointIndex, to: GeoPointIndex) {
+        if self.locked[from] || self.texture_points[to].len() != 1 {
+            return;
+        }
+        let cost = self.quadrics[from]
+            .add(&self.quadrics[to])
+            .eval(self.positions[to]);
+        self.heap.push(Candidate {
+            cost,
+            from,
+            to,
+            stamps: (self.stamps[from], self.stamps[to]),
+        });
+    }
+
+    fn push_candidates(&mut self, v: GeoPointIndex) {
+        for w in self.neighbours(v) {
+            self.push_candidate(v, w);
+            self.push_candidate(w, v);
+        }
+    }
+
+    /// Checks that the collapse keeps the mesh manifold and doesn't flip faces
+    fn is_valid(&self, from: GeoPointIndex, to: GeoPointIndex) -> bool {
+        let shared: Vec<usize> = self.vertex_faces[from]
+            .iter()
+            .filter(|f| self.face_vertices[**f].contains(&to))
+            .cloned()
+            .collect();
+        if shared.len() != 2 {
+            return false;
+        }
+        let ns_to = self.neighbours(to);
+        let common = self
+            .neighbours(from)
+            .into_iter()
+            .filter(|w| ns_to.binary_search(w).is_ok())
+            .count();
+        if common != 2 {
+            return false;
+        }
+        self.vertex_faces[from]
+            .iter()
+            .filter(|f| !shared.contains(f))
+            .all(|f| {
+                let old = self.face_vertices[*f];
+                let new = old.map(|w| if w == from { to } else { w });
+                let n_old = triangle_normal(self.positions, old);
+                let n_new = triangle_normal(self.positions, new);
+                let centroid = new
+                    .iter()
+                    .fold((0.0, 0.0, 0.0), |c, w| add(c, self.positions[*w]));
+                dot(n_old, n_new) > 0.0 && dot(n_new, centroid) > 0.0
+            })
+    }
+
+    /// Merges vertex `from` into vertex `to`, returns the number of removed faces
+    fn collapse(&mut self, from: GeoPointIndex, to: GeoPointIndex) -> usize {
+        let t_from = self.texture_points[from][0];
+        let t_to = self.texture_points[to][0];
+        let mut removed = 0;
+        for f in std::mem::take(&mut self.vertex_faces[from]) {
+            if self.face_vertices[f].contains(&to) {
+                self.face_alive[f] = false;
+                for w in self.face_vertices[f] {
+                    self.vertex_faces[w].retain(|g| *g != f);
+                }
+                removed += 1;
+            } else {
+                for c in 0..3 {
+                    if self.face_vertices[f][c] == from {
+                        self.face_vertices[f][c] = to;
+                    }
+                    if self.face_texture_points[f][c] == t_from {
+                        self.face_texture_points[f][c] = t_to;
+                    }
+                }
+                self.vertex_faces[to].push(f);
+            }
+        }
+        self.vertex_alive[from] = false;
+        self.quadrics[to] = self.quadrics[to].add(&self.quadrics[from]);
+        self.stamps[to] += 1;
+        self.push_candidates(to);
+        removed
+    }
+}
+
+/// Simplifies the model mesh
+///
+/// Vertices, heights, colors, sampled vertices, texture coordinates, faces and
+/// submeshes are rebuilt with contiguous indices.
+pub fn simplify(components: &mut ModelComponents, opts: &SimplifyOpts) -> Result<(), ErrBox> {
+    let vertices = components.get_vertices()?;
+    let pmap = components.get_texture_mapping()?;
+    let faces = components.get_faces()?;
+    let num_vertices = vertices.len();
+    if vertices.keys().enumerate().any(|(i, k)| i != *k) {
+        return Err("Model vertices must have contiguous indices for simplification".into());
+    }
+
+    let positions: Vec<Point3d> = vertices
+        .iter()
+        .map(|(k, gp)| {
+            let r = opts.radius + components.heights.get(k).cloned().unwrap_or(0.0);
+            scaled(geo_to_point3d(gp.lon, gp.lat), r)
+        })
+        .collect();
+
+    let mut texture_points: Vec<Vec<GeoPointIndex>> = vec![vec![]; num_vertices];
+    for (t, v) in pmap {
+        texture_points[*v].push(*t);
+    }
+    for tps in texture_points.iter_mut() {
+        tps.sort_unstable();
+    }
+    let locked: Vec<bool> = vertices
+        .values()
+        .zip(texture_points.iter())
+        .map(|(gp, tps)| tps.len() != 1 || 90.0 - gp.lat.abs() < POLE_EPSILON)
+        .collect();
+
+    let mut face_vertices = Vec::with_capacity(faces.len());
+    let mut face_texture_points = Vec::with_capacity(faces.len());
+    let mut vertex_faces: Vec<Vec<usize>> = vec![vec![]; num_vertices];
+    let mut quadrics = vec![Quadric::default(); num_vertices];
+    for (f, (t0, t1, t2)) in faces.iter().enumerate() {
+        let tps = [*t0, *t1, *t2];
+        let mut vs = [0; 3];
+        for c in 0..3 {
+            vs[c] = *pmap
+                .get(&tps[c])
+                .ok_or(format!("Point {} isn't found in points mapping", tps[c]))?;
+            vertex_faces[vs[c]].push(f);
+        }
+        let n = triangle_normal(&positions, vs);
+        let area2 = length(n);
+        if area2 > 0.0 {
+            let n = scaled(n, 1.0 / area2);
+            let q = Quadric::from_plane(n, -dot(n, positions[vs[0]]), area2 / 2.0);
+            for v in vs {
+                quadrics[v] = quadrics[v].add(&q);
+            }
+        }
+        face_vertices.push(vs);
+        face_texture_points.push(tps);
+    }
+
+    let mut decimator = Decimator {
+        positions: &positions,
+        face_alive: vec![true; faces.len()],
+        face_vertices,
+        face_texture_points,
+        vertex_faces,
+        vertex_alive: vec![true; num_vertices],
+        texture_points,
+        locked,
+        quadrics,
+        stamps: vec![0; num_vertices],
+        heap: BinaryHeap::new(),
+    };
+    for v in 0..num_vertices {
+        for w in decimator.neighbours(v) {
+            decimator.push_candidate(v, w);
+        }
+    }
+
+    let target_faces = opts.target_faces.unwrap_or(0);
+    // costs are mean squared distances
+    let max_cost = opts.max_error.map_or(f64::INFINITY, |e| e * e);
+    let mut num_faces = faces.len();
+    while num_faces > target_faces {
+        let Some(Candidate {
+            cost,
+            from,
+            to,
+            stamps,
+        }) = decimator.heap.pop()
+        else {
+            break;
+        };
+        if !decimator.vertex_alive[from]
+            || !decimator.vertex_alive[to]
+            || stamps != (decimator.stamps[from], decimator.stamps[to])
+        {
+            continue;
+        }
+        if cost > max_cost {
+            break;
+        }
+        if decimator.is_valid(from, to) {
+            num_faces -= decimator.collapse(from, to);
+        }
+    }
+
+    // compaction
+    let vertex_index: BTreeMap<GeoPointIndex, GeoPointIndex> = (0..num_vertices)
+        .filter(|v| decimator.vertex_alive[*v])
+        .enumerate()
+        .map(|(i, v)| (v, i))
+        .collect();
+    let mut used_texture_points: Vec<GeoPointIndex> = decimator
+        .face_texture_points
+        .iter()
+        .zip(decimator.face_alive.iter())
+        .filter(|(_, alive)| **alive)
+        .flat_map(|(tps, _)| *tps)
+        .collect();
+    used_texture_points.sort_unstable();
+    used_texture_points.dedup();
+    let texture_index: HashMap<GeoPointIndex, GeoPointIndex> = used_texture_points
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (*t, i))
+        .collect();
+
+    let new_vertices: Vertices = vertex_index
+        .iter()
+        .map(|(v, i)| {
+            let gp = &vertices[v];
+            (
+                *i,
+                GeoPoint {
+                    lon: gp.lon,
+                    lat: gp.lat,
+                },
+            )
+        })
+        .collect();
+    let new_heights: Heights = vertex_index
+        .iter()
+        .filter_map(|(v, i)| components.heights.get(v).map(|h| (*i, *h)))
+        .collect();
+    let new_colors: Option<Colors> = components.colors.as_ref().map(|colors| {
+        vertex_index
+            .iter()
+            .filter_map(|(v, i)| colors.get(v).map(|c| (*i, *c)))
+            .collect()
+    });
+    let new_sampled: Sampled = vertex_index
+        .iter()
+        .filter(|(v, _)| components.sampled.contains(v))
+        .map(|(_, i)| *i)
+        .collect();
+    let new_texture_mapping: PointsMapping = used_texture_points
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (i, vertex_index[&pmap[t]]))
+        .collect();
+    let new_texture_coordinates: Option<TextureCoordinates> = components
+        .texture_coordinates
+        .as_ref()
+        .map(|tcs| used_texture_points.iter().map(|t| tcs[*t]).collect());
+
+    let face_submeshes: Vec<Option<&String>> = (0..faces.len())
+        .map(|f| {
+            components.submeshes.as_ref().and_then(|submeshes| {
+                submeshes
+                    .iter()
+                    .find(|(_, range)| range.contains(&f))
+                    .map(|(name, _)| name)
+            })
+        })
+        .collect();
+    let mut new_faces: Faces = Vec::with_capacity(num_faces);
+    let mut new_submeshes: Submeshes = vec![];
+    for (f, tps) in decimator.face_texture_points.iter().enumerate() {
+        if !decimator.face_alive[f] {
+            continue;
+        }
+        if let Some(name) = face_submeshes[f] {
+            match new_submeshes.last_mut() {
+                Some((last, range)) if last == name => range.end = new_faces.len() + 1,
+                _ => new_submeshes.push((name.clone(), new_faces.len()..new_faces.len() + 1)),
+            }
+        }
+        new_faces.push((
+            texture_index[&tps[0]],
+            texture_index[&tps[1]],
+            texture_index[&tps[2]],
+        ));
+    }
+
+    components.vertices = Some(new_vertices);
+    components.heights = new_heights;
+    components.colors = new_colors;
+    components.sampled = new_sampled;
+    components.texture_mapping = Some(new_texture_mapping);
+    components.texture_coordinates = new_texture_coordinates;
+    components.faces = Some(new_faces);
+    if components.submeshes.is_some() {
+        components.submeshes = Some(new_submeshes);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tessellation::*;
+    use crate::model::testing::*;
+
+    fn make_components(data: TessellationData, heights: Heights) -> ModelComponents {
+        let TessellationData {
+            modeldata: ModelData(vertices, faces, texture_mapping),
+            texture_coordinates,
+            submeshes,
+        } = data;
+        ModelComponents {
+            spacing: 1.0,
+            heights,
+            colors: None,
+            sampled: Sampled::new(),
+            texture_coordinates: Some(texture_coordinates),
+            vertices: Some(vertices),
+            texture_mapping,
+            faces: Some(faces),
+            submeshes,
+        }
+    }
+
+    #[test]
+    fn simplify_target_faces() {
+        let data = create_icosphere(3);
+        let heights: Heights = (0..data.modeldata.0.len()).map(|k| (k, 0.0)).collect();
+        let mut components = make_components(data, heights);
+        let opts = SimplifyOpts {
+            target_faces: Some(300),
+            max_error: None,
+            radius: DEFAULT_RADIUS,
+        };
+        simplify(&mut components, &opts).unwrap();
+        let num_faces = components.get_faces().unwrap().len();
+        assert!(num_faces <= 300);
+        assert!(is_closed(
+            components.get_faces().unwrap(),
+            components.get_texture_mapping().unwrap()
+        ));
+        let num_vertices = components.get_vertices().unwrap().len();
+        assert_eq!(components.heights.len(), num_vertices);
+        assert_eq!(
+            components.get_texture_mapping().unwrap().len(),
+            components.get_texture_coordinates().unwrap().len()
+        );
+        // poles are kept
+        let vertices = components.get_vertices().unwrap();
+        assert!(vertices.values().any(|gp| gp.lat == 90.0));
+        assert!(vertices.values().any(|gp| gp.lat == -90.0));
+    }
+
+    #[test]
+    fn simplify_max_error() {
+        let data = create_icosphere(5);
+        let num_vertices = data.modeldata.0.len();
+        // a mountain in a flat ocean
+        let mountain = data
+            .modeldata
+            .0
+            .iter()
+            .find(|(_, gp)| gp.lat.abs() < 30.0 && gp.lon.abs() < 30.0)
+            .map(|(k, _)| *k)
+            .unwrap();
+        let mountain_gp = GeoPoint {
+            lon: data.modeldata.0[&mountain].lon,
+            lat: data.modeldata.0[&mountain].lat,
+        };
+        let heights: Heights = (0..num_vertices)
+            .map(|k| (k, if k == mountain { 8000.0 } else { 0.0 }))
+            .collect();
+        let num_faces = data.modeldata.1.len();
+        let mut components = make_components(data, heights);
+        let opts = SimplifyOpts {
+            target_faces: None,
+            max_error: Some(5000.0),
+            radius: DEFAULT_RADIUS,
+        };
+        simplify(&mut components, &opts).unwrap();
+        assert!(components.get_faces().unwrap().len() < num_faces / 2);
+        assert!(is_closed(
+            components.get_faces().unwrap(),
+            components.get_texture_mapping().unwrap()
+        ));
+        // the mountain top is kept
+        let vertices = components.get_vertices().unwrap();
+        let (k, _) = vertices
+            .iter()
+            .find(|(_, gp)| gp.lon == mountain_gp.lon && gp.lat == mountain_gp.lat)
+            .unwrap();
+        assert_eq!(components.heights[k], 8000.0);
+    }
+
+    #[test]
+    fn simplify_cubesphere_submeshes() {
+        let data = create_cubesphere(6, true);
+        let heights: Heights = (0..data.modeldata.0.len()).map(|k| (k, 0.0)).collect();
+        let mut components = make_components(data, heights);
+        let opts = SimplifyOpts {
+            target_faces: Some(100),
+            max_error: None,
+            radius: DEFAULT_RADIUS,
+        };
+        simplify(&mut components, &opts).unwrap();
+        assert!(is_closed(
+            components.get_faces().unwrap(),
+            components.get_texture_mapping().unwrap()
+        ));
+        let submeshes = components.submeshes.as_ref().unwrap();
+        assert_eq!(submeshes.len(), 6);
+        assert_eq!(submeshes[0].1.start, 0);
+        assert_eq!(submeshes[5].1.end, components.get_faces().unwrap().len());
+    }
+}
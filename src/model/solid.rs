@@ -0,0 +1,326 @@
+//! # Solid Meshes
+//!
+//! This module turns the model surface into the mesh written by the model writers.
+//! The mesh has explicit vertex positions, so it can contain vertices which are
+//! not points of the planet surface, such as the inner surface of a hollow globe.
+//!
+//! ## Printing Mode
+//!
+//! For 3D printing the surface is turned into a hollow shell:
+//!
+//! 1. The inner surface is the outer one moved towards the centre by the wall
+//!    thickness. Every inner vertex is placed below the lowest of its neighbours,
+//!    so that the wall is not thinner than requested under steep terrain.
+//! 2. Inner faces have the opposite winding, so their normals point into the cavity.
+//! 3. An optional flat base is cut at the bottom (south pole) of the globe.
+//! 4. An optional drain hole at the bottom is lined with a tube of faces which joins
+//!    the outer and the inner surfaces. The hole can also be used as a socket
+//!    for a stand rod.
+//!
+//! Each edge of the result is shared by exactly two faces with opposite
+//! directions, so the result is a closed (watertight) manifold solid.
+//!
+//! ## Configuration Parameters
+//!
+//! All lengths are in model units, where the planet radius is 1, or in physical
+//! units when a target `diameter` is given.
+//!
+//! - `print_wall_thickness`: wall thickness, enables the printing mode
+//! - `print_drain_hole`: diameter of the drain hole at the bottom (default: no hole)
+//! - `print_base`: height of the flat base cut off at the bottom (default: no base)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::tessellation::Point3d;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::collections::{HashMap, HashSet};
+
+/// Corner of a solid face: the solid vertex and the texture point
+pub type SolidCorner = (GeoPointIndex, GeoPointIndex);
+
+/// Mesh with explicit vertex positions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solid {
+    /// Positions of vertices
+    pub positions: Vec<Point3d>,
+    /// Model vertex each solid vertex is made from (for colors)
+    pub sources: Vec<GeoPointIndex>,
+    /// Triangles
+    pub faces: Vec<[SolidCorner; 3]>,
+    /// Model face each solid face is made from (for submeshes)
+    pub face_sources: Vec<usize>,
+    /// Texture coordinates of texture points (empty for color models)
+    pub texture_coordinates: TextureCoordinates,
+}
+
+/// Options of the printing mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintOpts {
+    /// Wall thickness
+    pub wall_thickness: Coord,
+    /// Diameter of the drain hole
+    pub drain_hole: Coord,
+    /// Height of the flat base
+    pub base: Coord,
+}
+
+impl PrintOpts {
+    /// Reads printing options from the settings
+    ///
+    /// Returns `None` if no wall thickness is given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let wall_thickness = settings.get_parameter_num("print_wall_thickness", 0.0)?;
+        if wall_thickness <= 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(PrintOpts {
+            wall_thickness,
+            drain_hole: settings.get_parameter_num("print_drain_hole", 0.0)?,
+            base: settings.get_parameter_num("print_base", 0.0)?,
+        }))
+    }
+}
+
+impl Solid {
+    /// Makes the solid of the model surface
+    ///
+    /// `positions` are indexed by model vertices.
+    pub fn from_surface(
+        positions: Vec<Point3d>,
+        faces: &Faces,
+        pmap: &PointsMapping,
+        texture_coordinates: TextureCoordinates,
+    ) -> Result<Self, ErrBox> {
+        let vertex = |t: &GeoPointIndex| -> Result<GeoPointIndex, ErrBox> {
+            pmap.get(t)
+                .cloned()
+                .ok_or(format!("Point {} isn't found in points mapping", t).into())
+        };
+        let solid_faces = faces
+            .iter()
+            .map(|(t0, t1, t2)| Ok([(vertex(t0)?, *t0), (vertex(t1)?, *t1), (vertex(t2)?, *t2)]))
+            .collect::<Result<Vec<_>, ErrBox>>()?;
+        Ok(Solid {
+            sources: (0..positions.len()).collect(),
+            positions,
+            face_sources: (0..faces.len()).collect(),
+            faces: solid_faces,
+            texture_coordinates,
+        })
+    }
+
+    /// Counts edges which aren't shared by exactly two faces with opposite directions
+    ///
+    /// Closed (watertight) solids have no open edges.
+    pub fn open_edges(&self) -> usize {
+        let mut edges: HashMap<(GeoPointIndex, GeoPointIndex), usize> = HashMap::new();
+        for face in &self.faces {
+            for c in 0..3 {
+                *edges.entry((face[c].0, face[(c + 1) % 3].0)).or_insert(0) += 1;
+            }
+        }
+        edges
+            .iter()
+            .filter(|((a, b), n)| **n != 1 || edges.get(&(*b, *a)) != Some(&1))
+            .count()
+    }
+
+    /// Makes a hollow printable shell of the surface solid
+    pub fn hollow(self, opts: &PrintOpts) -> Result<Self, ErrBox> {
+        let n = self.positions.len();
+        let mut outer = self.positions;
+
+        let mut ring_min: Vec<Coord> = outer.iter().map(|p| length(*p)).collect();
+        for face in &self.faces {
+            for c in 0..3 {
+                let (a, b) = (face[c].0, face[(c + 1) % 3].0);
+                let (ra, rb) = (length(outer[a]), length(outer[b]));
+                ring_min[a] = ring_min[a].min(rb);
+                ring_min[b] = ring_min[b].min(ra);
+            }
+        }
+        let mut inner = Vec::with_capacity(n);
+        for (p, r_min) in outer.iter().zip(ring_min) {
+            let r_inner = r_min - opts.wall_thickness;
+            if r_inner <= 0.0 {
+                return Err(format!(
+                    "Wall thickness {} exceeds the model radius",
+                    opts.wall_thickness
+                )
+                .into());
+            }
+            let k = r_inner / length(*p);
+            inner.push((p.0 * k, p.1 * k, p.2 * k));
+        }
+
+        if opts.base > 0.0 {
+            let z_min = outer.iter().map(|p| p.2).fold(Coord::INFINITY, Coord::min);
+            let z_inner_min = inner.iter().map(|p| p.2).fold(Coord::INFINITY, Coord::min);
+            let plane = z_min + opts.base;
+            if plane + opts.wall_thickness >= -z_inner_min {
+                return Err(format!("Base height {} is too large for the model", opts.base).into());
+            }
+            for p in outer.iter_mut() {
+                p.2 = p.2.max(plane);
+            }
+            for p in inner.iter_mut() {
+                p.2 = p.2.max(plane + opts.wall_thickness);
+            }
+        }
+
+        // faces around the bottom are removed for the drain hole
+        let mut removed = vec![false; self.faces.len()];
+        if opts.drain_hole > 0.0 {
+            let axis_distance = |p: &Point3d| (p.0 * p.0 + p.1 * p.1).sqrt();
+            let bottom = (0..n)
+                .filter(|v| outer[*v].2 < 0.0)
+                .min_by(|a, b| axis_distance(&outer[*a]).total_cmp(&axis_distance(&outer[*b])))
+                .ok_or("Model has no vertices at the bottom for the drain hole")?;
+            for (f, face) in self.faces.iter().enumerate() {
+                removed[f] = face.iter().any(|(v, _)| {
+                    *v == bottom
+                        || (outer[*v].2 < 0.0 && axis_distance(&outer[*v]) < opts.drain_hole / 2.0)
+                });
+            }
+            if removed.iter().all(|r| *r) {
+                return Err(
+                    format!("Drain hole {} is too large for the model", opts.drain_hole).into(),
+                );
+            }
+        }
+
+        let kept_faces: Vec<(&[SolidCorner; 3], usize)> = self
+            .faces
+            .iter()
+            .zip(self.face_sources.iter().cloned())
+            .zip(&removed)
+            .filter(|(_, r)| !**r)
+            .map(|(face, _)| face)
+            .collect();
+
+        // vertices of kept faces, outer ones followed by inner ones
+        let mut used = vec![false; n];
+        for (face, _) in &kept_faces {
+            for (v, _) in face.iter() {
+                used[*v] = true;
+            }
+        }
+        let used: Vec<GeoPointIndex> = (0..n).filter(|v| used[*v]).collect();
+        let mut outer_index = vec![0; n];
+        let mut inner_index = vec![0; n];
+        for (i, v) in used.iter().enumerate() {
+            outer_index[*v] = i;
+            inner_index[*v] = used.len() + i;
+        }
+        let positions: Vec<Point3d> = used
+            .iter()
+            .map(|v| outer[*v])
+            .chain(used.iter().map(|v| inner[*v]))
+            .collect();
+        let sources: Vec<GeoPointIndex> = used
+            .iter()
+            .chain(used.iter())
+            .map(|v| self.sources[*v])
+            .collect();
+
+        let mut faces = vec![];
+        let mut face_sources = vec![];
+        for (face, f) in &kept_faces {
+            faces.push(face.map(|(v, t)| (outer_index[v], t)));
+            face_sources.push(*f);
+        }
+        for (face, f) in &kept_faces {
+            let [c0, c1, c2] = face.map(|(v, t)| (inner_index[v], t));
+            faces.push([c0, c2, c1]);
+            face_sources.push(*f);
+        }
+
+        // tube joining the boundaries of the outer and the inner surfaces
+        let kept_edges: HashSet<(GeoPointIndex, GeoPointIndex)> = kept_faces
+            .iter()
+            .flat_map(|(face, _)| (0..3).map(|c| (face[c].0, face[(c + 1) % 3].0)))
+            .collect();
+        for (face, f) in &kept_faces {
+            for c in 0..3 {
+                let ((a, ta), (b, tb)) = (face[c], face[(c + 1) % 3]);
+                if kept_edges.contains(&(b, a)) {
+                    continue;
+                }
+                let (oa, ob) = (outer_index[a], outer_index[b]);
+                let (ia, ib) = (inner_index[a], inner_index[b]);
+                faces.push([(ob, tb), (oa, ta), (ia, ta)]);
+                faces.push([(ob, tb), (ia, ta), (ib, tb)]);
+                face_sources.push(*f);
+                face_sources.push(*f);
+            }
+        }
+
+        Ok(Solid {
+            positions,
+            sources,
+            faces,
+            face_sources,
+            texture_coordinates: self.texture_coordinates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing::*;
+
+    #[test]
+    fn open_edges_t0() {
+        let mut solid = surface();
+        assert_eq!(solid.open_edges(), 0);
+        solid.faces.pop();
+        assert_eq!(solid.open_edges(), 3);
+    }
+
+    #[test]
+    fn hollow_t0() {
+        let solid = surface();
+        let outer_volume = volume(&solid);
+        let opts = PrintOpts {
+            wall_thickness: 0.1,
+            drain_hole: 0.0,
+            base: 0.0,
+        };
+        let hollow = solid.hollow(&opts).unwrap();
+        assert_eq!(hollow.open_edges(), 0);
+        let expected = outer_volume * (1.0 - 0.9f64.powi(3));
+        assert!((volume(&hollow) - expected).abs() < 0.05 * expected);
+    }
+
+    #[test]
+    fn hollow_t1() {
+        let solid = surface();
+        let num_vertices = solid.positions.len();
+        let opts = PrintOpts {
+            wall_thickness: 0.1,
+            drain_hole: 0.3,
+            base: 0.1,
+        };
+        let hollow = solid.hollow(&opts).unwrap();
+        assert_eq!(hollow.open_edges(), 0);
+        assert!(volume(&hollow) > 0.0);
+        assert!(hollow.positions.len() < 2 * num_vertices);
+        let z_min = hollow
+            .positions
+            .iter()
+            .map(|p| p.2)
+            .fold(Coord::INFINITY, Coord::min);
+        assert!((z_min + 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hollow_t2() {
+        let opts = PrintOpts {
+            wall_thickness: 1.5,
+            drain_hole: 0.0,
+            base: 0.0,
+        };
+        assert!(surface().hollow(&opts).is_err());
+    }
+}
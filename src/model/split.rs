@@ -0,0 +1,753 @@
+//! # Splitting into Printable Pieces
+//!
+//! This module cuts a solid model into pieces which fit a 3D printer: two
+//! hemispheres cut along the equator or a meridian plane, or N lunes cut along
+//! meridians.
+//!
+//! ## Algorithm
+//!
+//! 1. Every piece is the intersection of the solid with one or two half-spaces.
+//!    The solid is clipped by each half-space, triangles crossing the cutting
+//!    plane are split, and new vertices are shared by neighbouring triangles.
+//! 2. The open boundary on the cutting plane is capped: boundary loops are
+//!    triangulated by ear clipping, with inner loops (the cavity of a hollow
+//!    shell) bridged into the outer ones.
+//! 3. Blind pin holes are cut into the caps. The holes of mating caps are at the
+//!    same places, so dowel pins align the pieces when they are glued together.
+//! 4. Each piece is rotated so that its first cut face lies flat on `z = 0`.
+//!
+//! Every piece is a closed manifold solid.
+//!
+//! ## Configuration Parameters
+//!
+//! Lengths are in model units.
+//!
+//! - `split_parts`: number of pieces, 2 for hemispheres, 3 or more for lunes (default: no split)
+//! - `split_plane`: cutting plane of hemispheres, "equator" (default) or "meridian"
+//! - `split_lon`: longitude of the meridian of the first cut in degrees (default: 0.0)
+//! - `split_pins`: number of pin holes on each cut face (default: 2)
+//! - `split_pin_diameter`: diameter of pin holes (default: 0.03 of the planet radius)
+//! - `split_pin_depth`: depth of pin holes (default: pin diameter)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::solid::*;
+use crate::model::tessellation::Point3d;
+use crate::model::units::*;
+use crate::model::vector::*;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+
+const DEFAULT_PINS: usize = 2;
+const DEFAULT_PIN_DIAMETER: Coord = 0.03;
+/// Distance below which a vertex lies on the cutting plane
+const PLANE_EPSILON: Coord = 1e-9;
+/// Number of segments of a pin hole
+const PIN_SEGMENTS: usize = 16;
+/// Number of candidate pin positions on a ring
+const PIN_CANDIDATES: usize = 72;
+
+/// Point in the coordinates of a cutting plane
+type Point2d = (Coord, Coord);
+
+/// Cutting plane of hemispheres
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitPlane {
+    Equator,
+    Meridian,
+}
+
+/// Options of splitting into pieces
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitOpts {
+    /// Number of pieces
+    pub parts: usize,
+    /// Cutting plane of hemispheres
+    pub plane: SplitPlane,
+    /// Longitude of the first meridian cut in degrees
+    pub lon: Coord,
+    /// Number of pin holes on each cut face
+    pub pins: usize,
+    /// Diameter of pin holes
+    pub pin_diameter: Coord,
+    /// Depth of pin holes
+    pub pin_depth: Coord,
+}
+
+impl SplitOpts {
+    /// Reads splitting options from the settings
+    ///
+    /// Returns `None` if the model isn't split.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let parts = settings.get_parameter_num("split_parts", 0usize)?;
+        if parts < 2 {
+            return Ok(None);
+        }
+        let plane = match settings
+            .get_parameter_str("split_plane", "equator")?
+            .as_str()
+        {
+            "equator" => SplitPlane::Equator,
+            "meridian" => SplitPlane::Meridian,
+            s => return Err(format!("Unknown split plane '{}'", s).into()),
+        };
+        let pin_diameter = settings.get_parameter_num(
+            "split_pin_diameter",
+            DEFAULT_PIN_DIAMETER * output_radius(settings)?,
+        )?;
+        Ok(Some(SplitOpts {
+            parts,
+            plane,
+            lon: settings.get_parameter_num("split_lon", 0.0)?,
+            pins: settings.get_parameter_num("split_pins", DEFAULT_PINS)?,
+            pin_diameter,
+            pin_depth: settings.get_parameter_num("split_pin_depth", pin_diameter)?,
+        }))
+    }
+}
+
+/// Normal of the meridian plane, pointing to the west of the meridian
+fn meridian_normal(lon: Coord) -> Point3d {
+    let phi = lon * PI / 180.0;
+    (phi.cos(), phi.sin(), 0.0)
+}
+
+/// Cutting planes (by their normals) and the half-spaces bounding each piece
+///
+/// A half-space is given by a cutting plane index and a sign `s` as `s * n * p >= 0`.
+fn cutting_planes(opts: &SplitOpts) -> (Vec<Point3d>, Vec<Vec<(usize, Coord)>>) {
+    if opts.parts == 2 {
+        let n = match opts.plane {
+            SplitPlane::Equator => (0.0, 0.0, 1.0),
+            SplitPlane::Meridian => scaled(meridian_normal(opts.lon), -1.0),
+        };
+        return (vec![n], vec![vec![(0, 1.0)], vec![(0, -1.0)]]);
+    }
+    let step = 360.0 / opts.parts as Coord;
+    let planes = (0..opts.parts)
+        .map(|k| meridian_normal(opts.lon + step * k as Coord))
+        .collect();
+    let pieces = (0..opts.parts)
+        .map(|k| vec![(k, -1.0), ((k + 1) % opts.parts, 1.0)])
+        .collect();
+    (planes, pieces)
+}
+
+/// Splits the solid into pieces lying flat on `z = 0`
+pub fn split(solid: &Solid, opts: &SplitOpts) -> Result<Vec<Solid>, ErrBox> {
+    let (planes, pieces) = cutting_planes(opts);
+    // pins are chosen once for each cutting plane, so that both sides get the same pins
+    let pins = (0..planes.len())
+        .map(|i| {
+            if opts.pins == 0 {
+                return Ok(vec![]);
+            }
+            let others: Vec<Point3d> = pieces
+                .iter()
+                .filter(|half_spaces| half_spaces.iter().any(|(j, _)| *j == i))
+                .flatten()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, sign)| scaled(planes[*j], *sign))
+                .collect();
+            let n = planes[i];
+            let part = clip(solid, n);
+            let (u, v) = plane_basis(n);
+            let (_, pts, loops) = cut_face(&part, &cut_loops(&part)?, u, v);
+            choose_pins(&pts, &loops, u, v, &others, opts)
+        })
+        .collect::<Result<Vec<_>, ErrBox>>()?;
+    pieces
+        .iter()
+        .map(|half_spaces| {
+            let mut part = solid.clone();
+            for (i, sign) in half_spaces {
+                let n = scaled(planes[*i], *sign);
+                part = clip(&part, n);
+                cap(&mut part, n, &pins[*i], opts)?;
+            }
+            let (i, sign) = half_spaces[0];
+            Ok(lay_flat(part, scaled(planes[i], sign)))
+        })
+        .collect()
+}
+
+/// Removes vertices which aren't used by faces
+fn compact(solid: Solid) -> Solid {
+    let mut index = vec![None; solid.positions.len()];
+    let mut positions = vec![];
+    let mut sources = vec![];
+    for face in &solid.faces {
+        for (v, _) in face {
+            if index[*v].is_none() {
+                index[*v] = Some(positions.len());
+                positions.push(solid.positions[*v]);
+                sources.push(solid.sources[*v]);
+            }
+        }
+    }
+    let faces = solid
+        .faces
+        .iter()
+        .map(|face| face.map(|(v, t)| (index[v].unwrap(), t)))
+        .collect();
+    Solid {
+        positions,
+        sources,
+        faces,
+        face_sources: solid.face_sources,
+        texture_coordinates: solid.texture_coordinates,
+    }
+}
+
+/// Clips the solid by the half-space `n * p >= 0`, leaving the cut open
+fn clip(solid: &Solid, n: Point3d) -> Solid {
+    let dist: Vec<Coord> = solid
+        .positions
+        .iter()
+        .map(|p| {
+            let d = dot(n, *p);
+            if d.abs() < PLANE_EPSILON { 0.0 } else { d }
+        })
+        .collect();
+    let mut positions = solid.positions.clone();
+    let mut sources = solid.sources.clone();
+    let mut texture_coordinates = solid.texture_coordinates.clone();
+    let mut cut_vertices: HashMap<(GeoPointIndex, GeoPointIndex), GeoPointIndex> = HashMap::new();
+    let mut cut_points: HashMap<(GeoPointIndex, GeoPointIndex), GeoPointIndex> = HashMap::new();
+    let mut faces = vec![];
+    let mut face_sources = vec![];
+    for (face, f) in solid.faces.iter().zip(&solid.face_sources) {
+        let mut polygon: Vec<SolidCorner> = Vec::with_capacity(4);
+        for c in 0..3 {
+            let (a, b) = (face[c], face[(c + 1) % 3]);
+            if dist[a.0] >= 0.0 {
+                polygon.push(a);
+            }
+            if dist[a.0] * dist[b.0] < 0.0 {
+                // the cut is interpolated from the vertex with the lower index
+                let ((a, ta), (b, tb)) = if a.0 < b.0 { (a, b) } else { (b, a) };
+                let t = dist[a] / (dist[a] - dist[b]);
+                let v = *cut_vertices.entry((a, b)).or_insert_with(|| {
+                    let (pa, pb) = (positions[a], positions[b]);
+                    positions.push(add(pa, scaled(add(pb, scaled(pa, -1.0)), t)));
+                    sources.push(if dist[a] > 0.0 {
+                        sources[a]
+                    } else {
+                        sources[b]
+                    });
+                    positions.len() - 1
+                });
+                let tp = if texture_coordinates.is_empty() {
+                    0
+                } else {
+                    *cut_points.entry((ta, tb)).or_insert_with(|| {
+                        let ((ua, va), (ub, vb)) =
+                            (texture_coordinates[ta], texture_coordinates[tb]);
+                        texture_coordinates.push((ua + (ub - ua) * t, va + (vb - va) * t));
+                        texture_coordinates.len() - 1
+                    })
+                };
+                polygon.push((v, tp));
+            }
+        }
+        for i in 1..polygon.len().saturating_sub(1) {
+            faces.push([polygon[0], polygon[i], polygon[i + 1]]);
+            face_sources.push(*f);
+        }
+    }
+    compact(Solid {
+        positions,
+        sources,
+        faces,
+        face_sources,
+        texture_coordinates,
+    })
+}
+
+/// Twice the signed area of the triangle, positive for counter-clockwise triangles
+fn orient(a: Point2d, b: Point2d, c: Point2d) -> Coord {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Twice the signed area of the polygon
+fn loop_area(pts: &[Point2d], lp: &[usize]) -> Coord {
+    (0..lp.len())
+        .map(|i| {
+            let (a, b) = (pts[lp[i]], pts[lp[(i + 1) % lp.len()]]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+/// Checks whether the point is inside of the polygon (even-odd rule)
+fn inside_loop(pts: &[Point2d], lp: &[usize], p: Point2d) -> bool {
+    let mut inside = false;
+    for i in 0..lp.len() {
+        let (a, b) = (pts[lp[i]], pts[lp[(i + 1) % lp.len()]]);
+        if (a.1 > p.1) != (b.1 > p.1) && p.0 < a.0 + (p.1 - a.1) * (b.0 - a.0) / (b.1 - a.1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Distance from the point to the segment
+fn segment_distance(p: Point2d, a: Point2d, b: Point2d) -> Coord {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((a.0 + t * dx - p.0).powi(2) + (a.1 + t * dy - p.1).powi(2)).sqrt()
+}
+
+/// Checks whether two segments cross at a point inside of both of them
+fn segments_cross(a: Point2d, b: Point2d, c: Point2d, d: Point2d) -> bool {
+    orient(a, b, c) * orient(a, b, d) < 0.0 && orient(c, d, a) * orient(c, d, b) < 0.0
+}
+
+/// Checks whether the point is inside of the counter-clockwise triangle or on its edges
+fn in_triangle(p: Point2d, a: Point2d, b: Point2d, c: Point2d) -> bool {
+    orient(a, b, p) >= 0.0 && orient(b, c, p) >= 0.0 && orient(c, a, p) >= 0.0
+}
+
+/// Joins the clockwise hole into the counter-clockwise polygon by a bridge
+fn bridge_hole(
+    pts: &[Point2d],
+    polygon: &mut Vec<usize>,
+    hole: &[usize],
+    other_holes: &[&Vec<usize>],
+) -> Result<(), ErrBox> {
+    let m_i = (0..hole.len())
+        .max_by(|a, b| pts[hole[*a]].0.total_cmp(&pts[hole[*b]].0))
+        .unwrap();
+    let m = pts[hole[m_i]];
+    let mut candidates: Vec<usize> = (0..polygon.len()).collect();
+    let dist2 = |i: &usize| {
+        let v = pts[polygon[*i]];
+        (v.0 - m.0).powi(2) + (v.1 - m.1).powi(2)
+    };
+    candidates.sort_by(|a, b| dist2(a).total_cmp(&dist2(b)));
+    let edges_of = |lp: &[usize]| -> Vec<(Point2d, Point2d)> {
+        (0..lp.len())
+            .map(|i| (pts[lp[i]], pts[lp[(i + 1) % lp.len()]]))
+            .collect()
+    };
+    let mut edges = edges_of(polygon);
+    edges.extend(edges_of(hole));
+    for other in other_holes {
+        edges.extend(edges_of(other));
+    }
+    let n = polygon.len();
+    let visible = candidates.into_iter().find(|i| {
+        let v = pts[polygon[*i]];
+        let (prev, next) = (pts[polygon[(i + n - 1) % n]], pts[polygon[(i + 1) % n]]);
+        let in_cone = if orient(prev, v, next) >= 0.0 {
+            orient(prev, v, m) > 0.0 && orient(v, next, m) > 0.0
+        } else {
+            orient(prev, v, m) > 0.0 || orient(v, next, m) > 0.0
+        };
+        in_cone && edges.iter().all(|(a, b)| !segments_cross(v, m, *a, *b))
+    });
+    let i = visible.ok_or("Can't join a hole of the cut face")?;
+    let mut merged = polygon[..=i].to_vec();
+    merged.extend(hole[m_i..].iter().chain(hole[..=m_i].iter()));
+    merged.push(polygon[i]);
+    merged.extend(&polygon[i + 1..]);
+    *polygon = merged;
+    Ok(())
+}
+
+/// Triangulates counter-clockwise polygons with clockwise holes by ear clipping
+fn triangulate(pts: &[Point2d], loops: &[Vec<usize>]) -> Result<Vec<[usize; 3]>, ErrBox> {
+    let (outers, holes): (Vec<&Vec<usize>>, Vec<&Vec<usize>>) =
+        loops.iter().partition(|lp| loop_area(pts, lp) > 0.0);
+    let mut polygon_holes: Vec<Vec<&Vec<usize>>> = vec![vec![]; outers.len()];
+    for hole in holes {
+        let outer = (0..outers.len())
+            .filter(|o| inside_loop(pts, outers[*o], pts[hole[0]]))
+            .min_by(|a, b| loop_area(pts, outers[*a]).total_cmp(&loop_area(pts, outers[*b])))
+            .ok_or("Hole of the cut face is outside of it")?;
+        polygon_holes[outer].push(hole);
+    }
+
+    let mut triangles = vec![];
+    for (outer, mut holes) in outers.into_iter().zip(polygon_holes) {
+        let max_x = |lp: &&Vec<usize>| lp.iter().map(|p| pts[*p].0).fold(Coord::MIN, Coord::max);
+        holes.sort_by(|a, b| max_x(b).total_cmp(&max_x(a)));
+        let mut polygon = outer.clone();
+        for h in 0..holes.len() {
+            bridge_hole(pts, &mut polygon, holes[h], &holes[h + 1..])?;
+        }
+
+        let mut start = 0;
+        while polygon.len() > 3 {
+            let n = polygon.len();
+            let corners = |i: usize| (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+            let is_ear = |i: usize| {
+                let (a, b, c) = corners(i);
+                let (pa, pb, pc) = (pts[a], pts[b], pts[c]);
+                orient(pa, pb, pc) > 0.0
+                    && polygon.iter().all(|p| {
+                        let pp = pts[*p];
+                        pp == pa || pp == pb || pp == pc || !in_triangle(pp, pa, pb, pc)
+                    })
+            };
+            // degenerate polygons are cut at their flattest corner
+            let i = (0..n)
+                .map(|k| (start + k) % n)
+                .find(|i| is_ear(*i))
+                .unwrap_or_else(|| {
+                    (0..n)
+                        .min_by(|x, y| {
+                            let area = |i: usize| {
+                                let (a, b, c) = corners(i);
+                                orient(pts[a], pts[b], pts[c]).abs()
+                            };
+                            area(*x).total_cmp(&area(*y))
+                        })
+                        .unwrap()
+                });
+            let (a, b, c) = corners(i);
+            triangles.push([a, b, c]);
+            polygon.remove(i);
+            start = i % polygon.len();
+        }
+        triangles.push([polygon[0], polygon[1], polygon[2]]);
+    }
+    Ok(triangles)
+}
+
+/// Boundary loops of the cut, traversed backwards so that the cap gets opposite edges
+fn cut_loops(part: &Solid) -> Result<Vec<Vec<GeoPointIndex>>, ErrBox> {
+    let edges: HashSet<(GeoPointIndex, GeoPointIndex)> = part
+        .faces
+        .iter()
+        .flat_map(|face| (0..3).map(move |c| (face[c].0, face[(c + 1) % 3].0)))
+        .collect();
+    let mut next: HashMap<GeoPointIndex, Vec<GeoPointIndex>> = HashMap::new();
+    for (a, b) in &edges {
+        if !edges.contains(&(*b, *a)) {
+            next.entry(*a).or_default().push(*b);
+        }
+    }
+    let mut starts: Vec<GeoPointIndex> = next.keys().cloned().collect();
+    starts.sort();
+    let mut loops = vec![];
+    for start in starts {
+        while let Some(b) = next.get_mut(&start).and_then(|bs| bs.pop()) {
+            let mut lp = vec![start];
+            let mut v = b;
+            while v != start {
+                lp.push(v);
+                v = next
+                    .get_mut(&v)
+                    .and_then(|bs| bs.pop())
+                    .ok_or("Cut boundary isn't closed")?;
+            }
+            lp.reverse();
+            loops.push(lp);
+        }
+    }
+    Ok(loops)
+}
+
+/// Basis `u`, `v` of the plane `n * p = 0` with `u x v = -n`
+fn plane_basis(n: Point3d) -> (Point3d, Point3d) {
+    let e = if n.2.abs() < 0.9 {
+        (0.0, 0.0, 1.0)
+    } else {
+        (1.0, 0.0, 0.0)
+    };
+    let u = cross(n, e);
+    let u = normalize(u);
+    (u, cross(u, n))
+}
+
+/// Cut face in plane coordinates: solid vertices, their points and loops of point indices
+fn cut_face(
+    part: &Solid,
+    vertex_loops: &[Vec<GeoPointIndex>],
+    u: Point3d,
+    v: Point3d,
+) -> (Vec<GeoPointIndex>, Vec<Point2d>, Vec<Vec<usize>>) {
+    let mut ids = vec![];
+    let mut pts = vec![];
+    let mut loops = vec![];
+    for lp in vertex_loops {
+        let mut local = vec![];
+        for vertex in lp {
+            let p = part.positions[*vertex];
+            local.push(ids.len());
+            ids.push(*vertex);
+            pts.push((dot(p, u), dot(p, v)));
+        }
+        loops.push(local);
+    }
+    (ids, pts, loops)
+}
+
+/// Closes the cut on the plane `n * p = 0`, with blind holes for the pins
+fn cap(part: &mut Solid, n: Point3d, pins: &[Point3d], opts: &SplitOpts) -> Result<(), ErrBox> {
+    let vertex_loops = cut_loops(part)?;
+    if vertex_loops.is_empty() {
+        return Ok(());
+    }
+    let (u, v) = plane_basis(n);
+    let (mut ids, mut pts, mut loops) = cut_face(part, &vertex_loops, u, v);
+
+    let cap_point = if part.texture_coordinates.is_empty() {
+        0
+    } else {
+        part.texture_coordinates.push((0.5, 0.0));
+        part.texture_coordinates.len() - 1
+    };
+    let face_source = part.face_sources.first().cloned().unwrap_or(0);
+
+    let pin_radius = opts.pin_diameter / 2.0;
+    let mut pockets = vec![];
+    for pin in pins {
+        let (cx, cy) = (dot(*pin, u), dot(*pin, v));
+        let source = (0..ids.len())
+            .min_by(|a, b| {
+                let d = |i: &usize| (pts[*i].0 - cx).powi(2) + (pts[*i].1 - cy).powi(2);
+                d(a).total_cmp(&d(b))
+            })
+            .map(|i| part.sources[ids[i]])
+            .unwrap_or(0);
+        let mut local = vec![];
+        let mut top = vec![];
+        let mut bottom = vec![];
+        for j in 0..PIN_SEGMENTS {
+            // holes are clockwise
+            let phi = -2.0 * PI * j as Coord / PIN_SEGMENTS as Coord;
+            let (x, y) = (cx + pin_radius * phi.cos(), cy + pin_radius * phi.sin());
+            let p = add(scaled(u, x), scaled(v, y));
+            local.push(ids.len());
+            ids.push(part.positions.len());
+            pts.push((x, y));
+            top.push(part.positions.len());
+            part.positions.push(p);
+            part.sources.push(source);
+            bottom.push(part.positions.len());
+            part.positions.push(add(p, scaled(n, opts.pin_depth)));
+            part.sources.push(source);
+        }
+        let centre = part.positions.len();
+        part.positions.push(add(
+            add(scaled(u, cx), scaled(v, cy)),
+            scaled(n, opts.pin_depth),
+        ));
+        part.sources.push(source);
+        pockets.push((top, bottom, centre));
+        loops.push(local);
+    }
+
+    for triangle in triangulate(&pts, &loops)? {
+        part.faces.push(triangle.map(|i| (ids[i], cap_point)));
+        part.face_sources.push(face_source);
+    }
+    for (top, bottom, centre) in pockets {
+        for j in 0..top.len() {
+            let k = (j + 1) % top.len();
+            let (a, b, a1, b1) = (top[j], top[k], bottom[j], bottom[k]);
+            part.faces
+                .push([(b, cap_point), (a, cap_point), (a1, cap_point)]);
+            part.faces
+                .push([(b, cap_point), (a1, cap_point), (b1, cap_point)]);
+            part.faces
+                .push([(centre, cap_point), (b1, cap_point), (a1, cap_point)]);
+            part.face_sources.extend([face_source; 3]);
+        }
+    }
+    Ok(())
+}
+
+/// Chooses pin positions on the cut face, as far from each other as possible
+///
+/// Pins are kept clear of the edges of the face and of the `others` half-spaces.
+fn choose_pins(
+    pts: &[Point2d],
+    loops: &[Vec<usize>],
+    u: Point3d,
+    v: Point3d,
+    others: &[Point3d],
+    opts: &SplitOpts,
+) -> Result<Vec<Point3d>, ErrBox> {
+    let clearance = opts.pin_diameter;
+    let r_max = pts
+        .iter()
+        .map(|(x, y)| (x * x + y * y).sqrt())
+        .fold(0.0, Coord::max);
+    // rings are stepped densely enough to fit into the wall of a hollow shell
+    let ring_step = clearance / 4.0;
+    let rings = (1..(r_max / ring_step) as usize).map(|ring| r_max - ring_step * ring as Coord);
+    let is_valid = |(x, y): Point2d| {
+        let p = add(scaled(u, x), scaled(v, y));
+        loops
+            .iter()
+            .filter(|lp| inside_loop(pts, lp, (x, y)))
+            .count()
+            % 2
+            == 1
+            && loops.iter().all(|lp| {
+                (0..lp.len()).all(|i| {
+                    segment_distance((x, y), pts[lp[i]], pts[lp[(i + 1) % lp.len()]]) >= clearance
+                })
+            })
+            && others
+                .iter()
+                .all(|m| dot(*m, p) >= clearance + opts.pin_depth)
+    };
+    for r in rings {
+        let mut valid: Vec<Point2d> = (0..PIN_CANDIDATES)
+            .map(|k| {
+                let phi = 2.0 * PI * k as Coord / PIN_CANDIDATES as Coord;
+                (r * phi.cos(), r * phi.sin())
+            })
+            .filter(|c| is_valid(*c))
+            .collect();
+        if valid.len() < opts.pins {
+            continue;
+        }
+        let mut chosen = vec![valid.remove(0)];
+        while chosen.len() < opts.pins {
+            let distance = |(x, y): &Point2d| {
+                chosen
+                    .iter()
+                    .map(|(cx, cy)| (x - cx).powi(2) + (y - cy).powi(2))
+                    .fold(Coord::MAX, Coord::min)
+            };
+            let best = (0..valid.len())
+                .max_by(|a, b| {
+                    distance(&valid[*a])
+                        .total_cmp(&distance(&valid[*b]))
+                        .then(b.cmp(a))
+                })
+                .unwrap();
+            chosen.push(valid.remove(best));
+        }
+        return Ok(chosen
+            .into_iter()
+            .map(|(x, y)| add(scaled(u, x), scaled(v, y)))
+            .collect());
+    }
+    Err(format!(
+        "No room for {} pin holes of diameter {} on the cut faces",
+        opts.pins, opts.pin_diameter
+    )
+    .into())
+}
+
+/// Rotates the piece so that the cut face of the half-space `n * p >= 0` lies on `z = 0`
+fn lay_flat(mut part: Solid, n: Point3d) -> Solid {
+    let z = (0.0, 0.0, 1.0);
+    let k = cross(n, z);
+    let s2 = dot(k, k);
+    let c = dot(n, z);
+    let rotate = |p: Point3d| -> Point3d {
+        if s2 < PLANE_EPSILON {
+            if c > 0.0 { p } else { (p.0, -p.1, -p.2) }
+        } else {
+            // Rodrigues' rotation formula
+            let kp = cross(k, p);
+            add(add(p, kp), scaled(cross(k, kp), (1.0 - c) / s2))
+        }
+    };
+    for p in part.positions.iter_mut() {
+        *p = rotate(*p);
+    }
+    let z_min = part
+        .positions
+        .iter()
+        .map(|p| p.2)
+        .fold(Coord::MAX, Coord::min);
+    for p in part.positions.iter_mut() {
+        p.2 -= z_min;
+    }
+    part
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing::*;
+
+    fn opts(parts: usize, pins: usize) -> SplitOpts {
+        SplitOpts {
+            parts,
+            plane: SplitPlane::Equator,
+            lon: 10.0,
+            pins,
+            pin_diameter: 0.05,
+            pin_depth: 0.05,
+        }
+    }
+
+    #[test]
+    fn split_hemispheres() {
+        let solid = surface();
+        let full = volume(&solid);
+        let parts = split(&solid, &opts(2, 0)).unwrap();
+        assert_eq!(parts.len(), 2);
+        for part in &parts {
+            assert_eq!(part.open_edges(), 0);
+            assert!((volume(part) - full / 2.0).abs() < 1e-9);
+            let z_min = part
+                .positions
+                .iter()
+                .map(|p| p.2)
+                .fold(Coord::MAX, Coord::min);
+            assert_eq!(z_min, 0.0);
+            assert!(part.positions.iter().filter(|p| p.2.abs() < 1e-9).count() > 3);
+        }
+    }
+
+    #[test]
+    fn split_pins() {
+        let solid = surface();
+        let full = volume(&solid);
+        let parts = split(&solid, &opts(2, 3)).unwrap();
+        let pin_volume = PI * 0.025 * 0.025 * 0.05;
+        for part in &parts {
+            assert_eq!(part.open_edges(), 0);
+            let expected = full / 2.0 - 3.0 * pin_volume;
+            assert!((volume(part) - expected).abs() < 0.1 * pin_volume);
+        }
+    }
+
+    #[test]
+    fn split_lunes() {
+        let solid = surface();
+        let full = volume(&solid);
+        let parts = split(&solid, &opts(5, 2)).unwrap();
+        assert_eq!(parts.len(), 5);
+        let mut total = 0.0;
+        for part in &parts {
+            assert_eq!(part.open_edges(), 0);
+            total += volume(part);
+        }
+        assert!(total < full && total > 0.95 * full);
+    }
+
+    #[test]
+    fn split_hollow() {
+        let print_opts = PrintOpts {
+            wall_thickness: 0.15,
+            drain_hole: 0.2,
+            base: 0.0,
+        };
+        let solid = surface().hollow(&print_opts).unwrap();
+        let full = volume(&solid);
+        let mut split_opts = opts(2, 2);
+        split_opts.plane = SplitPlane::Meridian;
+        let parts = split(&solid, &split_opts).unwrap();
+        let mut total = 0.0;
+        for part in &parts {
+            assert_eq!(part.open_edges(), 0);
+            total += volume(part);
+        }
+        assert!(total < full && total > 0.9 * full);
+    }
+}
@@ -0,0 +1,316 @@
+//! # STL Model Generation Module
+//!
+//! This module writes models as STL files for slicers. STL has only a list of
+//! triangles with facet normals, without colors or texture coordinates, so
+//! **Texture** and **Color** models give the same geometry.
+//!
+//! Vertices are placed by the same parameters as OBJ vertices (see `surface`):
+//! with a target `diameter` the coordinates are in `units`, which are written to
+//! the header (STL has no units of its own). Facet normals are computed from the
+//! written triangles.
+//!
+//! Slicers expect closed solids, so every written solid is checked to have no
+//! open edges. The plain surface is closed; printing (`print_wall_thickness`) and
+//! splitting (`split_parts`) work as for OBJ models, and each piece is written to
+//! `{planet_name}_part{N}.stl`.
+//!
+//! ## Configuration Parameters
+//!
+//! - `stl_format`: "binary" (default) or "ascii"
+//! - see `surface`, `solid` and `split` for the other parameters
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::frame::*;
+use crate::model::mask::*;
+use crate::model::solid::*;
+use crate::model::split::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const HEADER_SIZE: usize = 80;
+
+/// Encoding of STL files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StlFormat {
+    Binary,
+    Ascii,
+}
+
+impl StlFormat {
+    /// Reads the STL encoding from the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings.get_parameter_str("stl_format", "binary")?.as_str() {
+            "binary" => Ok(StlFormat::Binary),
+            "ascii" => Ok(StlFormat::Ascii),
+            s => Err(format!("Unknown STL format '{}'", s).into()),
+        }
+    }
+}
+
+/// Triangle with its unit normal
+type Facet = (Point3d, [Point3d; 3]);
+
+/// STL model structure
+///
+/// This struct represents a 3D geospatial model in STL format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct Stl<'a> {
+    model_type: ModelType,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    format: StlFormat,
+    print_opts: Option<PrintOpts>,
+    split_opts: Option<SplitOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for Stl<'a> {
+    /// Checks the STL encoding
+    fn options_check(settings: &'a Settings) -> Result<(), ErrBox> {
+        StlFormat::from_settings(settings).map(|_| ())
+    }
+
+    /// Builds and constructs an STL model instance
+    fn build_model(
+        model_type: ModelType,
+        _model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        if let Some(MaskOpts {
+            mode: MaskMode::Crop,
+            ..
+        }) = MaskOpts::from_settings(settings)?
+        {
+            return Err("Cropped models are open surfaces and can't be written to STL".into());
+        }
+        let surface = Surface::from_settings(settings, &components)?;
+        let print_opts = PrintOpts::from_settings(settings)?;
+        let split_opts = SplitOpts::from_settings(settings)?;
+        surface.check_print_opts(print_opts.as_ref(), split_opts.as_ref())?;
+        Ok(Stl {
+            model_type,
+            settings,
+            surface,
+            format: StlFormat::from_settings(settings)?,
+            print_opts,
+            split_opts,
+            components,
+        })
+    }
+
+    /// Saves the model to STL files
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let solid = match &self.print_opts {
+            Some(print_opts) => solid.hollow(print_opts)?,
+            None => solid,
+        };
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        match &self.split_opts {
+            Some(split_opts) => {
+                for (n, part) in split(&solid, split_opts)?.iter().enumerate() {
+                    let name = format!("{}_part{}", planet_name, n + 1);
+                    self.write(&name, part, origin)?;
+                }
+                Ok(())
+            }
+            None => self.write(planet_name, &solid, origin),
+        }
+    }
+}
+
+impl<'a> Stl<'a> {
+    /// Writes a closed solid to `{name}.stl`
+    fn write(&self, name: &str, solid: &Solid, origin: Point3d) -> Result<(), ErrBox> {
+        let path = Path::new(self.settings.output_dir)
+            .join(name)
+            .with_extension("stl");
+        let open_edges = solid.open_edges();
+        if open_edges > 0 {
+            return Err(format!(
+                "Solid of {:?} isn't closed ({} open edges), STL needs closed solids",
+                path, open_edges
+            )
+            .into());
+        }
+        let frame = &self.surface.frame;
+        let facets = make_facets(
+            solid,
+            |p| frame.transform(origin, p),
+            frame.handedness == Handedness::Left,
+        );
+        let units = match &self.surface.physical_size {
+            Some(physical_size) => physical_size.units.symbol(),
+            None => "model units",
+        };
+        let file =
+            File::create(&path).map_err(|err| format!("Can't create {:?}: {}", path, err))?;
+        let mut writer = BufWriter::new(file);
+        match self.format {
+            StlFormat::Binary => {
+                write_binary(&mut writer, &format!("{} ({})", name, units), &facets)
+            }
+            StlFormat::Ascii => write_ascii(&mut writer, name, &facets),
+        }
+        .and_then(|_| writer.flush())
+        .map_err(|err| format!("Can't write stl file {:?}: {}", path, err).into())
+    }
+}
+
+/// Makes facets of the solid with positions mapped to output coordinates
+///
+/// Normals follow the winding of the written triangles (zero for degenerate ones).
+/// STL is right-handed, so triangles mirrored by the transform (left-handed
+/// frames) are `reversed` to stay counterclockwise when seen from outside.
+fn make_facets(
+    solid: &Solid,
+    transform: impl Fn(Point3d) -> Point3d,
+    reversed: bool,
+) -> Vec<Facet> {
+    let positions: Vec<Point3d> = solid.positions.iter().map(|p| transform(*p)).collect();
+    solid
+        .faces
+        .iter()
+        .map(|face| {
+            let [a, mut b, mut c] = face.map(|(v, _)| positions[v]);
+            if reversed {
+                (b, c) = (c, b);
+            }
+            let n = cross(sub(b, a), sub(c, a));
+            let normal = if length(n) > 0.0 {
+                normalize(n)
+            } else {
+                (0.0, 0.0, 0.0)
+            };
+            (normal, [a, b, c])
+        })
+        .collect()
+}
+
+/// Writes binary STL: 80 bytes of header, the facet count and 50 bytes per facet
+fn write_binary(writer: &mut impl Write, header: &str, facets: &[Facet]) -> std::io::Result<()> {
+    let mut bytes = [0u8; HEADER_SIZE];
+    // binary files must not start with "solid", as ASCII files do
+    let header = format!("plmat {}", header);
+    let length = header.len().min(HEADER_SIZE);
+    bytes[..length].copy_from_slice(&header.as_bytes()[..length]);
+    writer.write_all(&bytes)?;
+    let count = u32::try_from(facets.len())
+        .map_err(|_| std::io::Error::other("Too many facets for STL"))?;
+    writer.write_all(&count.to_le_bytes())?;
+    for (normal, triangle) in facets {
+        for (x, y, z) in std::iter::once(normal).chain(triangle) {
+            for value in [x, y, z] {
+                writer.write_all(&(*value as f32).to_le_bytes())?;
+            }
+        }
+        writer.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+/// Writes ASCII STL
+fn write_ascii(writer: &mut impl Write, name: &str, facets: &[Facet]) -> std::io::Result<()> {
+    writeln!(writer, "solid {}", name)?;
+    for ((nx, ny, nz), triangle) in facets {
+        writeln!(writer, "  facet normal {:e} {:e} {:e}", nx, ny, nz)?;
+        writeln!(writer, "    outer loop")?;
+        for (x, y, z) in triangle {
+            writeln!(writer, "      vertex {:e} {:e} {:e}", x, y, z)?;
+        }
+        writeln!(writer, "    endloop")?;
+        writeln!(writer, "  endfacet")?;
+    }
+    writeln!(writer, "endsolid {}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tetrahedron() -> Solid {
+        Solid {
+            positions: vec![
+                (0.0, 0.0, 0.0),
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (0.0, 0.0, 1.0),
+            ],
+            sources: vec![0, 1, 2, 3],
+            faces: vec![
+                [(0, 0), (2, 0), (1, 0)],
+                [(0, 0), (1, 0), (3, 0)],
+                [(0, 0), (3, 0), (2, 0)],
+                [(1, 0), (2, 0), (3, 0)],
+            ],
+            face_sources: vec![0, 1, 2, 3],
+            texture_coordinates: vec![],
+        }
+    }
+
+    #[test]
+    fn make_facets_t0() {
+        let solid = tetrahedron();
+        assert_eq!(solid.open_edges(), 0);
+        let facets = make_facets(&solid, |(x, y, z)| (x * 2.0, y * 2.0, z * 2.0), false);
+        assert_eq!(facets[0].0, (0.0, 0.0, -1.0));
+        assert_eq!(
+            facets[1].1,
+            [(0.0, 0.0, 0.0), (2.0, 0.0, 0.0), (0.0, 0.0, 2.0)]
+        );
+        let s = 1.0 / 3.0_f64.sqrt();
+        let (nx, ny, nz) = facets[3].0;
+        assert!((nx - s).abs() < 1e-12 && (ny - s).abs() < 1e-12 && (nz - s).abs() < 1e-12);
+    }
+
+    #[test]
+    fn make_facets_t1() {
+        // mirrored solids keep outward normals
+        let facets = make_facets(&tetrahedron(), |(x, y, z)| (x, -y, z), true);
+        assert_eq!(facets[0].0, (0.0, 0.0, -1.0));
+        let s = 1.0 / 3.0_f64.sqrt();
+        let (nx, ny, nz) = facets[3].0;
+        assert!((nx - s).abs() < 1e-12 && (ny + s).abs() < 1e-12 && (nz - s).abs() < 1e-12);
+    }
+
+    #[test]
+    fn write_binary_t0() {
+        let facets = make_facets(&tetrahedron(), |p| p, false);
+        let mut bytes = vec![];
+        write_binary(&mut bytes, "test (mm)", &facets).unwrap();
+        assert_eq!(bytes.len(), 84 + 50 * 4);
+        assert!(bytes.starts_with(b"plmat test (mm)"));
+        assert_eq!(u32::from_le_bytes(bytes[80..84].try_into().unwrap()), 4);
+        let float = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        // normal and the second vertex of the first facet
+        assert_eq!((float(84), float(88), float(92)), (0.0, 0.0, -1.0));
+        assert_eq!((float(108), float(112), float(116)), (0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn write_ascii_t0() {
+        let facets = make_facets(&tetrahedron(), |p| p, false);
+        let mut bytes = vec![];
+        write_ascii(&mut bytes, "test", &facets).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.starts_with("solid test\n  facet normal 0e0 0e0 -1e0\n    outer loop\n"));
+        assert_eq!(text.matches("vertex ").count(), 12);
+        assert!(text.ends_with("  endfacet\nendsolid test\n"));
+    }
+}
@@ -0,0 +1,153 @@
+//! # Model Surface
+//!
+//! This module places the displaced planet surface in output coordinates. It is
+//! shared by the mesh formats (OBJ, glTF, STL, PLY, 3MF, USD, X3D, Collada), so that they
+//! read the same radius, elevation scale, physical size, relief curves and
+//! coordinate frame.
+//!
+//! ## Configuration Parameters
+//!
+//! - `radius`: planet radius in metres (default: 6378000.0)
+//! - `scale`: elevation multiplier relative to the radius (default: 1.0),
+//!   replaced by the relief of the physical size if a `diameter` is given
+//! - see `units`, `relief` and `frame` for the other parameters
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::frame::*;
+use crate::model::relief::*;
+use crate::model::solid::*;
+use crate::model::split::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use crate::model::units::*;
+use std::path::Path;
+
+/// Default planet radius in metres
+pub const DEFAULT_RADIUS: f64 = 6378000.0;
+/// Default elevation scale
+pub const DEFAULT_SCALE: f64 = 1.0;
+
+/// Placement of the model surface
+#[derive(Debug, Clone, PartialEq)]
+pub struct Surface {
+    /// Planet radius in metres
+    pub radius: Height,
+    /// Elevation multiplier
+    pub scale: Height,
+    /// Optional physical size of the model
+    pub physical_size: Option<PhysicalSize>,
+    /// Coordinate frame of written vertices
+    pub frame: FrameOpts,
+    /// Relief curves
+    pub relief: ReliefOpts,
+}
+
+impl Surface {
+    /// Reads the placement of the surface from the settings
+    ///
+    /// The relief of the physical size depends on the highest elevation of the model.
+    pub fn from_settings(
+        settings: &Settings,
+        components: &ModelComponents,
+    ) -> Result<Self, ErrBox> {
+        let radius = settings.get_parameter_num("radius", DEFAULT_RADIUS)? as Height;
+        let physical_size = PhysicalSize::from_settings(settings)?;
+        let relief = ReliefOpts::from_settings(settings)?;
+        let scale = match &physical_size {
+            Some(size) => {
+                let max_height = components.heights.values().cloned().fold(0.0, Height::max);
+                size.relief_scale(radius, relief.apply(max_height))
+            }
+            None => settings.get_parameter_num("scale", DEFAULT_SCALE)? as Height,
+        };
+        let frame = FrameOpts::from_settings(settings)?;
+        if frame.is_ecef() && physical_size.is_some() {
+            return Err("'diameter' can't be used with ECEF coordinates".into());
+        }
+        Ok(Surface {
+            radius,
+            scale,
+            physical_size,
+            frame,
+            relief,
+        })
+    }
+
+    /// Checks that the printing and splitting options can be applied to the surface
+    ///
+    /// Their lengths (wall thickness, pin holes) are given in model units, while
+    /// ECEF coordinates are metres on the planet.
+    pub fn check_print_opts(
+        &self,
+        print_opts: Option<&PrintOpts>,
+        split_opts: Option<&SplitOpts>,
+    ) -> Result<(), ErrBox> {
+        if self.frame.is_ecef() && (print_opts.is_some() || split_opts.is_some()) {
+            return Err("Hollow and split models can't be written in ECEF coordinates".into());
+        }
+        Ok(())
+    }
+
+    /// Positions of the model vertices in the model frame
+    pub fn positions(&self, components: &ModelComponents) -> Result<Vec<Point3d>, ErrBox> {
+        let heights = &components.heights;
+        let size = match &self.physical_size {
+            Some(physical_size) => physical_size.coordinate_scale(),
+            None => 1.0,
+        };
+        Ok(components
+            .get_vertices()?
+            .iter()
+            .map(|(i, GeoPoint { lon, lat })| {
+                let height = self.relief.apply(heights.get(i).cloned().unwrap_or(0.0));
+                let (x, y, z) = self
+                    .frame
+                    .position(self.radius, self.scale, height, *lon, *lat);
+                (x * size, y * size, z * size)
+            })
+            .collect())
+    }
+
+    /// Makes the solid of the model surface
+    ///
+    /// Texture coordinates are kept only for texture models.
+    pub fn solid(
+        &self,
+        model_type: ModelType,
+        components: &ModelComponents,
+    ) -> Result<Solid, ErrBox> {
+        Solid::from_surface(
+            self.positions(components)?,
+            components.get_faces()?,
+            components.get_texture_mapping()?,
+            match model_type {
+                ModelType::Texture => components.get_texture_coordinates()?.clone(),
+                ModelType::Color => vec![],
+            },
+        )
+    }
+
+    /// Origin of output coordinates for the vertex positions
+    ///
+    /// The physical size of the model is reported for the written positions. The
+    /// origin of "ecef_local" coordinates is written to `{planet_name}_origin.yaml`.
+    pub fn origin(
+        &self,
+        positions: &[Point3d],
+        output_path: &Path,
+        planet_name: &str,
+    ) -> Result<Point3d, ErrBox> {
+        if let Some(physical_size) = &self.physical_size {
+            println!("{}", physical_size.report(positions));
+        }
+        let origin = self.frame.origin(positions);
+        if let Coordinates::EcefLocal(_) = self.frame.coordinates {
+            let origin_path = output_path
+                .join(format!("{}_origin", planet_name))
+                .with_extension("yaml");
+            std::fs::write(&origin_path, self.frame.origin_sidecar(origin))
+                .map_err(|err| format!("Can't write origin file {:?}: {}", origin_path, err))?;
+        }
+        Ok(origin)
+    }
+}
@@ -0,0 +1,729 @@
+//! # Tessellation Schemes
+//!
+//! This module provides alternative ways of tessellating the globe into a
+//! triangular mesh. Every scheme produces the same kind of data as
+//! `Model::create_modeldata` (vertices, faces and the mapping between texture
+//! points and vertices) together with the matching texture coordinates.
+//!
+//! ## Schemes
+//!
+//! - **Native**: the model's own `create_modeldata` (grid for X3D geospatial models)
+//! - **Octahedral**: latitude bands of the OBJ model
+//! - **Icosphere**: recursively subdivided icosahedron (geodesic sphere)
+//! - **Cube-sphere**: six subdivided cube faces projected to the sphere, each face
+//!   being a separate submesh with its own texture tile in a cubemap layout
+//! - **HEALPix**: centres of the equal-area HEALPix pixels (ring scheme),
+//!   triangulated between neighbouring rings
+//!
+//! ## Texture Points
+//!
+//! Faces refer to texture points, and texture points are mapped to vertices.
+//! The first texture point of every vertex has the same index as the vertex.
+//! Extra texture points are appended for vertices lying on the antimeridian
+//! seam (so that no triangle wraps around the texture) and for the poles
+//! (where the longitude, and so the `u` coordinate, is undefined).
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::obj::Obj;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Average number of model vertices per icosphere vertex, used for
+/// the default subdivision level
+const ICOSPHERE_BASE_VERTICES: f64 = 10.0;
+/// Maximal icosphere subdivision level (about 10 million vertices)
+const MAX_ICOSPHERE_SUBDIVISIONS: GeoPointIndex = 10;
+/// Maximal cube-sphere subdivisions of a face edge (about 25 million vertices)
+const MAX_CUBESPHERE_SUBDIVISIONS: GeoPointIndex = 2048;
+/// Tolerance used for pole detection, in degrees
+const POLE_EPSILON: Coord = 1e-9;
+/// Names of the cube-sphere faces in the cubemap order (+X, -X, +Y, -Y, +Z, -Z)
+pub const CUBE_FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+/// Cube faces as (major axis, `u` axis, `v` axis) in the cubemap convention
+const CUBE_FACE_AXES: [[(i64, i64, i64); 3]; 6] = [
+    [(1, 0, 0), (0, 0, -1), (0, -1, 0)],
+    [(-1, 0, 0), (0, 0, 1), (0, -1, 0)],
+    [(0, 1, 0), (1, 0, 0), (0, 0, 1)],
+    [(0, -1, 0), (1, 0, 0), (0, 0, -1)],
+    [(0, 0, 1), (1, 0, 0), (0, -1, 0)],
+    [(0, 0, -1), (-1, 0, 0), (0, -1, 0)],
+];
+
+/// Unit vector in the model coordinate system
+pub type Point3d = (Coord, Coord, Coord);
+
+/// Tessellation scheme of the model mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tessellation {
+    /// The model's own `create_modeldata` scheme
+    Native,
+    /// Octahedral latitude bands
+    Octahedral,
+    /// Icosahedron subdivided the given number of times
+    Icosphere(GeoPointIndex),
+    /// Cube with faces subdivided into a grid of the given size,
+    /// with optional equal-angle (tangent-adjusted) projection
+    CubeSphere(GeoPointIndex, bool),
+    /// HEALPix pixel centres with the given `nside` resolution
+    Healpix(GeoPointIndex),
+}
+
+/// Geometry of a tessellated model with its texture coordinates
+pub struct TessellationData {
+    /// Vertices, faces and texture points mapping
+    pub modeldata: ModelData,
+    /// Texture coordinates indexed by texture points
+    pub texture_coordinates: TextureCoordinates,
+    /// Optional named ranges of faces with their own materials
+    pub submeshes: Option<Submeshes>,
+}
+
+impl TessellationData {
+    pub fn create(modeldata: ModelData, texture_coordinates: TextureCoordinates) -> Self {
+        Self {
+            modeldata,
+            texture_coordinates,
+            submeshes: None,
+        }
+    }
+}
+
+impl Tessellation {
+    /// Reads the tessellation scheme from the settings
+    ///
+    /// The scheme is given by the `tessellation` parameter. The subdivision level
+    /// is taken from the command line, then from the `subdivisions` parameter,
+    /// and is derived from the model size otherwise.
+    pub fn from_settings(settings: &Settings, model_size: GeoPointIndex) -> Result<Self, ErrBox> {
+        let scheme = settings.get_parameter_str("tessellation", "octahedral")?;
+        match scheme.as_str() {
+            "octahedral" => Ok(Tessellation::Octahedral),
+            "icosphere" => Ok(Tessellation::Icosphere(get_subdivisions(
+                settings,
+                default_icosphere_subdivisions(model_size),
+                MAX_ICOSPHERE_SUBDIVISIONS,
+            )?)),
+            "cubesphere" => {
+                let subdivisions = get_subdivisions(
+                    settings,
+                    default_cubesphere_subdivisions(model_size),
+                    MAX_CUBESPHERE_SUBDIVISIONS,
+                )?;
+                let projection = settings.get_parameter_str("cube_projection", "gnomonic")?;
+                let equal_angle = match projection.as_str() {
+                    "gnomonic" => false,
+                    "equal_angle" => true,
+                    _ => {
+                        return Err(format!(
+                            "Unknown cube projection '{}' in the settings file",
+                            projection
+                        )
+                        .into());
+                    }
+                };
+                Ok(Tessellation::CubeSphere(subdivisions.max(1), equal_angle))
+            }
+            "healpix" => {
+                let nside =
+                    settings.get_parameter_num("nside", default_healpix_nside(model_size))?;
+                Ok(Tessellation::Healpix(nside.max(1)))
+            }
+            _ => Err(format!(
+                "Unknown tessellation scheme '{}' in the settings file",
+                scheme
+            )
+            .into()),
+        }
+    }
+
+    /// Creates vertices, faces and texture coordinates of the scheme
+    ///
+    /// The `Native` scheme has no data of its own and must be created by the model.
+    pub fn create_data(&self, model_size: GeoPointIndex) -> Result<TessellationData, ErrBox> {
+        match self {
+            Tessellation::Native => Err("Native tessellation must be created by the model".into()),
+            Tessellation::Octahedral => Ok(TessellationData::create(
+                Obj::create_modeldata(model_size, Obj::define_spacing(model_size)),
+                Obj::create_texture_coordinates(model_size),
+            )),
+            Tessellation::Icosphere(subdivisions) => Ok(create_icosphere(*subdivisions)),
+            Tessellation::CubeSphere(subdivisions, equal_angle) => {
+                Ok(create_cubesphere(*subdivisions, *equal_angle))
+            }
+            Tessellation::Healpix(nside) => Ok(create_healpix(*nside)),
+        }
+    }
+}
+
+/// Returns the subdivision level from the command line or the settings file
+///
+/// Levels above `max` are rejected, as the number of vertices grows too fast;
+/// the default derived from the model size is limited to `max`.
+fn get_subdivisions(
+    settings: &Settings,
+    default: GeoPointIndex,
+    max: GeoPointIndex,
+) -> Result<GeoPointIndex, ErrBox> {
+    let subdivisions = match settings.subdivisions {
+        Some(n) => n,
+        None => settings.get_parameter_num("subdivisions", default.min(max))?,
+    };
+    if subdivisions > max {
+        return Err(format!(
+            "Subdivisions {} of the tessellation are too many, the maximum is {}",
+            subdivisions, max
+        )
+        .into());
+    }
+    Ok(subdivisions)
+}
+
+/// Subdivision level giving roughly as many vertices as the octahedral scheme
+fn default_icosphere_subdivisions(model_size: GeoPointIndex) -> GeoPointIndex {
+    let ratio = (model_size * model_size) as f64 / ICOSPHERE_BASE_VERTICES;
+    if ratio <= 1.0 {
+        0
+    } else {
+        ratio.log(4.0).round() as GeoPointIndex
+    }
+}
+
+/// Face grid size giving roughly as many vertices as the octahedral scheme
+fn default_cubesphere_subdivisions(model_size: GeoPointIndex) -> GeoPointIndex {
+    ((model_size as f64) / 6f64.sqrt()).round() as GeoPointIndex
+}
+
+/// HEALPix resolution giving roughly as many vertices as the octahedral scheme
+fn default_healpix_nside(model_size: GeoPointIndex) -> GeoPointIndex {
+    ((model_size as f64) / 12f64.sqrt()).round() as GeoPointIndex
+}
+
+/// Converts geographic coordinates to a unit vector
+///
+/// Uses the same axes orientation as `calc_point3d`.
+pub fn geo_to_point3d(lon: Coord, lat: Coord) -> Point3d {
+    let phi = lon.to_radians();
+    let theta = lat.to_radians();
+    (
+        -phi.sin() * theta.cos(),
+        phi.cos() * theta.cos(),
+        theta.sin(),
+    )
+}
+
+/// Converts a vector to geographic coordinates
+///
+/// Longitude is normalized to [-180, 180) and set to zero at the poles.
+pub fn point3d_to_geo((x, y, z): Point3d) -> GeoPoint {
+    let r = (x * x + y * y + z * z).sqrt();
+    let lat = (z / r).clamp(-1.0, 1.0).asin().to_degrees();
+    let mut lon = (-x).atan2(y).to_degrees();
+    if lon >= 180.0 {
+        lon -= 360.0;
+    }
+    if 90.0 - lat.abs() < POLE_EPSILON {
+        lon = 0.0;
+    }
+    GeoPoint { lon, lat }
+}
+
+/// Makes all triangles counterclockwise when seen from outside of the sphere
+fn orient_outward(
+    points: &[Point3d],
+    triangles: &mut [(GeoPointIndex, GeoPointIndex, GeoPointIndex)],
+) {
+    for t in triangles.iter_mut() {
+        let (a, b, c) = (points[t.0], points[t.1], points[t.2]);
+        let (ux, uy, uz) = (b.0 - a.0, b.1 - a.1, b.2 - a.2);
+        let (vx, vy, vz) = (c.0 - a.0, c.1 - a.1, c.2 - a.2);
+        let n = (uy * vz - uz * vy, uz * vx - ux * vz, ux * vy - uy * vx);
+        let centroid = (a.0 + b.0 + c.0, a.1 + b.1 + c.1, a.2 + b.2 + c.2);
+        if n.0 * centroid.0 + n.1 * centroid.1 + n.2 * centroid.2 < 0.0 {
+            *t = (t.0, t.2, t.1);
+        }
+    }
+}
+
+/// Checks whether the point is a pole, where the longitude is undefined
+pub fn is_pole(gp: &GeoPoint) -> bool {
+    90.0 - gp.lat.abs() < POLE_EPSILON
+}
+
+/// Returns texture coordinates of the point in the equirectangular texture mapping
+pub fn equirectangular_uv(gp: &GeoPoint) -> (TextureCoordinate, TextureCoordinate) {
+    ((gp.lon + 180.0) / 360.0, (gp.lat + 90.0) / 180.0)
+}
+
+/// Returns texture coordinates of the point in the texture tile of a cube-sphere face
+///
+/// The face is given by its index in `CUBE_FACE_NAMES`, the point is inverted
+/// from the gnomonic or equal-angle projection of `create_cubesphere`.
+pub fn cube_face_uv(
+    face: usize,
+    p: Point3d,
+    equal_angle: bool,
+) -> (TextureCoordinate, TextureCoordinate) {
+    let dot = |(x, y, z): (i64, i64, i64)| x as Coord * p.0 + y as Coord * p.1 + z as Coord * p.2;
+    let [ma, su, sv] = CUBE_FACE_AXES[face];
+    let unproject = |c: Coord| {
+        if equal_angle {
+            c.atan() / std::f64::consts::FRAC_PI_4
+        } else {
+            c
+        }
+    };
+    let a = unproject(dot(su) / dot(ma));
+    let b = unproject(dot(sv) / dot(ma));
+    ((a + 1.0) / 2.0, (1.0 - b) / 2.0)
+}
+
+/// Creates texture points for a closed mesh with equirectangular texture mapping
+///
+/// Triangles are given by vertex indices. Returns faces given by texture
+/// point indices, mapping of texture points to vertices and texture coordinates.
+pub fn make_texture_points(
+    vertices: &Vertices,
+    triangles: &[(GeoPointIndex, GeoPointIndex, GeoPointIndex)],
+) -> (Faces, PointsMapping, TextureCoordinates) {
+    let uv = equirectangular_uv;
+
+    let mut texture_mapping: PointsMapping = HashMap::with_capacity(vertices.len());
+    let mut texture_coordinates: TextureCoordinates = Vec::with_capacity(vertices.len());
+    for (k, gp) in vertices {
+        texture_mapping.insert(*k, *k);
+        texture_coordinates.push(uv(gp));
+    }
+
+    // extra texture points: (vertex, quantized u) -> texture point
+    let mut extra_points: HashMap<(GeoPointIndex, i64), GeoPointIndex> = HashMap::new();
+    let mut faces: Faces = Vec::with_capacity(triangles.len());
+    for &(i0, i1, i2) in triangles {
+        let corners = [i0, i1, i2];
+        let gps = corners.map(|i| &vertices[&i]);
+        let mut us = gps.map(|gp| uv(gp).0);
+
+        let non_pole_us = || (0..3).filter(|&c| !is_pole(gps[c]));
+        let u_min = non_pole_us().map(|c| us[c]).fold(f64::INFINITY, f64::min);
+        let u_max = non_pole_us()
+            .map(|c| us[c])
+            .fold(f64::NEG_INFINITY, f64::max);
+        if u_max - u_min > 0.5 {
+            for c in non_pole_us() {
+                if us[c] < 0.5 {
+                    us[c] += 1.0;
+                }
+            }
+        }
+        let n = non_pole_us().count();
+        if n != 0 {
+            let u_mean = non_pole_us().map(|c| us[c]).sum::<f64>() / n as f64;
+            for c in 0..3 {
+                if is_pole(gps[c]) {
+                    us[c] = u_mean;
+                }
+            }
+        }
+
+        let mut face = [0; 3];
+        for c in 0..3 {
+            let k = corners[c];
+            let (u0, v0) = uv(gps[c]);
+            face[c] = if us[c] == u0 {
+                k
+            } else {
+                let key = (k, (us[c] * 1e9).round() as i64);
+                *extra_points.entry(key).or_insert_with(|| {
+                    let index = texture_coordinates.len();
+                    texture_coordinates.push((us[c], v0));
+                    texture_mapping.insert(index, k);
+                    index
+                })
+            };
+        }
+        faces.push((face[0], face[1], face[2]));
+    }
+
+    (faces, texture_mapping, texture_coordinates)
+}
+
+/// Creates a geodesic sphere by subdividing an icosahedron
+///
+/// The icosahedron has vertices at the poles. Every subdivision step splits each
+/// triangle into four, projecting the new vertices onto the sphere, so that
+/// triangles have nearly the same size everywhere.
+pub fn create_icosphere(subdivisions: GeoPointIndex) -> TessellationData {
+    let ring_lat = (0.5 as Coord).atan().to_degrees();
+    let mut points: Vec<Point3d> = Vec::with_capacity(10 * 4usize.pow(subdivisions as u32) + 2);
+    points.push(geo_to_point3d(0.0, 90.0));
+    for k in 0..5 {
+        points.push(geo_to_point3d(-180.0 + 72.0 * k as Coord, ring_lat));
+    }
+    for k in 0..5 {
+        points.push(geo_to_point3d(-144.0 + 72.0 * k as Coord, -ring_lat));
+    }
+    points.push(geo_to_point3d(0.0, -90.0));
+
+    let mut triangles = Vec::with_capacity(20);
+    for k in 0..5 {
+        let (u0, u1) = (1 + k, 1 + (k + 1) % 5);
+        let (l0, l1) = (6 + k, 6 + (k + 1) % 5);
+        triangles.push((0, u0, u1));
+        triangles.push((u0, l0, u1));
+        triangles.push((u1, l0, l1));
+        triangles.push((11, l1, l0));
+    }
+    orient_outward(&points, &mut triangles);
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(GeoPointIndex, GeoPointIndex), GeoPointIndex> = HashMap::new();
+        let mut midpoint = |a: GeoPointIndex, b: GeoPointIndex, points: &mut Vec<Point3d>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let (pa, pb) = (points[a], points[b]);
+                points.push(normalize(add(pa, pb)));
+                points.len() - 1
+            })
+        };
+        let mut subdivided = Vec::with_capacity(4 * triangles.len());
+        for &(a, b, c) in &triangles {
+            let ab = midpoint(a, b, &mut points);
+            let bc = midpoint(b, c, &mut points);
+            let ca = midpoint(c, a, &mut points);
+            subdivided.push((a, ab, ca));
+            subdivided.push((ab, b, bc));
+            subdivided.push((ca, bc, c));
+            subdivided.push((ab, bc, ca));
+        }
+        triangles = subdivided;
+    }
+
+    let vertices: Vertices = points
+        .iter()
+        .enumerate()
+        .map(|(k, p)| (k, point3d_to_geo(*p)))
+        .collect::<BTreeMap<_, _>>();
+    let (faces, texture_mapping, texture_coordinates) = make_texture_points(&vertices, &triangles);
+
+    TessellationData::create(
+        ModelData::create(vertices, faces, Some(texture_mapping)),
+        texture_coordinates,
+    )
+}
+
+/// Creates a cube-sphere with six faces subdivided into `n` x `n` grids
+///
+/// Grid points are projected from the cube to the sphere either directly
+/// (gnomonic projection) or after the equal-angle adjustment `tan(a * PI / 4)`,
+/// which makes cells of nearly the same size. Vertices on the cube edges are
+/// shared by adjacent faces, while texture points are not: every face is a submesh
+/// named after `CUBE_FACE_NAMES` and covers the whole [0, 1] texture square,
+/// oriented as a cubemap face (`v` pointing up in the texture image).
+pub fn create_cubesphere(n: GeoPointIndex, equal_angle: bool) -> TessellationData {
+    let ni = n as i64;
+    let project = |c: i64| {
+        let a = c as Coord / ni as Coord;
+        if equal_angle {
+            (a * std::f64::consts::FRAC_PI_4).tan()
+        } else {
+            a
+        }
+    };
+
+    // lattice points of the cube surface -> vertex
+    let mut lattice: HashMap<(i64, i64, i64), GeoPointIndex> = HashMap::new();
+    let mut points: Vec<Point3d> = Vec::with_capacity(6 * (n + 1) * (n + 1));
+    // (vertex, texture coordinates) of every face grid point
+    let mut grid_points = Vec::with_capacity(6 * (n + 1) * (n + 1));
+    for [ma, su, sv] in CUBE_FACE_AXES {
+        for j in 0..=ni {
+            for i in 0..=ni {
+                let (a, b) = (2 * i - ni, 2 * j - ni);
+                let key = (
+                    ni * ma.0 + a * su.0 + b * sv.0,
+                    ni * ma.1 + a * su.1 + b * sv.1,
+                    ni * ma.2 + a * su.2 + b * sv.2,
+                );
+                let vertex = *lattice.entry(key).or_insert_with(|| {
+                    points.push(normalize((project(key.0), project(key.1), project(key.2))));
+                    points.len() - 1
+                });
+                let uv = (
+                    i as TextureCoordinate / n as TextureCoordinate,
+                    1.0 - j as TextureCoordinate / n as TextureCoordinate,
+                );
+                grid_points.push((vertex, uv));
+            }
+        }
+    }
+
+    // the first texture point of a vertex has the vertex index
+    let num_vertices = points.len();
+    let mut texture_mapping: PointsMapping = HashMap::with_capacity(grid_points.len());
+    let mut texture_coordinates: TextureCoordinates = vec![(0.0, 0.0); grid_points.len()];
+    let mut texture_points = Vec::with_capacity(grid_points.len());
+    let mut next_extra = num_vertices;
+    for &(vertex, uv) in &grid_points {
+        let texture_point = if texture_mapping.contains_key(&vertex) {
+            next_extra += 1;
+            next_extra - 1
+        } else {
+            vertex
+        };
+        texture_mapping.insert(texture_point, vertex);
+        texture_coordinates[texture_point] = uv;
+        texture_points.push(texture_point);
+    }
+
+    let row = n + 1;
+    let mut faces: Faces = Vec::with_capacity(12 * n * n);
+    let mut submeshes: Submeshes = Vec::with_capacity(6);
+    for (f, name) in CUBE_FACE_NAMES.iter().enumerate() {
+        let start = faces.len();
+        let base = f * row * row;
+        for j in 0..n {
+            for i in 0..n {
+                let p00 = base + j * row + i;
+                let (p10, p01, p11) = (p00 + 1, p00 + row, p00 + row + 1);
+                for corners in [(p00, p10, p11), (p00, p11, p01)] {
+                    let mut triangle = [(
+                        grid_points[corners.0].0,
+                        grid_points[corners.1].0,
+                        grid_points[corners.2].0,
+                    )];
+                    orient_outward(&points, &mut triangle);
+                    let face = if triangle[0].1 == grid_points[corners.1].0 {
+                        corners
+                    } else {
+                        (corners.0, corners.2, corners.1)
+                    };
+                    faces.push((
+                        texture_points[face.0],
+                        texture_points[face.1],
+                        texture_points[face.2],
+                    ));
+                }
+            }
+        }
+        submeshes.push((name.to_string(), start..faces.len()));
+    }
+
+    let vertices: Vertices = points
+        .iter()
+        .enumerate()
+        .map(|(k, p)| (k, point3d_to_geo(*p)))
+        .collect::<BTreeMap<_, _>>();
+
+    TessellationData {
+        modeldata: ModelData::create(vertices, faces, Some(texture_mapping)),
+        texture_coordinates,
+        submeshes: Some(submeshes),
+    }
+}
+
+/// Creates a mesh with vertices in the centres of HEALPix pixels
+///
+/// Pixels of the HEALPix ring scheme have equal areas, so every vertex
+/// represents the same part of the sphere. There are `4 * nside - 1` rings of
+/// constant latitude, ordered from north to south, with pixels ordered by
+/// longitude (from 0 eastwards) inside a ring. Neighbouring rings are
+/// triangulated by merging their points in the order of longitude, and the
+/// four pixels of each polar ring close the pole caps with two triangles.
+pub fn create_healpix(nside: GeoPointIndex) -> TessellationData {
+    use std::f64::consts::PI;
+
+    let ns = nside as Coord;
+    let num_rings = 4 * nside - 1;
+    let mut points: Vec<Point3d> = Vec::with_capacity(12 * nside * nside);
+    // (first vertex, longitudes) of every ring
+    let mut rings: Vec<(GeoPointIndex, Vec<Coord>)> = Vec::with_capacity(num_rings);
+    for i in 1..=num_rings {
+        let ring = if i > 2 * nside { 4 * nside - i } else { i };
+        let (z, len, offset) = if ring < nside {
+            let z = 1.0 - (ring * ring) as Coord / (3.0 * ns * ns);
+            (z, 4 * ring, 0.5)
+        } else {
+            let z = 4.0 / 3.0 - 2.0 * ring as Coord / (3.0 * ns);
+            let shifted = (ring - nside + 1) % 2 == 1;
+            (z, 4 * nside, if shifted { 0.5 } else { 0.0 })
+        };
+        let z = if i > 2 * nside { -z } else { z };
+        let lat = z.asin().to_degrees();
+        let phis: Vec<Coord> = (0..len)
+            .map(|j| PI / 2.0 * (j as Coord + offset) / (len / 4) as Coord)
+            .collect();
+        let start = points.len();
+        for phi in &phis {
+            points.push(geo_to_point3d(phi.to_degrees(), lat));
+        }
+        rings.push((start, phis));
+    }
+
+    let mut triangles = Vec::with_capacity(2 * points.len());
+    for (start, _) in [rings.first(), rings.last()].into_iter().flatten() {
+        triangles.push((*start, start + 1, start + 2));
+        triangles.push((*start, start + 2, start + 3));
+    }
+    for pair in rings.windows(2) {
+        let ((start_a, phis_a), (start_b, phis_b)) = (&pair[0], &pair[1]);
+        let (m, n) = (phis_a.len(), phis_b.len());
+        let unwrapped = |phis: &Vec<Coord>, k: usize| {
+            if k < phis.len() {
+                phis[k]
+            } else {
+                phis[k - phis.len()] + 2.0 * PI
+            }
+        };
+        let (mut a, mut b) = (0, 0);
+        while a < m || b < n {
+            let a_next = unwrapped(phis_a, a + 1);
+            let b_next = unwrapped(phis_b, b + 1);
+            if b == n || (a < m && a_next <= b_next) {
+                triangles.push((start_a + a % m, start_a + (a + 1) % m, start_b + b % n));
+                a += 1;
+            } else {
+                triangles.push((start_a + a % m, start_b + (b + 1) % n, start_b + b % n));
+                b += 1;
+            }
+        }
+    }
+    orient_outward(&points, &mut triangles);
+
+    let vertices: Vertices = points
+        .iter()
+        .enumerate()
+        .map(|(k, p)| (k, point3d_to_geo(*p)))
+        .collect::<BTreeMap<_, _>>();
+    let (faces, texture_mapping, texture_coordinates) = make_texture_points(&vertices, &triangles);
+
+    TessellationData::create(
+        ModelData::create(vertices, faces, Some(texture_mapping)),
+        texture_coordinates,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::testing::*;
+
+    #[test]
+    fn create_icosphere_t0() {
+        let data = create_icosphere(0);
+        let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+        assert_eq!(vertices.len(), 12);
+        assert_eq!(faces.len(), 20);
+        assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn create_icosphere_t1() {
+        let data = create_icosphere(2);
+        let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+        assert_eq!(vertices.len(), 162);
+        assert_eq!(faces.len(), 320);
+        assert_eq!(
+            texture_mapping.as_ref().unwrap().len(),
+            data.texture_coordinates.len()
+        );
+        assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn icosphere_texture_seam() {
+        let data = create_icosphere(3);
+        let ModelData(_, faces, _) = &data.modeldata;
+        let tcs = &data.texture_coordinates;
+        for (t0, t1, t2) in faces {
+            let us = [tcs[*t0].0, tcs[*t1].0, tcs[*t2].0];
+            let u_min = us.iter().cloned().fold(f64::INFINITY, f64::min);
+            let u_max = us.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            assert!(u_max - u_min < 0.5);
+            assert!(u_min >= 0.0);
+        }
+    }
+
+    #[test]
+    fn create_cubesphere_t0() {
+        let data = create_cubesphere(1, false);
+        let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(faces.len(), 12);
+        assert_eq!(texture_mapping.as_ref().unwrap().len(), 24);
+        assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn create_cubesphere_t1() {
+        for equal_angle in [false, true] {
+            let data = create_cubesphere(4, equal_angle);
+            let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+            assert_eq!(vertices.len(), 6 * 4 * 4 + 2);
+            assert_eq!(faces.len(), 6 * 2 * 4 * 4);
+            assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+            let submeshes = data.submeshes.unwrap();
+            assert_eq!(submeshes.len(), 6);
+            assert_eq!(submeshes[0], ("px".to_string(), 0..32));
+            assert_eq!(submeshes[5], ("nz".to_string(), 160..192));
+        }
+    }
+
+    #[test]
+    fn cube_face_uv_t0() {
+        for equal_angle in [false, true] {
+            let data = create_cubesphere(4, equal_angle);
+            let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+            let pmap = texture_mapping.as_ref().unwrap();
+            for (face, (_, range)) in data.submeshes.unwrap().iter().enumerate() {
+                for (t0, t1, t2) in &faces[range.clone()] {
+                    for t in [t0, t1, t2] {
+                        let gp = &vertices[&pmap[t]];
+                        let (u, v) =
+                            cube_face_uv(face, geo_to_point3d(gp.lon, gp.lat), equal_angle);
+                        let (tu, tv) = data.texture_coordinates[*t];
+                        assert!((u - tu).abs() < 1e-9 && (v - tv).abs() < 1e-9);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn create_healpix_t0() {
+        for nside in [1, 2, 3, 8] {
+            let data = create_healpix(nside);
+            let ModelData(vertices, faces, texture_mapping) = &data.modeldata;
+            assert_eq!(vertices.len(), 12 * nside * nside);
+            assert_eq!(faces.len(), 24 * nside * nside - 4);
+            assert!(is_closed(faces, texture_mapping.as_ref().unwrap()));
+        }
+    }
+
+    #[test]
+    fn create_healpix_t1() {
+        // rings are ordered from north to south, pixels by longitude
+        let data = create_healpix(2);
+        let ModelData(vertices, _, _) = &data.modeldata;
+        let lats: Vec<Coord> = vertices.values().map(|gp| gp.lat).collect();
+        assert!(lats.windows(2).all(|w| w[0] >= w[1] - 1e-9));
+        assert!((vertices[&0].lat - (1.0 - 1.0 / 12.0 as Coord).asin().to_degrees()).abs() < 1e-9);
+        assert!((vertices[&0].lon - 45.0).abs() < 1e-9);
+        assert!((vertices[&1].lon - 135.0).abs() < 1e-9);
+        assert!((vertices[&2].lon + 135.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn point3d_geo_roundtrip() {
+        for (lon, lat) in [(-180.0, 0.0), (45.0, 30.0), (-90.0, -60.0), (179.0, 10.0)] {
+            let gp = point3d_to_geo(geo_to_point3d(lon, lat));
+            assert!((gp.lon - lon).abs() < 1e-9);
+            assert!((gp.lat - lat).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn default_icosphere_subdivisions_t0() {
+        assert_eq!(default_icosphere_subdivisions(2), 0);
+        assert_eq!(default_icosphere_subdivisions(16), 2);
+        assert_eq!(default_icosphere_subdivisions(64), 4);
+    }
+}
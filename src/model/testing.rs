@@ -0,0 +1,55 @@
+//! # Test Fixtures
+//!
+//! This module provides the meshes and checks shared by the tests of the mesh
+//! processing modules.
+use crate::common::types::*;
+use crate::model::solid::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use crate::model::vector::*;
+use std::collections::HashMap;
+
+/// Checks that every edge of the model mesh is shared by exactly two faces with
+/// opposite directions
+pub fn is_closed(faces: &Faces, texture_mapping: &PointsMapping) -> bool {
+    let mut edges: HashMap<(GeoPointIndex, GeoPointIndex), i32> = HashMap::new();
+    for (t0, t1, t2) in faces {
+        let (v0, v1, v2) = (
+            texture_mapping[t0],
+            texture_mapping[t1],
+            texture_mapping[t2],
+        );
+        for (a, b) in [(v0, v1), (v1, v2), (v2, v0)] {
+            *edges.entry((a, b)).or_insert(0) += 1;
+        }
+    }
+    edges
+        .iter()
+        .all(|((a, b), n)| *n == 1 && edges.get(&(*b, *a)) == Some(&1))
+}
+
+/// Solid of the unit sphere tessellated by the icosphere of 3 subdivisions
+pub fn surface() -> Solid {
+    let TessellationData {
+        modeldata: ModelData(vertices, faces, pmap),
+        texture_coordinates,
+        ..
+    } = create_icosphere(3);
+    let positions = vertices
+        .values()
+        .map(|gp| geo_to_point3d(gp.lon, gp.lat))
+        .collect();
+    Solid::from_surface(positions, &faces, &pmap.unwrap(), texture_coordinates).unwrap()
+}
+
+/// Volume enclosed by the solid, negative if faces are oriented inwards
+pub fn volume(solid: &Solid) -> Coord {
+    solid
+        .faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.map(|(v, _)| solid.positions[v]);
+            dot(a, cross(b, c)) / 6.0
+        })
+        .sum()
+}
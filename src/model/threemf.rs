@@ -0,0 +1,530 @@
+//! # 3MF Model Generation Module
+//!
+//! This module writes models as 3MF packages for color 3D printers (PolyJet,
+//! multi-material FDM). A package is an OPC zip container with the content types,
+//! the package relationships and the `3D/3dmodel.model` XML part.
+//!
+//! ## Features
+//!
+//! - **Print Units**: the `unit` of the model is the `units` of the physical size,
+//!   so a target `diameter` is required
+//! - **Colors**: **Color** models get a color group (materials extension) of the
+//!   distinct colors of the color profile, referenced by every triangle, either per
+//!   vertex (smooth) or per triangle (the average color of its vertices).
+//!   **Texture** models are written without colors
+//! - **Printing**: hollow solids (`print_wall_thickness`) and pieces (`split_parts`)
+//!   are written as separate objects of a single build, as for OBJ models. Pieces
+//!   are laid out in a row along the x axis by the transforms of build items
+//!
+//! Vertices are placed by the same parameters as OBJ vertices (see `surface`).
+//! 3MF objects must be closed, so every written solid is checked to have no open edges.
+//!
+//! ## Configuration Parameters
+//!
+//! - `color_mode`: "vertex" (default) or "triangle"
+//! - see `surface`, `solid` and `split` for the other parameters
+use crate::common::color::*;
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::frame::*;
+use crate::model::mask::*;
+use crate::model::solid::*;
+use crate::model::split::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::writer::Writer;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const MODEL_PATH: &str = "3D/3dmodel.model";
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/></Types>
+"#;
+const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/></Relationships>
+"#;
+const CORE_NAMESPACE: &str = "http://schemas.microsoft.com/3dmanufacturing/core/2015/02";
+const MATERIAL_NAMESPACE: &str = "http://schemas.microsoft.com/3dmanufacturing/material/2015/02";
+const COLOR_GROUP_ID: usize = 1;
+
+/// How colors are assigned to triangles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    /// A color for every triangle vertex, blended across the triangle
+    Vertex,
+    /// A single color for every triangle
+    Triangle,
+}
+
+impl ColorMode {
+    /// Reads the color mode from the settings
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings.get_parameter_str("color_mode", "vertex")?.as_str() {
+            "vertex" => Ok(ColorMode::Vertex),
+            "triangle" => Ok(ColorMode::Triangle),
+            s => Err(format!("Unknown color mode '{}'", s).into()),
+        }
+    }
+}
+
+/// Distinct colors of the model in the sRGB `#RRGGBB` notation
+#[derive(Debug, Default, PartialEq)]
+struct ColorGroup {
+    colors: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl ColorGroup {
+    /// Returns the index of the color, adding it if it's new
+    fn add(&mut self, color: RGB) -> usize {
+        let RGB(r, g, b) = color;
+        let hex = format!("#{:02X}{:02X}{:02X}", to_byte(r), to_byte(g), to_byte(b));
+        match self.index.get(&hex) {
+            Some(i) => *i,
+            None => {
+                self.colors.push(hex.clone());
+                self.index.insert(hex, self.colors.len() - 1);
+                self.colors.len() - 1
+            }
+        }
+    }
+}
+
+/// Converts a color component from [0, 1] to [0, 255]
+fn to_byte(c: ColorComponent) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Mesh object of the 3MF model
+#[derive(Debug, Default, PartialEq)]
+struct MeshObject {
+    /// Positions in output coordinates
+    positions: Vec<Point3d>,
+    /// Triangles of position indices
+    triangles: Vec<[usize; 3]>,
+    /// Indices of triangle vertex colors in the color group (empty without colors)
+    colors: Vec<[usize; 3]>,
+    /// Translation of the build item
+    offset: Point3d,
+}
+
+/// Gap between laid out objects relative to the widest object
+const LAYOUT_GAP: Coord = 0.1;
+
+/// Lays out objects in a row along the x axis, so that pieces don't overlap
+///
+/// The first object stays in place.
+fn lay_out(objects: &mut [MeshObject]) {
+    let extents: Vec<(Coord, Coord)> = objects
+        .iter()
+        .map(|object| {
+            object
+                .positions
+                .iter()
+                .fold((Coord::INFINITY, Coord::NEG_INFINITY), |(min, max), p| {
+                    (min.min(p.0), max.max(p.0))
+                })
+        })
+        .collect();
+    let gap = LAYOUT_GAP
+        * extents
+            .iter()
+            .map(|(min, max)| max - min)
+            .fold(0.0, Coord::max);
+    let mut x = extents.first().map_or(0.0, |(_, max)| *max);
+    for (object, (min, max)) in objects.iter_mut().zip(&extents).skip(1) {
+        let dx = x + gap - min;
+        object.offset = (dx, 0.0, 0.0);
+        x = max + dx;
+    }
+}
+
+/// 3MF model structure
+///
+/// This struct represents a 3D geospatial model in 3MF format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct ThreeMf<'a> {
+    model_type: ModelType,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    color_mode: ColorMode,
+    print_opts: Option<PrintOpts>,
+    split_opts: Option<SplitOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for ThreeMf<'a> {
+    /// Checks the color mode
+    fn options_check(settings: &'a Settings) -> Result<(), ErrBox> {
+        ColorMode::from_settings(settings).map(|_| ())
+    }
+
+    /// Builds and constructs a 3MF model instance
+    fn build_model(
+        model_type: ModelType,
+        _model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        if let Some(MaskOpts {
+            mode: MaskMode::Crop,
+            ..
+        }) = MaskOpts::from_settings(settings)?
+        {
+            return Err("Cropped models are open surfaces and can't be written to 3MF".into());
+        }
+        let surface = Surface::from_settings(settings, &components)?;
+        if surface.physical_size.is_none() {
+            return Err("3MF models need a target 'diameter' in print units".into());
+        }
+        let print_opts = PrintOpts::from_settings(settings)?;
+        let split_opts = SplitOpts::from_settings(settings)?;
+        surface.check_print_opts(print_opts.as_ref(), split_opts.as_ref())?;
+        Ok(ThreeMf {
+            model_type,
+            settings,
+            surface,
+            color_mode: ColorMode::from_settings(settings)?,
+            print_opts,
+            split_opts,
+            components,
+        })
+    }
+
+    /// Saves the model to the 3MF package
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let solid = match &self.print_opts {
+            Some(print_opts) => solid.hollow(print_opts)?,
+            None => solid,
+        };
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        let solids = match &self.split_opts {
+            Some(split_opts) => split(&solid, split_opts)?,
+            None => vec![solid],
+        };
+        let mut color_group = ColorGroup::default();
+        let mut objects = solids
+            .iter()
+            .map(|solid| self.make_object(solid, origin, &mut color_group))
+            .collect::<Result<Vec<_>, ErrBox>>()?;
+        lay_out(&mut objects);
+        let unit = self
+            .surface
+            .physical_size
+            .as_ref()
+            .map_or("millimeter", |physical_size| physical_size.units.name());
+
+        let result_path = Path::new(&output_path)
+            .join(planet_name)
+            .with_extension("3mf");
+        let file = File::create(&result_path)
+            .map_err(|err| format!("Can't create {:?}: {}", result_path, err))?;
+        write_package(file, planet_name, unit, &objects, &color_group.colors)
+            .map_err(|err| format!("Can't write 3mf file {:?}: {}", result_path, err).into())
+    }
+}
+
+impl<'a> ThreeMf<'a> {
+    /// Makes the mesh object of a closed solid, adding its colors to the color group
+    fn make_object(
+        &self,
+        solid: &Solid,
+        origin: Point3d,
+        color_group: &mut ColorGroup,
+    ) -> Result<MeshObject, ErrBox> {
+        let open_edges = solid.open_edges();
+        if open_edges > 0 {
+            return Err(format!(
+                "Solid isn't closed ({} open edges), 3MF needs closed solids",
+                open_edges
+            )
+            .into());
+        }
+        let colors = match self.model_type {
+            ModelType::Texture => None,
+            ModelType::Color => Some(self.components.get_colors()?),
+        };
+        let vertex_colors = match colors {
+            Some(colors) => solid
+                .sources
+                .iter()
+                .map(|source| {
+                    colors
+                        .get(source)
+                        .copied()
+                        .ok_or(format!("Missed color for point {}", source).into())
+                })
+                .collect::<Result<Vec<RGB>, ErrBox>>()?,
+            None => vec![],
+        };
+        let frame = &self.surface.frame;
+        let triangles = make_triangles(solid, frame.handedness == Handedness::Left);
+        let colors = if vertex_colors.is_empty() {
+            vec![]
+        } else {
+            triangles
+                .iter()
+                .map(|triangle| match self.color_mode {
+                    ColorMode::Vertex => triangle.map(|v| color_group.add(vertex_colors[v])),
+                    ColorMode::Triangle => {
+                        let [a, b, c] = triangle.map(|v| vertex_colors[v]);
+                        let average = RGB(
+                            (a.0 + b.0 + c.0) / 3.0,
+                            (a.1 + b.1 + c.1) / 3.0,
+                            (a.2 + b.2 + c.2) / 3.0,
+                        );
+                        [color_group.add(average); 3]
+                    }
+                })
+                .collect()
+        };
+        Ok(MeshObject {
+            positions: solid
+                .positions
+                .iter()
+                .map(|p| frame.transform(origin, *p))
+                .collect(),
+            triangles,
+            colors,
+            offset: (0.0, 0.0, 0.0),
+        })
+    }
+}
+
+/// Makes triangles of position indices of the solid
+///
+/// 3MF is right-handed, so triangles mirrored by the transform (left-handed
+/// frames) are `reversed` to stay counterclockwise when seen from outside.
+fn make_triangles(solid: &Solid, reversed: bool) -> Vec<[usize; 3]> {
+    solid
+        .faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.map(|(v, _)| v);
+            if reversed { [a, c, b] } else { [a, b, c] }
+        })
+        .collect()
+}
+
+/// Writes the 3MF package (OPC zip container) of the model
+fn write_package(
+    writer: impl Write + Seek,
+    title: &str,
+    unit: &str,
+    objects: &[MeshObject],
+    colors: &[String],
+) -> Result<(), ErrBox> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(writer);
+    zip.start_file("[Content_Types].xml", options)?;
+    zip.write_all(CONTENT_TYPES.as_bytes())?;
+    zip.start_file("_rels/.rels", options)?;
+    zip.write_all(RELATIONSHIPS.as_bytes())?;
+    zip.start_file(MODEL_PATH, options)?;
+    write_model(&mut zip, title, unit, objects, colors)?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Writes the 3D model part
+///
+/// The color group has the id 1, objects follow it.
+fn write_model(
+    writer: impl Write,
+    title: &str,
+    unit: &str,
+    objects: &[MeshObject],
+    colors: &[String],
+) -> std::io::Result<()> {
+    let mut writer = Writer::new(std::io::BufWriter::new(writer));
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    let mut model = BytesStart::new("model");
+    model.push_attribute(("unit", unit));
+    model.push_attribute(("xml:lang", "en-US"));
+    model.push_attribute(("xmlns", CORE_NAMESPACE));
+    model.push_attribute(("xmlns:m", MATERIAL_NAMESPACE));
+    writer.write_event(Event::Start(model))?;
+    for (name, value) in [("Title", title), ("Application", "plmat")] {
+        let mut metadata = BytesStart::new("metadata");
+        metadata.push_attribute(("name", name));
+        writer.write_event(Event::Start(metadata))?;
+        writer.write_event(Event::Text(BytesText::new(value)))?;
+        writer.write_event(Event::End(BytesEnd::new("metadata")))?;
+    }
+    writer.write_event(Event::Start(BytesStart::new("resources")))?;
+    if !colors.is_empty() {
+        let mut group = BytesStart::new("m:colorgroup");
+        group.push_attribute(("id", COLOR_GROUP_ID.to_string().as_str()));
+        writer.write_event(Event::Start(group))?;
+        for color in colors {
+            let mut elem = BytesStart::new("m:color");
+            elem.push_attribute(("color", color.as_str()));
+            writer.write_event(Event::Empty(elem))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("m:colorgroup")))?;
+    }
+    for (n, object) in objects.iter().enumerate() {
+        let mut elem = BytesStart::new("object");
+        elem.push_attribute(("id", (COLOR_GROUP_ID + n + 1).to_string().as_str()));
+        elem.push_attribute(("type", "model"));
+        writer.write_event(Event::Start(elem))?;
+        writer.write_event(Event::Start(BytesStart::new("mesh")))?;
+        writer.write_event(Event::Start(BytesStart::new("vertices")))?;
+        for (x, y, z) in &object.positions {
+            let mut elem = BytesStart::new("vertex");
+            elem.push_attribute(("x", (*x as f32).to_string().as_str()));
+            elem.push_attribute(("y", (*y as f32).to_string().as_str()));
+            elem.push_attribute(("z", (*z as f32).to_string().as_str()));
+            writer.write_event(Event::Empty(elem))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("vertices")))?;
+        writer.write_event(Event::Start(BytesStart::new("triangles")))?;
+        for (t, [v1, v2, v3]) in object.triangles.iter().enumerate() {
+            let mut elem = BytesStart::new("triangle");
+            elem.push_attribute(("v1", v1.to_string().as_str()));
+            elem.push_attribute(("v2", v2.to_string().as_str()));
+            elem.push_attribute(("v3", v3.to_string().as_str()));
+            if let Some([p1, p2, p3]) = object.colors.get(t) {
+                elem.push_attribute(("pid", COLOR_GROUP_ID.to_string().as_str()));
+                elem.push_attribute(("p1", p1.to_string().as_str()));
+                // a single property applies to the whole triangle
+                if p2 != p1 || p3 != p1 {
+                    elem.push_attribute(("p2", p2.to_string().as_str()));
+                    elem.push_attribute(("p3", p3.to_string().as_str()));
+                }
+            }
+            writer.write_event(Event::Empty(elem))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("triangles")))?;
+        writer.write_event(Event::End(BytesEnd::new("mesh")))?;
+        writer.write_event(Event::End(BytesEnd::new("object")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("resources")))?;
+    writer.write_event(Event::Start(BytesStart::new("build")))?;
+    for (n, object) in objects.iter().enumerate() {
+        let mut elem = BytesStart::new("item");
+        elem.push_attribute(("objectid", (COLOR_GROUP_ID + n + 1).to_string().as_str()));
+        if object.offset != (0.0, 0.0, 0.0) {
+            let (x, y, z) = object.offset;
+            let transform = format!("1 0 0 0 1 0 0 0 1 {} {} {}", x as f32, y as f32, z as f32);
+            elem.push_attribute(("transform", transform.as_str()));
+        }
+        writer.write_event(Event::Empty(elem))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("build")))?;
+    writer.write_event(Event::End(BytesEnd::new("model")))?;
+    writer.into_inner().flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    #[test]
+    fn color_group_t0() {
+        let mut group = ColorGroup::default();
+        assert_eq!(group.add(RGB(1.0, 0.0, 0.5)), 0);
+        assert_eq!(group.add(RGB(0.0, 0.0, 0.0)), 1);
+        assert_eq!(group.add(RGB(1.0, 0.0, 0.501)), 0);
+        assert_eq!(group.colors, vec!["#FF0080", "#000000"]);
+    }
+
+    #[test]
+    fn make_triangles_t0() {
+        let solid = Solid {
+            positions: vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+            sources: vec![0, 1, 2],
+            faces: vec![[(0, 0), (1, 0), (2, 0)]],
+            face_sources: vec![0],
+            texture_coordinates: vec![],
+        };
+        assert_eq!(make_triangles(&solid, false), vec![[0, 1, 2]]);
+        // mirrored solids keep counterclockwise triangles
+        assert_eq!(make_triangles(&solid, true), vec![[0, 2, 1]]);
+    }
+
+    #[test]
+    fn write_package_t0() {
+        let object = MeshObject {
+            positions: vec![
+                (0.0, 0.0, 0.0),
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (0.0, 0.0, 1.0),
+            ],
+            triangles: vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+            colors: vec![[0, 0, 0], [0, 1, 1], [1, 1, 1], [0, 1, 0]],
+            offset: (0.0, 0.0, 0.0),
+        };
+        let colors = vec!["#FF0000".to_string(), "#0000FF".to_string()];
+        let mut bytes = Cursor::new(vec![]);
+        write_package(&mut bytes, "test", "millimeter", &[object], &colors).unwrap();
+
+        let mut archive = ZipArchive::new(bytes).unwrap();
+        assert!(archive.by_name("[Content_Types].xml").is_ok());
+        assert!(archive.by_name("_rels/.rels").is_ok());
+        let mut model = String::new();
+        archive
+            .by_name(MODEL_PATH)
+            .unwrap()
+            .read_to_string(&mut model)
+            .unwrap();
+        assert!(model.contains(r#"<model unit="millimeter" xml:lang="en-US""#));
+        assert!(model.contains(r#"<metadata name="Title">test</metadata>"#));
+        assert!(model.contains(
+            r##"<m:colorgroup id="1"><m:color color="#FF0000"/><m:color color="#0000FF"/></m:colorgroup>"##
+        ));
+        assert!(model.contains(
+            r#"<object id="2" type="model"><mesh><vertices><vertex x="0" y="0" z="0"/>"#
+        ));
+        assert!(model.contains(r#"<triangle v1="0" v2="2" v3="1" pid="1" p1="0"/>"#));
+        assert!(model.contains(r#"<triangle v1="0" v2="1" v3="3" pid="1" p1="0" p2="1" p3="1"/>"#));
+        assert!(model.ends_with(r#"<build><item objectid="2"/></build></model>"#));
+    }
+
+    #[test]
+    fn lay_out_t0() {
+        let object = |x0: Coord, x1: Coord| MeshObject {
+            positions: vec![(x0, 0.0, 0.0), (x1, 1.0, 0.0)],
+            triangles: vec![],
+            colors: vec![],
+            offset: (0.0, 0.0, 0.0),
+        };
+        let mut objects = vec![object(-10.0, 0.0), object(-10.0, 0.0), object(0.0, 5.0)];
+        lay_out(&mut objects);
+        assert_eq!(objects[0].offset, (0.0, 0.0, 0.0));
+        assert_eq!(objects[1].offset, (11.0, 0.0, 0.0));
+        assert_eq!(objects[2].offset, (12.0, 0.0, 0.0));
+
+        let colors = vec![];
+        let mut bytes = Cursor::new(vec![]);
+        write_package(&mut bytes, "test", "millimeter", &objects, &colors).unwrap();
+        let mut model = String::new();
+        ZipArchive::new(bytes)
+            .unwrap()
+            .by_name(MODEL_PATH)
+            .unwrap()
+            .read_to_string(&mut model)
+            .unwrap();
+        assert!(model.contains(r#"<item objectid="3" transform="1 0 0 0 1 0 0 0 1 11 0 0"/>"#));
+    }
+}
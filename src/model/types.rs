@@ -48,9 +48,17 @@ use crate::common::settings::*;
 use crate::common::types::*;
 use crate::input::dem::*;
 use crate::input::types::*;
-use std::collections::BTreeMap;
+use crate::model::edits::*;
+use crate::model::erosion::*;
+use crate::model::filter::*;
+use crate::model::mask::*;
+use crate::model::obj::Obj;
+use crate::model::refine::*;
+use crate::model::simplify::*;
+use crate::model::tessellation::*;
 use std::collections::HashMap;
-use std::ops::DerefMut;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Mutex;
 use std::thread;
@@ -77,6 +85,9 @@ pub struct ModelComponents {
     /// Optional color data for each vertex, used in color models to
     /// provide visual representation of elevation values
     pub colors: Option<Colors>,
+    /// Vertices whose elevations were sampled from source data rather than
+    /// filled with the sea level for nodata cells and missing tiles
+    pub sampled: Sampled,
     /// Optional UV coordinates for texture mapping, used
     /// in texture models to map texture images onto the 3D surface    
     pub texture_coordinates: Option<TextureCoordinates>,
@@ -89,6 +100,9 @@ pub struct ModelComponents {
     /// Optional triangular faces that define the connectivity of vertices
     /// in the 3D mesh, creating the surface structure of the model
     pub faces: Option<Faces>,
+    /// Optional named ranges of faces, written as separate submeshes
+    /// with their own materials
+    pub submeshes: Option<Submeshes>,
 }
 
 impl ModelComponents {
@@ -143,10 +157,18 @@ pub type Heights = BTreeMap<GeoPointIndex, Height>;
 /// Key: GeoPointIndex, Value: RGB color value
 pub type Colors = BTreeMap<GeoPointIndex, RGB>;
 
+/// Type alias for the set of vertices with elevations sampled from source data
+/// (the other vertices are filled with the sea level)
+pub type Sampled = BTreeSet<GeoPointIndex>;
+
 /// Type alias for texture coordinates data using vector of tuples
 /// Each tuple represents (u, v) texture coordinates
 pub type TextureCoordinates = Vec<(TextureCoordinate, TextureCoordinate)>;
 
+/// Type alias for named ranges of faces using vector of tuples
+/// Each tuple represents (name, range of indices in Faces)
+pub type Submeshes = Vec<(String, Range<usize>)>;
+
 /// Container for model points data including vertices and optional point mapping
 pub struct ModelData(pub Vertices, pub Faces, pub Option<PointsMapping>);
 
@@ -191,23 +213,55 @@ pub fn load_tile_data<'a>(
 /// accessed by multiple threads during the model creation process. It's wrapped in
 /// a `Mutex` to ensure thread-safe access and prevent data races when multiple
 /// threads update the model's elevation and color information simultaneously.
+/// Threads collect the values of a tile in their own instance first, which is
+/// then appended to the shared one.
+#[derive(Default)]
 pub struct MutexStruct {
     heights: Heights,
     colors: Colors,
+    sampled: Sampled,
+}
+
+impl MutexStruct {
+    /// Moves all values of `other` to this instance
+    fn append(&mut self, other: &mut MutexStruct) {
+        self.heights.append(&mut other.heights);
+        self.colors.append(&mut other.colors);
+        self.sampled.append(&mut other.sampled);
+    }
 }
 
 /// Trait defining the interface for model creation and management
 /// This trait provides methods for creating different types of models (texture/color)
 /// and managing their data processing
+///
+/// The mesh methods default to the octahedral mesh of OBJ models with the
+/// tessellation scheme from the settings, which is shared by the mesh formats.
 pub trait Model<'a> {
     /// Defines the spacing parameter for model creation based on model size
-    fn define_spacing(model_size: GeoPointIndex) -> Coord;
+    fn define_spacing(model_size: GeoPointIndex) -> Coord {
+        Obj::define_spacing(model_size)
+    }
 
     /// Defines a valid model size, ensuring it meets minimum requirements
-    fn make_valid_model_size(model_size: Option<GeoPointIndex>) -> GeoPointIndex;
+    fn make_valid_model_size(model_size: Option<GeoPointIndex>) -> GeoPointIndex {
+        Obj::make_valid_model_size(model_size)
+    }
 
     /// Creates geographic points and Faces for the model
-    fn create_modeldata(model_size: GeoPointIndex, spacing: Coord) -> ModelData;
+    fn create_modeldata(model_size: GeoPointIndex, spacing: Coord) -> ModelData {
+        Obj::create_modeldata(model_size, spacing)
+    }
+
+    /// Defines the tessellation scheme of the model mesh
+    ///
+    /// By default, the scheme is read from the `tessellation` parameter.
+    fn define_tessellation(
+        settings: &'a Settings,
+        model_size: GeoPointIndex,
+    ) -> Result<Tessellation, ErrBox> {
+        Tessellation::from_settings(settings, model_size)
+    }
 
     /// Returns number of points in the model
     fn num_model_vertices(_: GeoPointIndex, vertices: &Vertices) -> GeoPointIndex {
@@ -215,8 +269,8 @@ pub trait Model<'a> {
     }
 
     /// Creates texture coordinates for the model
-    fn create_texture_coordinates(_model_size: GeoPointIndex) -> TextureCoordinates {
-        vec![]
+    fn create_texture_coordinates(model_size: GeoPointIndex) -> TextureCoordinates {
+        Obj::create_texture_coordinates(model_size)
     }
 
     /// Creates mapping between geographic points and tiles
@@ -273,8 +327,8 @@ pub trait Model<'a> {
     /// * `tile_id` - Identifier for the DEM tile being processed
     /// * `tile_vertices` - Vector of vertex indices and geographic points that fall within this tile
     /// * `mutex` - Thread-safe mutex protecting shared model data (elevations and colors)
-    /// * `tile_heights` - Mutable reference to store elevation values for vertices in this tile
-    /// * `tile_colors` - Mutable reference to store color values for vertices in this tile
+    /// * `tile_data` - Mutable reference to store elevation values, color values and
+    ///   vertices with valid data of this tile
     ///
     /// # Processing Flow
     /// 1. Loads the DEM tile data for the given tile_id
@@ -301,8 +355,7 @@ pub trait Model<'a> {
         tile_id: TileID,
         tile_vertices: &Vec<(usize, &GeoPoint)>,
         mutex: &Mutex<MutexStruct>,
-        tile_heights: &mut Heights,
-        tile_colors: &mut Colors,
+        tile_data: &mut MutexStruct,
     ) {
         let load_result =
             load_tile_data(&settings.data_source_dir, &data_source_name, opts, &tile_id);
@@ -313,26 +366,29 @@ pub trait Model<'a> {
                 for (k, geo_point) in tile_vertices {
                     match dem_tile.calc_height(geo_point) {
                         None => (), // Geopoint is not in the tile
-                        Some(h) => match model_type {
-                            ModelType::Texture => {
-                                (*tile_heights).insert(*k, h);
+                        Some(h) => {
+                            if dem_tile.has_data(geo_point) {
+                                tile_data.sampled.insert(*k);
                             }
-                            ModelType::Color => {
-                                match color_mapping.get_color(h.floor() as HeightInt) {
-                                    Ok(c) => {
-                                        (*tile_colors).insert(*k, c);
-                                        (*tile_heights).insert(*k, h);
+                            match model_type {
+                                ModelType::Texture => {
+                                    tile_data.heights.insert(*k, h);
+                                }
+                                ModelType::Color => {
+                                    match color_mapping.get_color(h.floor() as HeightInt) {
+                                        Ok(c) => {
+                                            tile_data.colors.insert(*k, c);
+                                            tile_data.heights.insert(*k, h);
+                                        }
+                                        Err(err) => eprintln!("{}", err),
                                     }
-                                    Err(err) => eprintln!("{}", err),
                                 }
                             }
-                        },
+                        }
                     }
                 }
                 let mut ms = mutex.lock().unwrap();
-                let MutexStruct { heights, colors } = ms.deref_mut();
-                heights.append(tile_heights);
-                colors.append(tile_colors);
+                ms.append(tile_data);
                 drop(ms);
             }
         }
@@ -355,13 +411,20 @@ pub trait Model<'a> {
     /// 2. **Model Size and Spacing Calculation**: Determines the appropriate model size and
     ///    vertex spacing based on input parameters
     /// 3. **Model Point Generation**: Creates the geographic points and triangular faces that
-    ///    define the 3D model structure
+    ///    define the 3D model structure, using the scheme from `define_tessellation`,
+    ///    refined around the focus region if one is given (see `refine_focus`)
     /// 4. **Tile Mapping**: Associates geographic points with DEM tiles for efficient processing
     /// 5. **Parallel Tile Processing**: Distributes tile processing across multiple threads:
     ///    - Each thread processes tiles in a work-stealing pattern
     ///    - For each tile, loads DEM data and calculates elevation values for vertices
     ///    - Updates shared model data structures through thread-safe mutex operations
-    /// 6. **Model Construction**: Builds the final model using the `build_model` method
+    /// 6. **Edits**: Optionally applies the terrain edit layer (see `edits`)
+    /// 7. **Filtering**: Optionally smooths the elevations (see `filter`)
+    /// 8. **Erosion**: Optionally erodes the elevations (see `erosion`)
+    /// 9. **Masking**: Optionally flattens or recolors the model outside of the mask
+    ///    (see `mask`), cropped meshes are cut right after their generation
+    /// 10. **Simplification**: Optionally decimates the mesh (see `simplify`)
+    /// 11. **Model Construction**: Builds the final model using the `build_model` method
     ///
     /// # Thread Safety
     /// The function uses a `Mutex` to protect shared model data structures (elevations and colors)
@@ -404,12 +467,50 @@ pub trait Model<'a> {
         let model_size = Self::make_valid_model_size(settings.model_size);
         let spacing = Self::define_spacing(model_size);
 
-        let ModelData(vertices, faces, texture_mapping) =
-            Self::create_modeldata(model_size, spacing);
+        let tessellation = Self::define_tessellation(settings, model_size)?;
+        let tessellation_data = match tessellation {
+            Tessellation::Native => TessellationData::create(
+                Self::create_modeldata(model_size, spacing),
+                Self::create_texture_coordinates(model_size),
+            ),
+            _ => tessellation.create_data(model_size)?,
+        };
+        let tessellation_data = match FocusOpts::from_settings(settings)? {
+            Some(focus_opts) => refine_focus(tessellation_data, &tessellation, &focus_opts)?,
+            None => tessellation_data,
+        };
+        let mask_opts = MaskOpts::from_settings(settings)?;
+        if let (
+            Some(MaskOpts {
+                mode: MaskMode::Recolor(_),
+                ..
+            }),
+            ModelType::Texture,
+        ) = (&mask_opts, model_type)
+        {
+            return Err("Mask recoloring requires a color model".into());
+        }
+        // elevation grids can't be cropped, they are flattened after sampling
+        let tessellation_data = match &mask_opts {
+            Some(
+                mask_opts @ MaskOpts {
+                    mode: MaskMode::Crop,
+                    ..
+                },
+            ) if !tessellation_data.modeldata.1.is_empty() => {
+                crop_tessellation(tessellation_data, mask_opts)?
+            }
+            _ => tessellation_data,
+        };
+        let TessellationData {
+            modeldata: ModelData(vertices, faces, texture_mapping),
+            texture_coordinates: tessellation_texture_coordinates,
+            submeshes,
+        } = tessellation_data;
         let vertices_tiles = Self::create_vertices_tiles(&opts, &vertices);
 
         let texture_coordinates = match model_type {
-            ModelType::Texture => Some(Self::create_texture_coordinates(model_size)),
+            ModelType::Texture => Some(tessellation_texture_coordinates),
             ModelType::Color => None,
         };
 
@@ -450,14 +551,14 @@ pub trait Model<'a> {
         let mutex = Mutex::new(MutexStruct {
             heights: heights,
             colors: colors,
+            sampled: BTreeSet::new(),
         });
 
         thread::scope(|scope| {
             for _job in 1..=settings.jobs {
                 scope.spawn(|| {
                     while let Some(tile_id) = TileID::next(tiles_limit) {
-                        let mut tile_heights: Heights = BTreeMap::new();
-                        let mut tile_colors: Colors = BTreeMap::new();
+                        let mut tile_data = MutexStruct::default();
                         match vertices_tiles.get(&tile_id) {
                             Some(tile_vertices) => Self::calc_tile(
                                 model_type,
@@ -468,8 +569,7 @@ pub trait Model<'a> {
                                 tile_id,
                                 tile_vertices,
                                 &mutex,
-                                &mut tile_heights,
-                                &mut tile_colors,
+                                &mut tile_data,
                             ),
                             None => (),
                         };
@@ -479,22 +579,88 @@ pub trait Model<'a> {
         });
 
         let MutexStruct {
-            heights: heights_ready,
-            colors: colors_,
+            heights: mut heights_ready,
+            colors: mut colors_,
+            sampled,
         } = mutex
             .into_inner()
             .map_err(|err| format!("Failed to acquire mutex lock: {}", err))?;
 
-        let components = ModelComponents {
+        let edit_opts = EditOpts::from_settings(settings)?;
+        if let Some(edit_opts) = &edit_opts {
+            edit_heights(&mut heights_ready, &vertices, edit_opts);
+        }
+        let filter_opts = FilterOpts::from_settings(settings)?;
+        if let Some(filter_opts) = &filter_opts {
+            filter_heights(&mut heights_ready, &vertices, filter_opts, settings.jobs);
+        }
+        let erosion_opts = ErosionOpts::from_settings(settings)?;
+        if let Some(erosion_opts) = &erosion_opts {
+            erode_heights(
+                &mut heights_ready,
+                &vertices,
+                &faces,
+                texture_mapping.as_ref(),
+                opts.get_sea_level() as Height,
+                erosion_opts,
+            )?;
+        }
+        let mask_level = match &mask_opts {
+            Some(MaskOpts {
+                mode: MaskMode::Flatten(level),
+                ..
+            }) => Some(level.unwrap_or(opts.get_sea_level() as Height)),
+            Some(MaskOpts {
+                mode: MaskMode::Crop,
+                ..
+            }) if faces.is_empty() => Some(opts.get_sea_level() as Height),
+            _ => None,
+        };
+        if let (Some(mask_opts), Some(level)) = (&mask_opts, mask_level) {
+            mask_heights(&mut heights_ready, &vertices, mask_opts, level);
+        }
+        if let (ModelType::Color, true) = (
+            model_type,
+            edit_opts.is_some()
+                || filter_opts.is_some()
+                || erosion_opts.is_some()
+                || mask_level.is_some(),
+        ) {
+            for (k, h) in heights_ready.iter() {
+                colors_.insert(*k, color_mapping.get_color(h.floor() as HeightInt)?);
+            }
+        }
+        if let Some(
+            mask_opts @ MaskOpts {
+                mode: MaskMode::Recolor(color),
+                ..
+            },
+        ) = &mask_opts
+        {
+            mask_colors(
+                &mut colors_,
+                &vertices,
+                mask_opts,
+                color.unwrap_or(default_color),
+            );
+        }
+
+        let mut components = ModelComponents {
             spacing,
             heights: heights_ready,
             colors: Some(colors_),
+            sampled,
             texture_coordinates,
             vertices: Some(vertices),
             texture_mapping,
             faces: Some(faces),
+            submeshes,
         };
 
+        if let Some(simplify_opts) = SimplifyOpts::from_settings(settings)? {
+            simplify(&mut components, &simplify_opts)?;
+        }
+
         Self::build_model(model_type, model_size, settings, components)
     }
 
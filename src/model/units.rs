@@ -0,0 +1,197 @@
+//! # Physical Units
+//!
+//! This module maps model coordinates to physical sizes for printing. By default
+//! models are written around a unit sphere, and the `scale` parameter multiplies
+//! elevations relative to the planet radius. With a target `diameter` the model
+//! is written in physical units, and relief can be given as the physical height
+//! of the highest point.
+//!
+//! ## Configuration Parameters
+//!
+//! - `diameter`: target diameter of the sea level sphere, enables physical units
+//! - `units`: units of the output coordinates, "mm" (default), "cm", "m" or "in"
+//! - `relief_max_height`: physical height of the highest point above sea level
+//! - `exaggeration`: relief exaggeration relative to the true scale (default: `scale`)
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::surface::DEFAULT_SCALE;
+use crate::model::tessellation::Point3d;
+
+/// Units of output coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    Millimetres,
+    Centimetres,
+    Metres,
+    Inches,
+}
+
+impl Units {
+    /// Parses units by their symbol
+    pub fn from_symbol(symbol: &str) -> Result<Self, ErrBox> {
+        match symbol {
+            "mm" => Ok(Units::Millimetres),
+            "cm" => Ok(Units::Centimetres),
+            "m" => Ok(Units::Metres),
+            "in" => Ok(Units::Inches),
+            _ => Err(format!("Unknown units '{}'", symbol).into()),
+        }
+    }
+
+    /// Symbol of units
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Units::Millimetres => "mm",
+            Units::Centimetres => "cm",
+            Units::Metres => "m",
+            Units::Inches => "in",
+        }
+    }
+
+    /// Name of units in 3MF and Collada files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Units::Millimetres => "millimeter",
+            Units::Centimetres => "centimeter",
+            Units::Metres => "meter",
+            Units::Inches => "inch",
+        }
+    }
+
+    /// Length of the unit in metres
+    pub fn metres(&self) -> Coord {
+        match self {
+            Units::Millimetres => 0.001,
+            Units::Centimetres => 0.01,
+            Units::Metres => 1.0,
+            Units::Inches => 0.0254,
+        }
+    }
+}
+
+/// Relief of the physical model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relief {
+    /// Height of the highest point above sea level in output units
+    MaxHeight(Coord),
+    /// Exaggeration relative to the true scale
+    Exaggeration(Height),
+}
+
+/// Physical size of the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhysicalSize {
+    /// Units of output coordinates
+    pub units: Units,
+    /// Diameter of the sea level sphere
+    pub diameter: Coord,
+    /// Relief
+    pub relief: Relief,
+}
+
+impl PhysicalSize {
+    /// Reads the physical size from the settings
+    ///
+    /// Returns `None` if no target diameter is given.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ErrBox> {
+        let diameter = settings.get_parameter_num("diameter", 0.0)?;
+        if diameter <= 0.0 {
+            return Ok(None);
+        }
+        let units = Units::from_symbol(&settings.get_parameter_str("units", "mm")?)?;
+        let max_height = settings.get_parameter_num("relief_max_height", 0.0)?;
+        let relief = if max_height > 0.0 {
+            Relief::MaxHeight(max_height)
+        } else {
+            let scale = settings.get_parameter_num("scale", DEFAULT_SCALE)?;
+            Relief::Exaggeration(settings.get_parameter_num("exaggeration", scale)?)
+        };
+        Ok(Some(PhysicalSize {
+            units,
+            diameter,
+            relief,
+        }))
+    }
+
+    /// Size of output coordinates per planet radius
+    pub fn coordinate_scale(&self) -> Coord {
+        self.diameter / 2.0
+    }
+
+    /// Elevation multiplier (the `scale` parameter) giving the relief
+    ///
+    /// `max_height` is the highest elevation of the model in metres.
+    pub fn relief_scale(&self, radius: Height, max_height: Height) -> Height {
+        match self.relief {
+            Relief::Exaggeration(exaggeration) => exaggeration,
+            Relief::MaxHeight(height) if max_height > 0.0 => {
+                height / self.coordinate_scale() * radius / max_height
+            }
+            Relief::MaxHeight(_) => DEFAULT_SCALE,
+        }
+    }
+
+    /// Describes the physical dimensions of the model with given vertex positions
+    pub fn report(&self, positions: &[Point3d]) -> String {
+        let mut min = (Coord::INFINITY, Coord::INFINITY, Coord::INFINITY);
+        let mut max = (
+            Coord::NEG_INFINITY,
+            Coord::NEG_INFINITY,
+            Coord::NEG_INFINITY,
+        );
+        let mut r_max: Coord = 0.0;
+        for &(x, y, z) in positions {
+            min = (min.0.min(x), min.1.min(y), min.2.min(z));
+            max = (max.0.max(x), max.1.max(y), max.2.max(z));
+            r_max = r_max.max((x * x + y * y + z * z).sqrt());
+        }
+        let symbol = self.units.symbol();
+        format!(
+            "Model size: {:.2} x {:.2} x {:.2} {}, sea level diameter {:.2} {}, highest relief {:.2} {}",
+            max.0 - min.0,
+            max.1 - min.1,
+            max.2 - min.2,
+            symbol,
+            self.diameter,
+            symbol,
+            (r_max - self.coordinate_scale()).max(0.0),
+            symbol,
+        )
+    }
+}
+
+/// Radius of the sea level sphere in output units
+pub fn output_radius(settings: &Settings) -> Result<Coord, ErrBox> {
+    Ok(match PhysicalSize::from_settings(settings)? {
+        Some(size) => size.coordinate_scale(),
+        None => 1.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relief_scale_t0() {
+        let size = PhysicalSize {
+            units: Units::Millimetres,
+            diameter: 150.0,
+            relief: Relief::MaxHeight(2.0),
+        };
+        let radius = 6378000.0;
+        let scale = size.relief_scale(radius, 8848.0);
+        // highest point rises 2 mm above the 75 mm sea level sphere
+        let height = size.coordinate_scale() * scale * 8848.0 / radius;
+        assert!((height - 2.0).abs() < 1e-9);
+        assert!((scale - 19.22).abs() < 0.01);
+    }
+
+    #[test]
+    fn units_t0() {
+        for symbol in ["mm", "cm", "m", "in"] {
+            assert_eq!(Units::from_symbol(symbol).unwrap().symbol(), symbol);
+        }
+        assert!(Units::from_symbol("ft").is_err());
+    }
+}
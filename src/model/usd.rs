@@ -0,0 +1,513 @@
+//! # USD Model Generation Module
+//!
+//! This module writes models as ASCII USD stages (`.usda`) for DCC pipelines
+//! (Blender, Houdini, Omniverse).
+//!
+//! ## Features
+//!
+//! - **Mesh Prim**: `/Planet/Surface` with `points`, `faceVertexIndices` and,
+//!   with one index per vertex (see `mesh`), vertex interpolated `normals`,
+//!   `primvars:displayColor` (**Color** models) and `primvars:st` (**Texture** models)
+//! - **Materials**: `UsdPreviewSurface` materials reading the texture `texture_uri`
+//!   through `UsdUVTexture`, or the display colors. Submeshes (cube-sphere faces) are
+//!   `GeomSubset`s bound to their own materials with textures named as in OBJ materials
+//! - **Stage Metadata**: `upAxis` of the frame and `metersPerUnit` of physical units
+//!   or ECEF coordinates; left-handed frames set the mesh `orientation` to `leftHanded`
+//! - **Planet Metadata**: the planet name, radius, elevation scale, model size and
+//!   data source are written to `customData` of the planet prim
+//!
+//! Vertices are placed by the same parameters as OBJ vertices (see `surface`).
+//!
+//! ## Configuration Parameters
+//!
+//! - `texture_uri`: texture image of texture models (default: "texture.png")
+//! - `normals`, `crease_angle`: see `normals`
+use crate::common::color::*;
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::model::frame::*;
+use crate::model::mesh::*;
+use crate::model::normals::*;
+use crate::model::obj::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const DEFAULT_TEXTURE_URI: &str = "texture.png";
+const MATERIALS_PATH: &str = "/Planet/Materials";
+
+/// Stage-level data of the USD model
+#[derive(Debug, Clone, PartialEq)]
+struct Stage {
+    /// Up axis of the stage
+    up: UpAxis,
+    /// Metres per coordinate unit, if known
+    meters_per_unit: Option<Coord>,
+    /// Typed `customData` entries of the planet prim
+    custom_data: Vec<String>,
+    /// Texture URIs of the mesh primitives (`None` for color models)
+    textures: Option<Vec<String>>,
+    /// Whether the mesh is in a left-handed frame
+    left_handed: bool,
+}
+
+/// USD model structure
+///
+/// This struct represents a 3D geospatial model in USD format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct Usd<'a> {
+    model_type: ModelType,
+    model_size: GeoPointIndex,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    texture_uri: String,
+    normal_opts: Option<NormalOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for Usd<'a> {
+    /// No files are required, textures are referenced by their URIs
+    fn options_check(_settings: &'a Settings) -> Result<(), ErrBox> {
+        Ok(())
+    }
+
+    /// Builds and constructs a USD model instance
+    fn build_model(
+        model_type: ModelType,
+        model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        Ok(Usd {
+            model_type,
+            model_size,
+            settings,
+            surface: Surface::from_settings(settings, &components)?,
+            texture_uri: settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?,
+            normal_opts: NormalOpts::from_settings(settings)?,
+            components,
+        })
+    }
+
+    /// Saves the model to the usda file
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        let normals = self.normal_opts.as_ref().map(|opts| solid.normals(opts));
+        let mesh = IndexedMesh::from_solid(
+            &solid,
+            normals.as_ref(),
+            match self.model_type {
+                ModelType::Texture => None,
+                ModelType::Color => Some(self.components.get_colors()?),
+            },
+            self.components.submeshes.as_ref(),
+            &self.surface.frame,
+            origin,
+        )?;
+
+        let frame = &self.surface.frame;
+        let stage = Stage {
+            up: frame.up,
+            meters_per_unit: match &self.surface.physical_size {
+                Some(physical_size) => Some(physical_size.units.metres()),
+                None if frame.is_ecef() => Some(1.0),
+                None => None,
+            },
+            custom_data: vec![
+                format!("string planet_name = {:?}", planet_name),
+                format!("double radius = {}", self.surface.radius),
+                format!("double scale = {}", self.surface.scale),
+                format!("int model_size = {}", self.model_size),
+                format!("string data_source = \"{:?}\"", settings.data_source),
+            ],
+            textures: match self.model_type {
+                ModelType::Texture => Some(
+                    mesh.primitives
+                        .iter()
+                        .map(|(name, _)| match name {
+                            Some(name) => make_tile_uri(&self.texture_uri, name),
+                            None => self.texture_uri.clone(),
+                        })
+                        .collect(),
+                ),
+                ModelType::Color => None,
+            },
+            left_handed: frame.handedness == Handedness::Left,
+        };
+
+        let result_path = Path::new(&output_path)
+            .join(planet_name)
+            .with_extension("usda");
+        let file = File::create(&result_path)
+            .map_err(|err| format!("Can't create {:?}: {}", result_path, err))?;
+        let mut writer = BufWriter::new(file);
+        write_stage(&mut writer, &mesh, &stage)
+            .and_then(|_| writer.flush())
+            .map_err(|err| format!("Can't write usda file {:?}: {}", result_path, err).into())
+    }
+}
+
+/// Writes the USD stage of the mesh
+fn write_stage(writer: &mut impl Write, mesh: &IndexedMesh, stage: &Stage) -> std::io::Result<()> {
+    writeln!(writer, "#usda 1.0")?;
+    writeln!(writer, "(")?;
+    writeln!(writer, "    defaultPrim = \"Planet\"")?;
+    if let Some(meters_per_unit) = stage.meters_per_unit {
+        writeln!(writer, "    metersPerUnit = {}", meters_per_unit)?;
+    }
+    let up = match stage.up {
+        UpAxis::Y => "Y",
+        UpAxis::Z => "Z",
+    };
+    writeln!(writer, "    upAxis = \"{}\"", up)?;
+    writeln!(writer, ")")?;
+    writeln!(writer)?;
+    writeln!(writer, "def Xform \"Planet\" (")?;
+    writeln!(writer, "    customData = {{")?;
+    for entry in &stage.custom_data {
+        writeln!(writer, "        {}", entry)?;
+    }
+    writeln!(writer, "    }}")?;
+    writeln!(writer, ")")?;
+    writeln!(writer, "{{")?;
+
+    let materials: Vec<String> = mesh
+        .primitives
+        .iter()
+        .map(|(name, _)| match name {
+            Some(name) => format!("Material_{}", name),
+            None => "Material".to_string(),
+        })
+        .collect();
+    writeln!(writer, "    def Mesh \"Surface\" (")?;
+    writeln!(
+        writer,
+        "        prepend apiSchemas = [\"MaterialBindingAPI\"]"
+    )?;
+    writeln!(writer, "    )")?;
+    writeln!(writer, "    {{")?;
+    let triangles = || mesh.primitives.iter().flat_map(|(_, triangles)| triangles);
+    let (min, max) = extent(&mesh.positions);
+    writeln!(
+        writer,
+        "        float3[] extent = [{}, {}]",
+        tuple3(&min),
+        tuple3(&max)
+    )?;
+    write_array(
+        writer,
+        "int[] faceVertexCounts",
+        triangles().map(|_| "3".to_string()),
+    )?;
+    write_array(
+        writer,
+        "int[] faceVertexIndices",
+        triangles().flat_map(|triangle| triangle.map(|i| i.to_string())),
+    )?;
+    write_array(
+        writer,
+        "point3f[] points",
+        mesh.positions.iter().map(tuple3),
+    )?;
+    if !mesh.normals.is_empty() {
+        write_values(
+            writer,
+            "normal3f[] normals",
+            mesh.normals.iter().map(tuple3),
+        )?;
+        write_interpolation(writer)?;
+    }
+    if !mesh.colors.is_empty() {
+        write_values(
+            writer,
+            "color3f[] primvars:displayColor",
+            mesh.colors
+                .iter()
+                .map(|RGB(r, g, b)| format!("({}, {}, {})", r, g, b)),
+        )?;
+        write_interpolation(writer)?;
+    }
+    if !mesh.texture_coordinates.is_empty() {
+        write_values(
+            writer,
+            "texCoord2f[] primvars:st",
+            mesh.texture_coordinates
+                .iter()
+                .map(|(s, t)| format!("({}, {})", *s as f32, *t as f32)),
+        )?;
+        write_interpolation(writer)?;
+    }
+    if stage.left_handed {
+        writeln!(writer, "        uniform token orientation = \"leftHanded\"")?;
+    }
+    writeln!(writer, "        uniform token subdivisionScheme = \"none\"")?;
+    if mesh.primitives.iter().any(|(name, _)| name.is_some()) {
+        writeln!(
+            writer,
+            "        uniform token subsetFamily:materialBind:familyType = \"partition\""
+        )?;
+    }
+    let mut first_face = 0;
+    for ((name, triangles), material) in mesh.primitives.iter().zip(&materials) {
+        match name {
+            None => writeln!(
+                writer,
+                "        rel material:binding = <{}/{}>",
+                MATERIALS_PATH, material
+            )?,
+            Some(name) => {
+                writeln!(writer)?;
+                writeln!(writer, "        def GeomSubset \"{}\" (", name)?;
+                writeln!(
+                    writer,
+                    "            prepend apiSchemas = [\"MaterialBindingAPI\"]"
+                )?;
+                writeln!(writer, "        )")?;
+                writeln!(writer, "        {{")?;
+                writeln!(writer, "            uniform token elementType = \"face\"")?;
+                writeln!(
+                    writer,
+                    "            uniform token familyName = \"materialBind\""
+                )?;
+                write!(writer, "    ")?;
+                write_array(
+                    writer,
+                    "int[] indices",
+                    (first_face..first_face + triangles.len()).map(|f| f.to_string()),
+                )?;
+                writeln!(
+                    writer,
+                    "            rel material:binding = <{}/{}>",
+                    MATERIALS_PATH, material
+                )?;
+                writeln!(writer, "        }}")?;
+            }
+        }
+        first_face += triangles.len();
+    }
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "    def Scope \"Materials\"")?;
+    writeln!(writer, "    {{")?;
+    for (n, material) in materials.iter().enumerate() {
+        let texture = stage.textures.as_ref().map(|textures| &textures[n]);
+        write_material(writer, material, texture)?;
+    }
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "}}")
+}
+
+/// Writes a `UsdPreviewSurface` material reading the texture or display colors
+fn write_material(
+    writer: &mut impl Write,
+    name: &str,
+    texture: Option<&String>,
+) -> std::io::Result<()> {
+    let path = format!("{}/{}", MATERIALS_PATH, name);
+    let diffuse = match texture {
+        Some(_) => format!("{}/Texture.outputs:rgb", path),
+        None => format!("{}/ColorReader.outputs:result", path),
+    };
+    write!(
+        writer,
+        r#"        def Material "{name}"
+        {{
+            token outputs:surface.connect = <{path}/PreviewSurface.outputs:surface>
+
+            def Shader "PreviewSurface"
+            {{
+                uniform token info:id = "UsdPreviewSurface"
+                color3f inputs:diffuseColor.connect = <{diffuse}>
+                float inputs:metallic = 0
+                float inputs:roughness = 1
+                token outputs:surface
+            }}
+"#
+    )?;
+    match texture {
+        Some(texture) => write!(
+            writer,
+            r#"
+            def Shader "StReader"
+            {{
+                uniform token info:id = "UsdPrimvarReader_float2"
+                string inputs:varname = "st"
+                float2 outputs:result
+            }}
+
+            def Shader "Texture"
+            {{
+                uniform token info:id = "UsdUVTexture"
+                asset inputs:file = @{texture}@
+                float2 inputs:st.connect = <{path}/StReader.outputs:result>
+                token inputs:wrapS = "repeat"
+                token inputs:wrapT = "clamp"
+                float3 outputs:rgb
+            }}
+"#
+        )?,
+        None => write!(
+            writer,
+            r#"
+            def Shader "ColorReader"
+            {{
+                uniform token info:id = "UsdPrimvarReader_float3"
+                string inputs:varname = "displayColor"
+                float3 outputs:result
+            }}
+"#
+        )?,
+    }
+    writeln!(writer, "        }}")
+}
+
+/// Writes an attribute with an array value
+fn write_array(
+    writer: &mut impl Write,
+    declaration: &str,
+    values: impl Iterator<Item = String>,
+) -> std::io::Result<()> {
+    write_values(writer, declaration, values)?;
+    writeln!(writer)
+}
+
+/// Writes an attribute with an array value, leaving the line open for metadata
+fn write_values(
+    writer: &mut impl Write,
+    declaration: &str,
+    values: impl Iterator<Item = String>,
+) -> std::io::Result<()> {
+    write!(writer, "        {} = [", declaration)?;
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            write!(writer, ", ")?;
+        }
+        write!(writer, "{}", value)?;
+    }
+    write!(writer, "]")
+}
+
+/// Writes vertex interpolation metadata of the attribute on the open line
+fn write_interpolation(writer: &mut impl Write) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        " (\n            interpolation = \"vertex\"\n        )"
+    )
+}
+
+/// Formats a 3d point as a USD tuple
+fn tuple3((x, y, z): &Point3d) -> String {
+    format!("({}, {}, {})", *x as f32, *y as f32, *z as f32)
+}
+
+/// Returns the bounding box of the points
+fn extent(points: &[Point3d]) -> (Point3d, Point3d) {
+    points.iter().fold(
+        (
+            (f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            (f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), p| {
+            (
+                (min.0.min(p.0), min.1.min(p.1), min.2.min(p.2)),
+                (max.0.max(p.0), max.1.max(p.1), max.2.max(p.2)),
+            )
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh() -> IndexedMesh {
+        IndexedMesh {
+            positions: vec![
+                (0.0, 0.0, 0.0),
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (1.0, 1.0, 0.5),
+            ],
+            texture_coordinates: vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)],
+            sources: vec![0, 1, 2, 3],
+            primitives: vec![
+                (Some("px".to_string()), vec![[0, 1, 2]]),
+                (Some("nx".to_string()), vec![[1, 3, 2]]),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn write_stage_t0() {
+        let stage = Stage {
+            up: UpAxis::Z,
+            meters_per_unit: Some(0.001),
+            custom_data: vec!["string planet_name = \"Test\"".to_string()],
+            textures: Some(vec!["image_px.png".to_string(), "image_nx.png".to_string()]),
+            left_handed: false,
+        };
+        let mut bytes = vec![];
+        write_stage(&mut bytes, &mesh(), &stage).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.starts_with("#usda 1.0\n(\n    defaultPrim = \"Planet\"\n"));
+        assert!(text.contains("    metersPerUnit = 0.001\n    upAxis = \"Z\"\n"));
+        assert!(text.contains("        string planet_name = \"Test\"\n"));
+        assert!(text.contains("float3[] extent = [(0, 0, 0), (1, 1, 0.5)]"));
+        assert!(text.contains("int[] faceVertexCounts = [3, 3]\n"));
+        assert!(text.contains("int[] faceVertexIndices = [0, 1, 2, 1, 3, 2]\n"));
+        assert!(text.contains(
+            "texCoord2f[] primvars:st = [(0, 0), (1, 0), (0, 1), (1, 1)] (\n            interpolation = \"vertex\"\n"
+        ));
+        assert!(!text.contains("primvars:displayColor"));
+        assert!(!text.contains("orientation"));
+        assert!(text.contains("materialBind:familyType = \"partition\""));
+        assert!(text.contains("def GeomSubset \"nx\""));
+        assert!(text.contains("            int[] indices = [1]\n"));
+        assert!(text.contains("rel material:binding = </Planet/Materials/Material_nx>"));
+        assert!(text.contains("asset inputs:file = @image_px.png@"));
+        assert!(text.ends_with("        }\n    }\n}\n"));
+    }
+
+    #[test]
+    fn write_stage_t1() {
+        // color model: display colors read by the material bound to the whole mesh
+        let mesh = IndexedMesh {
+            texture_coordinates: vec![],
+            colors: vec![RGB(1.0, 0.0, 0.0); 4],
+            primitives: vec![(None, vec![[0, 1, 2], [1, 3, 2]])],
+            ..mesh()
+        };
+        let stage = Stage {
+            up: UpAxis::Y,
+            meters_per_unit: None,
+            custom_data: vec![],
+            textures: None,
+            left_handed: true,
+        };
+        let mut bytes = vec![];
+        write_stage(&mut bytes, &mesh, &stage).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(!text.contains("metersPerUnit"));
+        assert!(text.contains("color3f[] primvars:displayColor = [(1, 0, 0), (1, 0, 0)"));
+        assert!(text.contains("        rel material:binding = </Planet/Materials/Material>\n"));
+        assert!(!text.contains("GeomSubset"));
+        assert!(text.contains("        uniform token orientation = \"leftHanded\"\n"));
+        assert!(text.contains("string inputs:varname = \"displayColor\""));
+    }
+}
@@ -0,0 +1,39 @@
+//! # Vector Arithmetic
+//!
+//! This module provides the arithmetic of points and vectors in the model
+//! coordinate system (`Point3d`), shared by the mesh processing modules.
+use crate::common::types::*;
+use crate::model::tessellation::Point3d;
+
+pub fn add(a: Point3d, b: Point3d) -> Point3d {
+    (a.0 + b.0, a.1 + b.1, a.2 + b.2)
+}
+
+pub fn sub(a: Point3d, b: Point3d) -> Point3d {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+pub fn scaled(a: Point3d, k: Coord) -> Point3d {
+    (a.0 * k, a.1 * k, a.2 * k)
+}
+
+pub fn dot(a: Point3d, b: Point3d) -> Coord {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+pub fn cross(a: Point3d, b: Point3d) -> Point3d {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+pub fn length(a: Point3d) -> Coord {
+    dot(a, a).sqrt()
+}
+
+/// Returns the vector scaled to unit length
+pub fn normalize(a: Point3d) -> Point3d {
+    scaled(a, 1.0 / length(a))
+}
@@ -0,0 +1,588 @@
+//! # VRML Encodings of X3D Scenes
+//!
+//! This module converts X3D scenes written in the XML encoding to the ClassicVRML
+//! encoding (`.x3dv`) and to VRML97 (`.wrl`), for viewers and CAD importers which
+//! don't read XML. The XML document is read into a tree of nodes, which is written
+//! with the field syntax of the chosen encoding:
+//!
+//! - child nodes are put into the field of their `containerField`, with the
+//!   default container field of common nodes
+//! - `SFString` fields are quoted, `SFBool` values are upper-cased
+//! - multiple-valued fields (`MFString` fields and the number lists of common
+//!   nodes) are written in brackets
+//! - `ROUTE` statements are moved to the end of the scene
+//!
+//! VRML97 has no Geospatial component, so geospatial nodes are declared as
+//! `EXTERNPROTO`s of GeoVRML 1.0, where double precision fields are strings.
+//! Fields added by X3D to VRML97 nodes are dropped.
+//!
+//! ## Configuration Parameters
+//!
+//! - `x3d_encoding`: "xml" (default), "classic_vrml" or "vrml97"
+use crate::common::settings::*;
+use crate::common::types::*;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::reader::Reader;
+use std::io::Write;
+
+/// Default `containerField` of child nodes, other nodes are `children`
+const CONTAINER_FIELDS: &[(&str, &str)] = &[
+    ("Appearance", "appearance"),
+    ("Box", "geometry"),
+    ("Color", "color"),
+    ("ColorRGBA", "color"),
+    ("Cone", "geometry"),
+    ("Coordinate", "coord"),
+    ("Cylinder", "geometry"),
+    ("ElevationGrid", "geometry"),
+    ("FontStyle", "fontStyle"),
+    ("GeoElevationGrid", "geometry"),
+    ("GeoOrigin", "geoOrigin"),
+    ("ImageTexture", "texture"),
+    ("IndexedFaceSet", "geometry"),
+    ("IndexedLineSet", "geometry"),
+    ("IndexedTriangleSet", "geometry"),
+    ("Material", "material"),
+    ("Normal", "normal"),
+    ("PixelTexture", "texture"),
+    ("PointSet", "geometry"),
+    ("Sphere", "geometry"),
+    ("Text", "geometry"),
+    ("TextureCoordinate", "texCoord"),
+    ("TextureTransform", "textureTransform"),
+];
+/// Fields with lists of nodes, other node fields have a single node
+const MF_NODE_FIELDS: &[&str] = &["children", "rootNode"];
+/// Fields of type `SFString`, quoted in VRML encodings
+const SF_STRING_FIELDS: &[&str] = &["description", "language", "style", "title"];
+/// Fields of type `MFString`, XML may have a single unquoted string
+const MF_STRING_FIELDS: &[&str] = &[
+    "child1Url",
+    "child2Url",
+    "child3Url",
+    "child4Url",
+    "family",
+    "geoSystem",
+    "info",
+    "justify",
+    "rootUrl",
+    "string",
+    "transitionType",
+    "type",
+    "url",
+];
+/// Fields of node types with lists of numbers, written in brackets
+const MF_VALUE_FIELDS: &[(&str, &str)] = &[
+    ("Background", "groundAngle"),
+    ("Background", "groundColor"),
+    ("Background", "skyAngle"),
+    ("Background", "skyColor"),
+    ("Color", "color"),
+    ("ColorInterpolator", "key"),
+    ("ColorInterpolator", "keyValue"),
+    ("ColorRGBA", "color"),
+    ("Coordinate", "point"),
+    ("CoordinateInterpolator", "key"),
+    ("CoordinateInterpolator", "keyValue"),
+    ("ElevationGrid", "height"),
+    ("GeoElevationGrid", "height"),
+    ("IndexedFaceSet", "colorIndex"),
+    ("IndexedFaceSet", "coordIndex"),
+    ("IndexedFaceSet", "normalIndex"),
+    ("IndexedFaceSet", "texCoordIndex"),
+    ("IndexedLineSet", "colorIndex"),
+    ("IndexedLineSet", "coordIndex"),
+    ("IndexedTriangleSet", "index"),
+    ("LOD", "range"),
+    ("Normal", "vector"),
+    ("NormalInterpolator", "key"),
+    ("NormalInterpolator", "keyValue"),
+    ("OrientationInterpolator", "key"),
+    ("OrientationInterpolator", "keyValue"),
+    ("PositionInterpolator", "key"),
+    ("PositionInterpolator", "keyValue"),
+    ("ScalarInterpolator", "key"),
+    ("ScalarInterpolator", "keyValue"),
+    ("TextureCoordinate", "point"),
+];
+/// Double precision fields of geospatial nodes, which are strings in GeoVRML
+const GEOVRML_STRING_FIELDS: &[(&str, &str)] = &[
+    ("GeoElevationGrid", "geoGridOrigin"),
+    ("GeoElevationGrid", "xSpacing"),
+    ("GeoElevationGrid", "zSpacing"),
+    ("GeoLOD", "center"),
+    ("GeoOrigin", "geoCoords"),
+    ("GeoViewpoint", "position"),
+];
+/// Fields of X3D nodes missing in VRML97
+const X3D_ONLY_FIELDS: &[(&str, &str)] = &[
+    ("NavigationInfo", "transitionTime"),
+    ("NavigationInfo", "transitionType"),
+];
+/// Interfaces of GeoVRML 1.0 nodes
+const GEOVRML_PROTOS: &[(&str, &str)] = &[
+    (
+        "GeoElevationGrid",
+        "eventIn MFFloat set_height
+  eventIn SFFloat set_yScale
+  exposedField SFNode color
+  exposedField SFNode normal
+  exposedField SFNode texCoord
+  field SFNode geoOrigin
+  field MFString geoSystem
+  field SFString geoGridOrigin
+  field SFInt32 xDimension
+  field SFString xSpacing
+  field SFInt32 zDimension
+  field SFString zSpacing
+  field MFFloat height
+  field SFFloat yScale
+  field SFBool ccw
+  field SFBool colorPerVertex
+  field SFFloat creaseAngle
+  field SFBool normalPerVertex
+  field SFBool solid",
+    ),
+    (
+        "GeoLOD",
+        "field MFString rootUrl
+  field MFNode rootNode
+  field MFString child1Url
+  field MFString child2Url
+  field MFString child3Url
+  field MFString child4Url
+  field SFNode geoOrigin
+  field MFString geoSystem
+  field SFString center
+  field SFFloat range
+  eventOut MFNode children",
+    ),
+    (
+        "GeoOrigin",
+        "exposedField MFString geoSystem
+  exposedField SFString geoCoords
+  field SFBool rotateYUp",
+    ),
+    (
+        "GeoViewpoint",
+        "eventIn SFBool set_bind
+  eventIn SFString set_orientation
+  eventIn SFString set_position
+  exposedField SFFloat fieldOfView
+  exposedField SFBool headlight
+  exposedField SFBool jump
+  exposedField MFString navType
+  field SFString description
+  field SFNode geoOrigin
+  field MFString geoSystem
+  field SFRotation orientation
+  field SFString position
+  field SFFloat speedFactor
+  eventOut SFTime bindTime
+  eventOut SFBool isBound",
+    ),
+];
+
+/// Encoding of X3D files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum X3DEncoding {
+    Xml,
+    ClassicVrml,
+    Vrml97,
+}
+
+impl X3DEncoding {
+    /// Reads the encoding from the `x3d_encoding` parameter
+    pub fn from_settings(settings: &Settings) -> Result<Self, ErrBox> {
+        match settings.get_parameter_str("x3d_encoding", "xml")?.as_str() {
+            "xml" => Ok(X3DEncoding::Xml),
+            "classic_vrml" => Ok(X3DEncoding::ClassicVrml),
+            "vrml97" => Ok(X3DEncoding::Vrml97),
+            s => Err(format!("Unknown X3D encoding '{}'", s).into()),
+        }
+    }
+
+    /// Returns the file extension of the encoding
+    pub fn extension(&self) -> &'static str {
+        match self {
+            X3DEncoding::Xml => "x3d",
+            X3DEncoding::ClassicVrml => "x3dv",
+            X3DEncoding::Vrml97 => "wrl",
+        }
+    }
+}
+
+/// Element of the XML document
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    /// Attributes in the order of the document
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn from_xml(e: &BytesStart) -> Result<Self, ErrBox> {
+        let mut attributes = vec![];
+        for attr in e.attributes() {
+            let attr = attr?;
+            attributes.push((
+                String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                attr.unescape_value()?.into_owned(),
+            ));
+        }
+        Ok(Element {
+            name: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+            attributes,
+            children: vec![],
+        })
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Returns the field of the parent node holding the node
+    fn container_field(&self) -> &str {
+        self.attribute("containerField").unwrap_or_else(|| {
+            CONTAINER_FIELDS
+                .iter()
+                .find(|(name, _)| *name == self.name)
+                .map_or("children", |(_, field)| field)
+        })
+    }
+}
+
+/// Reads the root element of an XML document
+fn parse_xml(xml: &[u8]) -> Result<Element, ErrBox> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut stack = vec![Element::default()];
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Err(e) => {
+                return Err(
+                    format!("Error at position {}: {:?}", reader.buffer_position(), e).into(),
+                );
+            }
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(e)) => stack.push(Element::from_xml(&e)?),
+            Ok(Event::Empty(e)) => {
+                let element = Element::from_xml(&e)?;
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(element);
+                }
+            }
+            Ok(Event::End(_)) => {
+                let element = stack.pop().ok_or("Unbalanced X3D document")?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Err("Unbalanced X3D document".into()),
+                }
+            }
+            Ok(_) => (),
+        }
+        buf.clear();
+    }
+    match (stack.pop(), stack.is_empty()) {
+        (Some(mut document), true) => document.children.pop().ok_or("Empty X3D document".into()),
+        _ => Err("Unbalanced X3D document".into()),
+    }
+}
+
+/// Quotes a string value
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Writes a scene in the XML encoding in a VRML encoding
+pub fn write_scene<W: Write>(xml: &[u8], encoding: X3DEncoding, out: &mut W) -> Result<(), ErrBox> {
+    let x3d = parse_xml(xml)?;
+    let scene = x3d.child("Scene").ok_or("X3D document without Scene")?;
+    match encoding {
+        X3DEncoding::Xml => return Err("XML scenes are written directly".into()),
+        X3DEncoding::ClassicVrml => {
+            let version = x3d.attribute("version").unwrap_or("3.3");
+            writeln!(out, "#X3D V{} utf8", version)?;
+            writeln!(
+                out,
+                "PROFILE {}",
+                x3d.attribute("profile").unwrap_or("Full")
+            )?;
+            if let Some(head) = x3d.child("head") {
+                for e in &head.children {
+                    match (e.name.as_str(), e.attribute("name")) {
+                        ("component", Some(name)) => {
+                            let level = e.attribute("level").unwrap_or("1");
+                            writeln!(out, "COMPONENT {}:{}", name, level)?;
+                        }
+                        ("meta", Some(name)) => {
+                            let content = e.attribute("content").unwrap_or("");
+                            writeln!(out, "META {} {}", quote(name), quote(content))?;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+        X3DEncoding::Vrml97 => {
+            writeln!(out, "#VRML V2.0 utf8")?;
+            for (name, interface) in GEOVRML_PROTOS {
+                if uses_node(scene, name) {
+                    writeln!(out)?;
+                    writeln!(out, "EXTERNPROTO {} [\n  {}\n] [", name, interface)?;
+                    writeln!(out, "  \"urn:web3d:geovrml:1.0/protos/{}.wrl\"", name)?;
+                    writeln!(out, "  \"http://www.geovrml.org/1.0/protos/{}.wrl\"", name)?;
+                    writeln!(out, "]")?;
+                }
+            }
+        }
+    }
+    writeln!(out)?;
+
+    let mut routes = vec![];
+    for node in &scene.children {
+        write_statement(out, node, encoding, 0, &mut routes)?;
+    }
+    for route in routes {
+        writeln!(out, "{}", route)?;
+    }
+    Ok(())
+}
+
+/// Checks if the node or its descendants are nodes of the type
+fn uses_node(e: &Element, name: &str) -> bool {
+    e.name == name || e.children.iter().any(|child| uses_node(child, name))
+}
+
+/// Writes a node statement, `ROUTE` statements are kept for the end of the scene
+fn write_statement<W: Write>(
+    out: &mut W,
+    e: &Element,
+    encoding: X3DEncoding,
+    indent: usize,
+    routes: &mut Vec<String>,
+) -> Result<(), ErrBox> {
+    if e.name == "ROUTE" {
+        let field = |name| e.attribute(name).unwrap_or("");
+        routes.push(format!(
+            "ROUTE {}.{} TO {}.{}",
+            field("fromNode"),
+            field("fromField"),
+            field("toNode"),
+            field("toField")
+        ));
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "{:indent$}{}",
+        "",
+        node_value(e, encoding, indent, routes)?
+    )?;
+    Ok(())
+}
+
+/// Returns the node in the VRML encoding, from the type to the closing brace
+fn node_value(
+    e: &Element,
+    encoding: X3DEncoding,
+    indent: usize,
+    routes: &mut Vec<String>,
+) -> Result<String, ErrBox> {
+    if let Some(name) = e.attribute("USE") {
+        return Ok(format!("USE {}", name));
+    }
+    let mut body = Vec::new();
+    write_fields(&mut body, e, encoding, indent + 2, routes)?;
+    let header = match e.attribute("DEF") {
+        Some(name) => format!("DEF {} {}", name, e.name),
+        None => e.name.clone(),
+    };
+    Ok(format!(
+        "{} {{\n{}{:indent$}}}",
+        header,
+        String::from_utf8_lossy(&body),
+        ""
+    ))
+}
+
+/// Writes fields and child nodes of the node
+fn write_fields<W: Write>(
+    out: &mut W,
+    e: &Element,
+    encoding: X3DEncoding,
+    indent: usize,
+    routes: &mut Vec<String>,
+) -> Result<(), ErrBox> {
+    for (field, value) in &e.attributes {
+        if let Some(value) = field_value(&e.name, field, value, encoding) {
+            writeln!(out, "{:indent$}{} {}", "", field, value)?;
+        }
+    }
+
+    let mut container_fields: Vec<&str> = vec![];
+    for child in &e.children {
+        let field = child.container_field();
+        if child.name != "ROUTE" && !container_fields.contains(&field) {
+            container_fields.push(field);
+        }
+    }
+    for field in container_fields {
+        let children = e
+            .children
+            .iter()
+            .filter(|child| child.name != "ROUTE" && child.container_field() == field);
+        if MF_NODE_FIELDS.contains(&field) {
+            writeln!(out, "{:indent$}{} [", "", field)?;
+            for child in children {
+                write_statement(out, child, encoding, indent + 2, routes)?;
+            }
+            writeln!(out, "{:indent$}]", "")?;
+        } else {
+            for child in children {
+                let value = node_value(child, encoding, indent, routes)?;
+                writeln!(out, "{:indent$}{} {}", "", field, value)?;
+            }
+        }
+    }
+    for child in e.children.iter().filter(|child| child.name == "ROUTE") {
+        write_statement(out, child, encoding, indent, routes)?;
+    }
+    Ok(())
+}
+
+/// Returns the field value in the VRML encoding, `None` if the field isn't written
+fn field_value(node: &str, field: &str, value: &str, encoding: X3DEncoding) -> Option<String> {
+    match field {
+        "DEF" | "USE" | "containerField" => return None,
+        _ => (),
+    }
+    if encoding == X3DEncoding::Vrml97 {
+        if X3D_ONLY_FIELDS.contains(&(node, field)) {
+            return None;
+        }
+        if GEOVRML_STRING_FIELDS.contains(&(node, field)) {
+            return Some(quote(value));
+        }
+    }
+    if SF_STRING_FIELDS.contains(&field) {
+        return Some(quote(value));
+    }
+    if MF_STRING_FIELDS.contains(&field) {
+        let value = value.trim();
+        return Some(if value.starts_with('"') {
+            format!("[{}]", value)
+        } else {
+            format!("[{}]", quote(value))
+        });
+    }
+    if MF_VALUE_FIELDS.contains(&(node, field)) {
+        return Some(format!("[{}]", value.trim()));
+    }
+    match value.trim() {
+        "true" => Some("TRUE".to_string()),
+        "false" => Some("FALSE".to_string()),
+        _ => Some(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<X3D profile='Immersive' version='3.3'>
+<head><component level='1' name='Geospatial'/><meta name='title' content='Planet'/></head>
+<Scene>
+  <WorldInfo title='Unnamed Planet'/>
+  <NavigationInfo headlight='true' type='\"EXAMINE\"' transitionType='ANIMATE'/>
+  <GeoViewpoint description='Initial' position='0 0 8000000' containerField='children'/>
+  <Transform DEF='Globe'>
+    <ROUTE fromField='value_changed' fromNode='Spin' toField='set_rotation' toNode='Globe'/>
+    <Shape>
+      <Appearance><Material diffuseColor='0.75 0.75 0.75'/></Appearance>
+      <GeoElevationGrid geoGridOrigin='-90 -180 0' xDimension='2' xSpacing='180' zDimension='2' zSpacing='180' height='0 1 2 3'>
+        <Color color='1 0 0 0 1 0 0 0 1 1 1 1'/>
+      </GeoElevationGrid>
+    </Shape>
+  </Transform>
+</Scene>
+</X3D>";
+
+    fn write(encoding: X3DEncoding) -> String {
+        let mut out = vec![];
+        write_scene(SCENE.as_bytes(), encoding, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_scene_t0() {
+        assert_eq!(
+            write(X3DEncoding::ClassicVrml),
+            "#X3D V3.3 utf8
+PROFILE Immersive
+COMPONENT Geospatial:1
+META \"title\" \"Planet\"
+
+WorldInfo {
+  title \"Unnamed Planet\"
+}
+NavigationInfo {
+  headlight TRUE
+  type [\"EXAMINE\"]
+  transitionType [\"ANIMATE\"]
+}
+GeoViewpoint {
+  description \"Initial\"
+  position 0 0 8000000
+}
+DEF Globe Transform {
+  children [
+    Shape {
+      appearance Appearance {
+        material Material {
+          diffuseColor 0.75 0.75 0.75
+        }
+      }
+      geometry GeoElevationGrid {
+        geoGridOrigin -90 -180 0
+        xDimension 2
+        xSpacing 180
+        zDimension 2
+        zSpacing 180
+        height [0 1 2 3]
+        color Color {
+          color [1 0 0 0 1 0 0 0 1 1 1 1]
+        }
+      }
+    }
+  ]
+}
+ROUTE Spin.value_changed TO Globe.set_rotation
+"
+        );
+    }
+
+    #[test]
+    fn write_scene_t1() {
+        let wrl = write(X3DEncoding::Vrml97);
+        assert!(wrl.starts_with("#VRML V2.0 utf8\n\nEXTERNPROTO GeoElevationGrid [\n"));
+        assert!(wrl.contains("EXTERNPROTO GeoViewpoint ["));
+        assert!(!wrl.contains("EXTERNPROTO GeoLOD"));
+        assert!(!wrl.contains("transitionType"));
+        assert!(wrl.contains("  position \"0 0 8000000\"\n"));
+        assert!(wrl.contains("        geoGridOrigin \"-90 -180 0\"\n"));
+        assert!(wrl.contains("        xDimension 2\n"));
+        assert!(wrl.contains("        height [0 1 2 3]\n"));
+        assert!(wrl.contains("  type [\"EXAMINE\"]\n"));
+        assert!(wrl.ends_with("}\nROUTE Spin.value_changed TO Globe.set_rotation\n"));
+    }
+}
@@ -0,0 +1,292 @@
+//! # X3D Model Implementation
+//!
+//! This module writes models as plain X3D scenes, without the Geospatial
+//! component which many X3D browsers (X_ITE, X3DOM) support poorly. The mesh of
+//! OBJ models (see `tessellation`) is written as an `IndexedTriangleSet` with
+//! `Coordinate`, `Color` or `TextureCoordinate`, and `Normal` nodes, with one
+//! index per vertex (see `mesh`).
+//!
+//! As for `X3DGeospatial` models, the scene comes from a template file, where
+//! placeholder nodes are replaced:
+//!
+//! - `_IndexedTriangleSet`: the triangle set, keeping the attributes of the
+//!   placeholder (`solid`, `creaseAngle`, ...)
+//! - `_ImageTexture`: the `ImageTexture` of texture models
+//!
+//! Vertices are placed by the same parameters as OBJ vertices (see `surface`).
+//! X3D is Y-up, so `axes: "y_up"` should be set.
+//!
+//! ## Configuration Parameters
+//!
+//! - `template_file_x3d`: Path to the X3D template file (default: "./x3d.template")
+//! - `texture_uri`: URI for texture mapping (default: "\"texture.png\"")
+//! - `normals`, `crease_angle`: see `normals`
+use crate::common::settings::*;
+use crate::common::types::*;
+use crate::common::util::check_file;
+use crate::model::mesh::*;
+use crate::model::normals::*;
+use crate::model::surface::*;
+use crate::model::tessellation::*;
+use crate::model::types::*;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::reader::Reader;
+use quick_xml::writer::Writer;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Default template file path for X3D models
+const DEFAULT_TEMPLATE_FILE: &str = "./x3d.template";
+/// Default texture URI for X3D models
+const DEFAULT_TEXTURE_URI: &str = "\"texture.png\"";
+
+/// X3D model structure
+///
+/// This struct represents a 3D geospatial model in plain X3D format that can be
+/// created from Digital Elevation Model (DEM) data. It uses the tessellations of
+/// OBJ models.
+pub struct X3D<'a> {
+    model_type: ModelType,
+    settings: &'a Settings<'a>,
+    surface: Surface,
+    template_file: PathBuf,
+    texture_uri: String,
+    normal_opts: Option<NormalOpts>,
+    components: ModelComponents,
+}
+
+impl<'a> Model<'a> for X3D<'a> {
+    /// Checks that the X3D template file exists
+    fn options_check(settings: &'a Settings) -> Result<(), ErrBox> {
+        let str = settings.get_parameter_str("template_file_x3d", DEFAULT_TEMPLATE_FILE)?;
+        check_file(Path::new(&str))
+    }
+
+    /// Builds and constructs an X3D model instance
+    fn build_model(
+        model_type: ModelType,
+        _model_size: GeoPointIndex,
+        settings: &'a Settings,
+        components: ModelComponents,
+    ) -> Result<Self, ErrBox>
+    where
+        Self: Sized,
+    {
+        let str = settings.get_parameter_str("template_file_x3d", DEFAULT_TEMPLATE_FILE)?;
+        Ok(X3D {
+            model_type,
+            settings,
+            surface: Surface::from_settings(settings, &components)?,
+            template_file: Path::new(&str).to_owned(),
+            texture_uri: settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?,
+            normal_opts: NormalOpts::from_settings(settings)?,
+            components,
+        })
+    }
+
+    /// Saves the model to the X3D file filled from the template
+    fn save(&self) -> Result<(), ErrBox> {
+        let settings = self.settings;
+        let planet_name = &settings.planet_name;
+        let output_path = settings.output_dir;
+
+        let solid = self.surface.solid(self.model_type, &self.components)?;
+        let origin = self
+            .surface
+            .origin(&solid.positions, output_path, planet_name)?;
+        let normals = self.normal_opts.as_ref().map(|opts| solid.normals(opts));
+        let mesh = IndexedMesh::from_solid(
+            &solid,
+            normals.as_ref(),
+            match self.model_type {
+                ModelType::Texture => None,
+                ModelType::Color => Some(self.components.get_colors()?),
+            },
+            None,
+            &self.surface.frame,
+            origin,
+        )?;
+        if let (ModelType::Texture, Some(_)) = (self.model_type, &self.components.submeshes) {
+            return Err(
+                "X3D shapes have a single texture, cube-sphere texture models can't be written"
+                    .into(),
+            );
+        }
+        let texture_uri = match self.model_type {
+            ModelType::Texture => Some(self.texture_uri.as_str()),
+            ModelType::Color => None,
+        };
+
+        let mut reader = Reader::from_file(&self.template_file)
+            .map_err(|err| format!("Can't read template: {}", err))?;
+        reader.config_mut().check_comments = true;
+        let result_path = Path::new(&output_path)
+            .join(planet_name)
+            .with_extension("x3d");
+        let file = File::create(&result_path)
+            .map_err(|err| format!("Can't write to output file {:?}: {}", result_path, err))?;
+        let mut writer = Writer::new(BufWriter::new(file));
+        fill_template(&mut reader, &mut writer, &mesh, texture_uri)?;
+        Ok(writer.into_inner().flush()?)
+    }
+}
+
+/// Copies the template to the writer, replacing the placeholder nodes
+fn fill_template<R: BufRead, W: Write>(
+    reader: &mut Reader<R>,
+    writer: &mut Writer<W>,
+    mesh: &IndexedMesh,
+    texture_uri: Option<&str>,
+) -> Result<(), ErrBox> {
+    let mut buf = Vec::new();
+    let mut in_placeholder = false;
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Err(e) => {
+                return Err(
+                    format!("Error at position {}: {:?}", reader.buffer_position(), e).into(),
+                );
+            }
+            Ok(Event::Eof) => break,
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"_IndexedTriangleSet" => {
+                write_triangle_set(writer, &e, mesh)?;
+            }
+            // children of the placeholder are replaced by the generated nodes
+            Ok(Event::Start(e)) if e.name().as_ref() == b"_IndexedTriangleSet" => {
+                write_triangle_set(writer, &e, mesh)?;
+                in_placeholder = true;
+            }
+            Ok(Event::End(e)) if e.name().as_ref() == b"_IndexedTriangleSet" => {
+                in_placeholder = false;
+            }
+            Ok(_) if in_placeholder => (),
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"_ImageTexture" => {
+                if let Some(texture_uri) = texture_uri {
+                    let mut elem = BytesStart::new("ImageTexture");
+                    elem.extend_attributes(e.attributes().filter_map(|attr| attr.ok()));
+                    elem.push_attribute(("url", texture_uri));
+                    writer.write_event(Event::Empty(elem))?;
+                }
+            }
+            Ok(e) => writer.write_event(e)?,
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// Writes the `IndexedTriangleSet` of the mesh with the attributes of the placeholder
+fn write_triangle_set<W: Write>(
+    writer: &mut Writer<W>,
+    placeholder: &BytesStart,
+    mesh: &IndexedMesh,
+) -> std::io::Result<()> {
+    let index = mesh
+        .primitives
+        .iter()
+        .flat_map(|(_, triangles)| triangles)
+        .flat_map(|triangle| triangle.map(|i| i.to_string()))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let mut elem = BytesStart::new("IndexedTriangleSet");
+    elem.extend_attributes(placeholder.attributes().filter_map(|attr| attr.ok()));
+    elem.push_attribute(("index", index.as_str()));
+    writer.write_event(Event::Start(elem))?;
+
+    let points = |values: Vec<String>| values.join(", ");
+    let vec3 = |(x, y, z): &Point3d| format!("{} {} {}", *x as f32, *y as f32, *z as f32);
+    let mut elem = BytesStart::new("Coordinate");
+    let point = points(mesh.positions.iter().map(vec3).collect());
+    elem.push_attribute(("point", point.as_str()));
+    writer.write_event(Event::Empty(elem))?;
+    if !mesh.colors.is_empty() {
+        let mut elem = BytesStart::new("Color");
+        let color = points(mesh.colors.iter().map(|c| c.to_string()).collect());
+        elem.push_attribute(("color", color.as_str()));
+        writer.write_event(Event::Empty(elem))?;
+    }
+    if !mesh.texture_coordinates.is_empty() {
+        let mut elem = BytesStart::new("TextureCoordinate");
+        let point = points(
+            mesh.texture_coordinates
+                .iter()
+                .map(|(u, v)| format!("{} {}", *u as f32, *v as f32))
+                .collect(),
+        );
+        elem.push_attribute(("point", point.as_str()));
+        writer.write_event(Event::Empty(elem))?;
+    }
+    if !mesh.normals.is_empty() {
+        let mut elem = BytesStart::new("Normal");
+        let vector = points(
+            mesh.normals
+                .iter()
+                .map(|(x, y, z)| format!("{:.4} {:.4} {:.4}", x, y, z))
+                .collect(),
+        );
+        elem.push_attribute(("vector", vector.as_str()));
+        writer.write_event(Event::Empty(elem))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("IndexedTriangleSet")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::color::RGB;
+
+    fn fill(template: &str, mesh: &IndexedMesh, texture_uri: Option<&str>) -> String {
+        let mut reader = Reader::from_str(template);
+        let mut writer = Writer::new(vec![]);
+        fill_template(&mut reader, &mut writer, mesh, texture_uri).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn fill_template_t0() {
+        let mesh = IndexedMesh {
+            positions: vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+            normals: vec![(0.0, 0.0, 1.0); 3],
+            colors: vec![RGB(1.0, 0.0, 0.0), RGB(0.0, 1.0, 0.0), RGB(0.0, 0.0, 1.0)],
+            sources: vec![0, 1, 2],
+            primitives: vec![(None, vec![[0, 1, 2]])],
+            ..Default::default()
+        };
+        let x3d = fill(
+            "<Shape><Appearance><_ImageTexture/></Appearance>\
+             <_IndexedTriangleSet solid='true'><Color/></_IndexedTriangleSet></Shape>",
+            &mesh,
+            None,
+        );
+        assert_eq!(
+            x3d,
+            "<Shape><Appearance></Appearance>\
+             <IndexedTriangleSet solid=\"true\" index=\"0 1 2\">\
+             <Coordinate point=\"0 0 0, 1 0 0, 0 1 0\"/>\
+             <Color color=\"1 0 0, 0 1 0, 0 0 1\"/>\
+             <Normal vector=\"0.0000 0.0000 1.0000, 0.0000 0.0000 1.0000, 0.0000 0.0000 1.0000\"/>\
+             </IndexedTriangleSet></Shape>"
+        );
+    }
+
+    #[test]
+    fn fill_template_t1() {
+        let mesh = IndexedMesh {
+            positions: vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+            texture_coordinates: vec![(0.0, 0.0), (0.5, 0.0), (0.0, 1.0)],
+            sources: vec![0, 1, 2],
+            primitives: vec![(None, vec![[0, 2, 1]])],
+            ..Default::default()
+        };
+        let x3d = fill(
+            "<_ImageTexture repeatS='true'/><_IndexedTriangleSet/>",
+            &mesh,
+            Some("\"image.png\""),
+        );
+        assert!(x3d.starts_with("<ImageTexture repeatS=\"true\" url=\"&quot;image.png&quot;\"/>"));
+        assert!(x3d.contains("<IndexedTriangleSet index=\"0 2 1\">"));
+        assert!(x3d.contains("<TextureCoordinate point=\"0 0, 0.5 0, 0 1\"/>"));
+        assert!(!x3d.contains("<Normal"));
+    }
+}
@@ -35,6 +35,19 @@
 //! - Vertex spacing is calculated to ensure proper geographic coverage
 //! - Elevation values are interpolated and mapped to 3D coordinates
 //!
+//! ## GeoLOD Tiles
+//!
+//! A single grid of a large model is slow in browsers. With `lod_levels` the
+//! grid is split into a quadtree of tiles: the western and the eastern
+//! hemispheres are the root tiles, and every tile has four quarters at the next
+//! level. All tiles have the same number of points, so the resolution doubles
+//! at each level, and the finest level has the model resolution. The shape of
+//! the elevation grid in the template is replaced by `GeoLOD` nodes of the root
+//! tiles, which show the shape of their own grid until the viewer comes closer
+//! than `range`, and then load the files of their quarters
+//! (`{planet_name}_{level}_{x}_{y}.x3d`). Tiles of the finest level are plain
+//! shapes.
+//!
 //! ## Usage
 //!
 //! To create an X3D geospatial model:
@@ -47,16 +60,32 @@
 //!
 //! - `template_file_x3d`: Path to the X3D template file (default: "./geospatial.x3d.template")
 //! - `texture_uri`: URI for texture mapping (default: "\"texture.png\"")
+//! - `scale`: `yScale` of the elevation grid (default: the value in the template)
+//! - `relief_curve` and related parameters: relief curve of elevations (see `relief` module)
+//! - `normals`: "smooth" (default) writes a `Normal` node with geocentric normals of the
+//!   grid points, "none" leaves shading to `creaseAngle`
+//! - `lod_levels`: number of GeoLOD levels below the root tiles (default: 0, a single grid)
+//! - `lod_range`: `range` of the root GeoLOD tiles in metres, halved at each level
+//!   (default: 10000000)
+//! - `x3d_encoding`: "xml" (default), "classic_vrml" or "vrml97" (see `vrml`)
+//! - `compression`, `compression_level`: compression of output files (see `compression`)
 use crate::common::settings::*;
 use crate::common::types::*;
 use crate::common::util::check_file;
+use crate::model::compression::*;
+use crate::model::frame::*;
+use crate::model::normals::*;
+use crate::model::relief::*;
+use crate::model::tessellation::*;
 use crate::model::types::*;
-use quick_xml::events::{BytesEnd, BytesStart, Event};
+use crate::model::vrml::*;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
 use quick_xml::reader::Reader;
 use quick_xml::writer::Writer;
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::Write;
+use std::fmt::Display;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
 /// Minimum valid model size for X3D geospatial models
@@ -65,6 +94,8 @@ const MIN_VALID_MODEL_SIZE: usize = 4;
 const DEFAULT_TEMPLATE_FILE: &str = "./geospatial.x3d.template";
 /// Default texture URI for X3D geospatial models
 const DEFAULT_TEXTURE_URI: &str = "\"texture.png\"";
+/// Default `range` of the root GeoLOD tiles in metres
+const DEFAULT_LOD_RANGE: Coord = 10_000_000.0;
 
 /// X3DGeospatial model structure
 ///
@@ -85,6 +116,16 @@ pub struct X3DGeospatial<'a> {
     components: ModelComponents,
     /// Path to the X3D template file used for output generation
     template_file: PathBuf,
+    /// Relief curves of elevations
+    relief: ReliefOpts,
+    /// Options of normals, `None` if normals aren't written
+    normal_opts: Option<NormalOpts>,
+    /// Options of GeoLOD tiles, `None` if the grid isn't tiled
+    lod_opts: Option<GeoLodOpts>,
+    /// Encoding of the output files
+    encoding: X3DEncoding,
+    /// Compression of the output files
+    compression: Compression,
 }
 
 impl<'a> Model<'a> for X3DGeospatial<'a> {
@@ -136,6 +177,19 @@ impl<'a> Model<'a> for X3DGeospatial<'a> {
         ModelData::create(vertices, vec![], None)
     }
 
+    /// Uses the elevation grid of `create_modeldata`
+    fn define_tessellation(
+        _settings: &'a Settings,
+        _model_size: GeoPointIndex,
+    ) -> Result<Tessellation, ErrBox> {
+        Ok(Tessellation::Native)
+    }
+
+    /// No texture coordinates, the grid is textured by the geospatial component
+    fn create_texture_coordinates(_model_size: GeoPointIndex) -> TextureCoordinates {
+        vec![]
+    }
+
     /// Checks that required files and directories exist
     ///
     /// Validates that the X3D template file exists and is accessible.
@@ -162,6 +216,9 @@ impl<'a> Model<'a> for X3DGeospatial<'a> {
     {
         let str = settings.get_parameter_str("template_file_x3d", DEFAULT_TEMPLATE_FILE)?;
         let template_file = Path::new(&str).to_owned();
+        let relief = ReliefOpts::from_settings(settings)?;
+        let normal_opts = NormalOpts::from_settings(settings)?;
+        let lod_opts = GeoLodOpts::from_settings(settings, model_size)?;
 
         return Ok(X3DGeospatial {
             model_type,
@@ -169,133 +226,630 @@ impl<'a> Model<'a> for X3DGeospatial<'a> {
             settings,
             components,
             template_file,
+            relief,
+            normal_opts,
+            lod_opts,
+            encoding: X3DEncoding::from_settings(settings)?,
+            compression: Compression::from_settings(settings)?,
         });
     }
 
     /// Saves the model data to X3D output files
     ///
     /// Writes the final X3D file by processing the template file and
-    /// inserting the elevation data and color/texture information.
+    /// inserting the elevation data and color/texture information. With GeoLOD
+    /// tiles the shape of the elevation grid is replaced by `GeoLOD` nodes of
+    /// the root tiles, and the finer tiles are written to their own files.
     fn save(&self) -> Result<(), ErrBox> {
-        let settings = self.settings;
-        let planet_name = &settings.planet_name;
-        let output_path = &settings.output_dir;
-
         let mut reader = match Reader::from_file(&self.template_file) {
             Ok(r) => r,
             Err(err) => return Err(format!("Can't read template: {}", err).into()),
         };
         reader.config_mut().check_comments = true;
 
+        if self.lod_opts.is_some() && !has_grid_shape(&self.template_file)? {
+            return Err("GeoLOD tiles need a Shape with _GeoElevationGrid".into());
+        }
+
         let mut buf = Vec::new();
 
-        let _result_path = Path::new(&output_path)
-            .join(&planet_name)
-            .with_extension("x3d");
-        let result_path = match _result_path.to_str() {
-            Some(fp) => fp,
-            None => return Err("Can't get file path for result data".into()),
-        };
+        let mut grid = GridValues::new(self)?;
+        let whole = GridTile::whole(self.model_size);
+        let mut grid_shape = None;
+        self.save_document(&self.settings.planet_name, |writer| {
+            // events of a template shape kept until its end, with GeoLOD tiles
+            let mut shape: Option<Vec<Event<'static>>> = None;
+            loop {
+                match reader.read_event_into(&mut buf) {
+                    Err(e) => {
+                        return Err(format!(
+                            "Error at position {}: {:?}",
+                            reader.buffer_position(),
+                            e
+                        )
+                        .into());
+                    }
+                    Ok(Event::Eof) => break,
+                    Ok(Event::Start(e))
+                        if self.lod_opts.is_some() && e.name().as_ref() == b"Shape" =>
+                    {
+                        shape = Some(vec![Event::Start(e.into_owned())]);
+                    }
+                    Ok(Event::End(e)) if shape.is_some() && e.name().as_ref() == b"Shape" => {
+                        let mut events = shape.take().unwrap_or_default();
+                        events.push(Event::End(e.into_owned()));
+                        match &self.lod_opts {
+                            Some(lod_opts) if events.iter().any(is_grid_placeholder) => {
+                                for x in 0..2 {
+                                    let tile = LodTile { level: 0, x, y: 0 };
+                                    self.write_lod(writer, lod_opts, &events, &mut grid, tile)?;
+                                }
+                                grid_shape = Some(events);
+                            }
+                            _ => {
+                                for event in events {
+                                    grid.write_event(writer, event, &whole)?;
+                                }
+                            }
+                        }
+                    }
+                    Ok(e) => match &mut shape {
+                        Some(events) => events.push(e.into_owned()),
+                        None => grid.write_event(writer, e, &whole)?,
+                    },
+                }
+                buf.clear();
+            }
+            Ok(())
+        })?;
 
-        let buffer = match File::create(result_path) {
-            Ok(f) => f,
-            Err(err) => return Err(format!("Can't write to output file: {}", err).into()),
-        };
+        if let (Some(lod_opts), Some(shape)) = (&self.lod_opts, grid_shape) {
+            self.save_lod_tiles(lod_opts, &shape, &mut grid)?;
+        }
+        Ok(())
+    }
+}
 
-        let height_values = self
-            .components
-            .heights
-            .values()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>()
-            .join(" ");
-
-        let color_values = match &self.model_type {
-            ModelType::Texture => "".to_string(),
-            ModelType::Color => self
-                .components
-                .get_colors()?
-                .values()
-                .map(|v| v.to_string())
-                .collect::<Vec<String>>()
-                .join(" "),
+impl<'a> X3DGeospatial<'a> {
+    /// Makes the grid normals, `None` if normals aren't written
+    ///
+    /// Grid points are placed on the WGS84 ellipsoid, the default geospatial
+    /// system of `GeoElevationGrid`, with elevations multiplied by `y_scale`.
+    fn normal_values(&self, y_scale: Height) -> Result<Option<Vec<Point3d>>, ErrBox> {
+        if self.normal_opts.is_none() {
+            return Ok(None);
+        }
+        let frame = FrameOpts {
+            coordinates: Coordinates::Ecef,
+            ellipsoid: Ellipsoid::Wgs84,
+            ..Default::default()
         };
+        let heights = &self.components.heights;
+        let positions: Vec<Point3d> = self
+            .components
+            .get_vertices()?
+            .iter()
+            .map(|(i, GeoPoint { lon, lat })| {
+                let height = self.relief.apply(heights.get(i).cloned().unwrap_or(0.0));
+                frame.position(0.0, y_scale, height, *lon, *lat)
+            })
+            .collect();
+        Ok(Some(grid_normals(&positions, 2 * self.model_size + 1)))
+    }
 
-        let create_height_attr = |elem: &mut BytesStart<'static>| {
-            elem.push_attribute((
-                "xDimension",
-                (2 * (self.model_size) + 1).to_string().as_str(),
-            ));
-            elem.push_attribute(("xSpacing", (self.components.spacing.to_string().as_str())));
-            elem.push_attribute(("zDimension", ((self.model_size) + 1).to_string().as_str()));
-            elem.push_attribute(("zSpacing", (self.components.spacing.to_string().as_str())));
-            elem.push_attribute(("height", height_values.as_str()));
-        };
+    /// Writes a GeoLOD tile
+    ///
+    /// Tiles of the finest level are the shape of their grid. Coarser tiles are
+    /// `GeoLOD` nodes with the shape as `rootNode` and the files of their
+    /// quarters as `child1Url`..`child4Url`.
+    fn write_lod<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        lod_opts: &GeoLodOpts,
+        shape: &[Event<'static>],
+        grid: &mut GridValues,
+        tile: LodTile,
+    ) -> Result<(), ErrBox> {
+        let grid_tile = GridTile::lod(self.model_size, lod_opts.levels, tile);
+        if tile.level == lod_opts.levels {
+            for event in shape {
+                grid.write_event(writer, event.clone(), &grid_tile)?;
+            }
+            return Ok(());
+        }
 
-        let mut writer = Writer::new(buffer);
-        let mut in_geo_elevation_grid = false;
-        loop {
-            match reader.read_event_into(&mut buf) {
-                Err(e) => {
-                    return Err(
-                        format!("Error at position {}: {:?}", reader.buffer_position(), e).into(),
+        let (lat, lon) = tile.center();
+        let mut elem = BytesStart::new("GeoLOD");
+        elem.push_attribute(("center", format!("{} {} 0", lat, lon).as_str()));
+        let range = lod_opts.range / (1 << tile.level) as Coord;
+        elem.push_attribute(("range", range.to_string().as_str()));
+        for (k, child) in tile.children().iter().enumerate() {
+            let name = Path::new(&self.lod_name(child)).with_extension(self.extension());
+            let url = format!("\"{}\"", name.to_string_lossy());
+            elem.push_attribute((format!("child{}Url", k + 1).as_str(), url.as_str()));
+        }
+        writer.write_event(Event::Start(elem))?;
+        for (k, event) in shape.iter().enumerate() {
+            match event {
+                Event::Start(e) if k == 0 => {
+                    let mut elem = BytesStart::new("Shape");
+                    elem.extend_attributes(
+                        e.attributes()
+                            .filter_map(|attr| attr.ok())
+                            .filter(|attr| attr.key.as_ref() != b"containerField"),
                     );
+                    elem.push_attribute(("containerField", "rootNode"));
+                    grid.write_event(writer, Event::Start(elem), &grid_tile)?;
                 }
-                Ok(Event::Eof) => break,
-                Ok(Event::Empty(e)) if e.name().as_ref() == b"_GeoElevationGrid" => {
-                    let mut elem = BytesStart::new("GeoElevationGrid");
-                    elem.extend_attributes(e.attributes().map(|attr| attr.unwrap()));
-                    create_height_attr(&mut elem);
+                _ => grid.write_event(writer, event.clone(), &grid_tile)?,
+            }
+        }
+        writer.write_event(Event::End(BytesEnd::new("GeoLOD")))?;
+        Ok(())
+    }
 
-                    assert!(writer.write_event(Event::Empty(elem)).is_ok());
-                }
-                Ok(Event::Start(e)) if e.name().as_ref() == b"_GeoElevationGrid" => {
-                    in_geo_elevation_grid = true;
-                    let mut elem = BytesStart::new("GeoElevationGrid");
-                    elem.extend_attributes(e.attributes().map(|attr| attr.unwrap()));
-                    create_height_attr(&mut elem);
+    /// Returns the file name of a GeoLOD tile without extension
+    fn lod_name(&self, tile: &LodTile) -> String {
+        format!(
+            "{}_{}_{}_{}",
+            self.settings.planet_name, tile.level, tile.x, tile.y
+        )
+    }
 
-                    assert!(writer.write_event(Event::Start(elem)).is_ok());
-                }
-                Ok(Event::End(e)) if e.name().as_ref() == b"_GeoElevationGrid" => {
-                    in_geo_elevation_grid = false;
-                    let elem = BytesEnd::new("GeoElevationGrid");
+    /// Writes an X3D document to the output directory in the encoding of the model
+    ///
+    /// XML documents are written directly, other encodings are converted from the
+    /// whole XML document held in memory.
+    fn save_document<F>(&self, name: &str, write: F) -> Result<(), ErrBox>
+    where
+        F: FnOnce(&mut Writer<&mut dyn Write>) -> Result<(), ErrBox>,
+    {
+        let path = Path::new(&self.settings.output_dir)
+            .join(name)
+            .with_extension(self.extension());
+        let mut out = self
+            .compression
+            .create(&path)
+            .map_err(|err| format!("Can't write to output file {:?}: {}", path, err))?;
+        match self.encoding {
+            X3DEncoding::Xml => write(&mut Writer::new(&mut out as &mut dyn Write))?,
+            encoding => {
+                let mut xml = vec![];
+                write(&mut Writer::new(&mut xml as &mut dyn Write))?;
+                write_scene(&xml, encoding, &mut out)?;
+            }
+        }
+        Ok(out.finish()?)
+    }
 
-                    assert!(writer.write_event(Event::End(elem)).is_ok());
-                }
-                Ok(Event::Empty(e)) if e.name().as_ref() == b"_Color" && in_geo_elevation_grid => {
-                    let mut elem = BytesStart::new("Color");
-
-                    match &self.model_type {
-                        ModelType::Texture => (),
-                        ModelType::Color => {
-                            elem.extend_attributes(e.attributes().map(|attr| attr.unwrap()));
-                            elem.push_attribute(("color", color_values.as_str()))
-                        }
-                    };
+    /// Returns the extension of output files
+    fn extension(&self) -> String {
+        self.compression.extension(self.encoding.extension())
+    }
 
-                    assert!(writer.write_event(Event::Empty(elem)).is_ok());
+    /// Writes files of the GeoLOD tiles below the root tiles
+    fn save_lod_tiles(
+        &self,
+        lod_opts: &GeoLodOpts,
+        shape: &[Event<'static>],
+        grid: &mut GridValues,
+    ) -> Result<(), ErrBox> {
+        for level in 1..=lod_opts.levels {
+            for y in 0..(1 << level) {
+                for x in 0..(2 << level) {
+                    let tile = LodTile { level, x, y };
+                    self.save_document(&self.lod_name(&tile), |writer| {
+                        writer.write_event(Event::Decl(BytesDecl::new(
+                            "1.0",
+                            Some("UTF-8"),
+                            None,
+                        )))?;
+                        let mut elem = BytesStart::new("X3D");
+                        elem.push_attribute(("profile", "Immersive"));
+                        elem.push_attribute(("version", "3.3"));
+                        writer.write_event(Event::Start(elem))?;
+                        writer.write_event(Event::Start(BytesStart::new("head")))?;
+                        let mut elem = BytesStart::new("component");
+                        elem.push_attribute(("level", "1"));
+                        elem.push_attribute(("name", "Geospatial"));
+                        writer.write_event(Event::Empty(elem))?;
+                        writer.write_event(Event::End(BytesEnd::new("head")))?;
+                        writer.write_event(Event::Start(BytesStart::new("Scene")))?;
+                        self.write_lod(writer, lod_opts, shape, grid, tile)?;
+                        writer.write_event(Event::End(BytesEnd::new("Scene")))?;
+                        writer.write_event(Event::End(BytesEnd::new("X3D")))?;
+                        Ok(())
+                    })?;
                 }
-                Ok(Event::Empty(e)) if e.name().as_ref() == b"_ImageTexture" => {
-                    let texture_uri =
-                        settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?;
-                    let mut elem = BytesStart::new("ImageTexture");
-                    match &self.model_type {
-                        ModelType::Texture => elem.push_attribute(("url", &texture_uri[..])),
-                        ModelType::Color => (),
-                    };
-
-                    assert!(writer.write_event(Event::Empty(elem)).is_ok());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options of GeoLOD tiles
+pub struct GeoLodOpts {
+    /// Number of levels below the root tiles
+    levels: u32,
+    /// `range` of the root tiles in metres, halved at each level
+    range: Coord,
+}
+
+impl GeoLodOpts {
+    /// Reads options from settings, `None` if the grid isn't tiled
+    ///
+    /// The model size must be divisible by the number of finest tiles along a
+    /// meridian, `2^levels`.
+    pub fn from_settings(
+        settings: &Settings,
+        model_size: GeoPointIndex,
+    ) -> Result<Option<Self>, ErrBox> {
+        let levels = settings.get_parameter_num("lod_levels", 0u32)?;
+        if levels == 0 {
+            return Ok(None);
+        }
+        if levels > model_size.trailing_zeros() {
+            return Err(format!(
+                "Model size {} isn't divisible by 2^{}, the number of finest GeoLOD tiles along a meridian",
+                model_size, levels
+            )
+            .into());
+        }
+        Ok(Some(GeoLodOpts {
+            levels,
+            range: settings.get_parameter_num("lod_range", DEFAULT_LOD_RANGE)?,
+        }))
+    }
+}
+
+/// Tile of the GeoLOD quadtree
+///
+/// The two root tiles are the western and the eastern hemispheres, every tile
+/// has four quarters at the next level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LodTile {
+    level: u32,
+    /// Column from the antimeridian eastwards
+    x: usize,
+    /// Row from the south pole northwards
+    y: usize,
+}
+
+impl LodTile {
+    /// Returns size of the tile in degrees
+    fn span(&self) -> Coord {
+        180.0 / (1 << self.level) as Coord
+    }
+
+    /// Returns latitude and longitude of the tile center
+    fn center(&self) -> (Coord, Coord) {
+        let span = self.span();
+        (
+            -90.0 + span * (self.y as Coord + 0.5),
+            -180.0 + span * (self.x as Coord + 0.5),
+        )
+    }
+
+    /// Returns quarters of the tile: south-west, south-east, north-west and north-east
+    fn children(&self) -> [LodTile; 4] {
+        let (level, x, y) = (self.level + 1, 2 * self.x, 2 * self.y);
+        [
+            LodTile { level, x, y },
+            LodTile { level, x: x + 1, y },
+            LodTile { level, x, y: y + 1 },
+            LodTile {
+                level,
+                x: x + 1,
+                y: y + 1,
+            },
+        ]
+    }
+}
+
+/// Part of the global grid written as a `GeoElevationGrid`
+#[derive(Debug, PartialEq)]
+struct GridTile {
+    /// Column and row of the south-west point in the global grid
+    column: usize,
+    row: usize,
+    /// Step between written points in the global grid
+    step: usize,
+    x_dimension: usize,
+    z_dimension: usize,
+    /// Latitude and longitude of `geoGridOrigin`, `None` keeps the template value
+    origin: Option<(Coord, Coord)>,
+}
+
+impl GridTile {
+    /// Returns the grid of the whole globe
+    fn whole(model_size: GeoPointIndex) -> Self {
+        GridTile {
+            column: 0,
+            row: 0,
+            step: 1,
+            x_dimension: 2 * model_size + 1,
+            z_dimension: model_size + 1,
+            origin: None,
+        }
+    }
+
+    /// Returns the grid of a GeoLOD tile
+    ///
+    /// Tiles of all levels have the same number of points, tiles of the finest
+    /// level have the spacing of the model.
+    fn lod(model_size: GeoPointIndex, levels: u32, tile: LodTile) -> Self {
+        let cells = model_size >> levels;
+        let step = 1 << (levels - tile.level);
+        let span = tile.span();
+        GridTile {
+            column: tile.x * cells * step,
+            row: tile.y * cells * step,
+            step,
+            x_dimension: cells + 1,
+            z_dimension: cells + 1,
+            origin: Some((
+                -90.0 + span * tile.y as Coord,
+                -180.0 + span * tile.x as Coord,
+            )),
+        }
+    }
+
+    /// Returns indices of the written points in the global grid, by rows from the south
+    fn indices(&self, width: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.z_dimension).flat_map(move |b| {
+            (0..self.x_dimension)
+                .map(move |a| (self.row + b * self.step) * width + self.column + a * self.step)
+        })
+    }
+}
+
+/// Values of the global grid, written to the `GeoElevationGrid` nodes of tiles
+///
+/// Long fields are streamed to the output value by value, so they aren't held
+/// in memory as strings in XML documents.
+struct GridValues<'b, 'a> {
+    model: &'b X3DGeospatial<'a>,
+    /// Number of points in a row of the global grid
+    width: usize,
+    /// `scale` parameter, replaces `yScale` of the template if positive
+    y_scale: Height,
+    texture_uri: String,
+    /// Grid normals, made at the first elevation grid
+    normals: Option<Vec<Point3d>>,
+    /// `yScale` of the elevation grid started by the template
+    grid_y_scale: Height,
+    in_geo_elevation_grid: bool,
+}
+
+impl<'b, 'a> GridValues<'b, 'a> {
+    fn new(model: &'b X3DGeospatial<'a>) -> Result<Self, ErrBox> {
+        let settings = model.settings;
+        Ok(GridValues {
+            model,
+            width: 2 * model.model_size + 1,
+            y_scale: settings.get_parameter_num("scale", 0.0)?,
+            texture_uri: settings.get_parameter_str("texture_uri", DEFAULT_TEXTURE_URI)?,
+            normals: None,
+            grid_y_scale: 1.0,
+            in_geo_elevation_grid: false,
+        })
+    }
+
+    /// Writes an event of the template, replacing placeholders with values of the tile
+    fn write_event<W: Write>(
+        &mut self,
+        writer: &mut Writer<W>,
+        event: Event,
+        tile: &GridTile,
+    ) -> Result<(), ErrBox> {
+        match event {
+            Event::Empty(e) if e.name().as_ref() == b"_GeoElevationGrid" => {
+                let y_scale = grid_y_scale(&e, self.y_scale);
+                if self.has_grid_children(tile) {
+                    self.write_grid(writer.get_mut(), &e, tile, false)?;
+                    self.write_grid_children(writer.get_mut(), y_scale, tile)?;
+                    writer.write_event(Event::End(BytesEnd::new("GeoElevationGrid")))?;
+                } else {
+                    self.write_grid(writer.get_mut(), &e, tile, true)?;
                 }
-                Ok(e) => assert!(writer.write_event(e).is_ok()),
             }
-            buf.clear();
+            Event::Start(e) if e.name().as_ref() == b"_GeoElevationGrid" => {
+                self.in_geo_elevation_grid = true;
+                self.grid_y_scale = grid_y_scale(&e, self.y_scale);
+                self.write_grid(writer.get_mut(), &e, tile, false)?;
+            }
+            Event::End(e) if e.name().as_ref() == b"_GeoElevationGrid" => {
+                self.in_geo_elevation_grid = false;
+                self.write_grid_children(writer.get_mut(), self.grid_y_scale, tile)?;
+                writer.write_event(Event::End(BytesEnd::new("GeoElevationGrid")))?;
+            }
+            Event::Empty(e) if e.name().as_ref() == b"_Color" && self.in_geo_elevation_grid => {
+                let mut elem = BytesStart::new("Color");
+                match &self.model.model_type {
+                    ModelType::Texture => writer.write_event(Event::Empty(elem))?,
+                    ModelType::Color => {
+                        elem.extend_attributes(e.attributes().filter_map(|attr| attr.ok()));
+                        let colors = self.model.components.get_colors()?;
+                        if let Some(i) = tile.indices(self.width).find(|i| !colors.contains_key(i))
+                        {
+                            return Err(format!("Missed color for point {}", i).into());
+                        }
+                        let values = tile.indices(self.width).map(|i| colors[&i]);
+                        write_streamed(writer.get_mut(), &elem, "color", true, |out| {
+                            write_values(out, values, " ")
+                        })?;
+                    }
+                };
+            }
+            Event::Empty(e) if e.name().as_ref() == b"_ImageTexture" => {
+                let mut elem = BytesStart::new("ImageTexture");
+                match &self.model.model_type {
+                    ModelType::Texture => elem.push_attribute(("url", self.texture_uri.as_str())),
+                    ModelType::Color => (),
+                };
+                writer.write_event(Event::Empty(elem))?;
+            }
+            e => writer.write_event(e)?,
+        }
+        Ok(())
+    }
+
+    /// Writes the tag of the `GeoElevationGrid` of the tile from the template element
+    fn write_grid<W: Write>(
+        &self,
+        out: &mut W,
+        e: &BytesStart,
+        tile: &GridTile,
+        empty: bool,
+    ) -> Result<(), ErrBox> {
+        let mut elem = BytesStart::new("GeoElevationGrid");
+        elem.extend_attributes(
+            grid_attributes(e, self.y_scale > 0.0)
+                .into_iter()
+                .filter(|attr| tile.origin.is_none() || attr.key.as_ref() != b"geoGridOrigin"),
+        );
+        if let Some((lat, lon)) = tile.origin {
+            elem.push_attribute(("geoGridOrigin", format!("{} {} 0", lat, lon).as_str()));
+        }
+        let spacing = (self.model.components.spacing * tile.step as Coord).to_string();
+        elem.push_attribute(("xDimension", tile.x_dimension.to_string().as_str()));
+        elem.push_attribute(("xSpacing", spacing.as_str()));
+        elem.push_attribute(("zDimension", tile.z_dimension.to_string().as_str()));
+        elem.push_attribute(("zSpacing", spacing.as_str()));
+        if self.y_scale > 0.0 {
+            elem.push_attribute(("yScale", self.y_scale.to_string().as_str()));
         }
+        let heights = &self.model.components.heights;
+        let relief = &self.model.relief;
+        let values = tile
+            .indices(self.width)
+            .map(|i| relief.apply(heights.get(&i).cloned().unwrap_or(0.0)));
+        Ok(write_streamed(out, &elem, "height", empty, |out| {
+            write_values(out, values, " ")
+        })?)
+    }
 
-        Ok(writer.into_inner().flush()?)
+    /// Checks if the grid of the tile has a `Normal` or a `TextureCoordinate` node
+    fn has_grid_children(&self, tile: &GridTile) -> bool {
+        self.model.normal_opts.is_some()
+            || (matches!(self.model.model_type, ModelType::Texture) && tile.origin.is_some())
+    }
+
+    /// Writes the `Normal` node, and the `TextureCoordinate` node of textured GeoLOD tiles
+    fn write_grid_children<W: Write>(
+        &mut self,
+        out: &mut W,
+        y_scale: Height,
+        tile: &GridTile,
+    ) -> Result<(), ErrBox> {
+        if self.normals.is_none() {
+            self.normals = self.model.normal_values(y_scale)?;
+        }
+        if let Some(normals) = &self.normals {
+            let values = tile.indices(self.width).map(|i| {
+                let (x, y, z) = normals[i];
+                format!("{:.4} {:.4} {:.4}", x, y, z)
+            });
+            let elem = BytesStart::new("Normal");
+            write_streamed(out, &elem, "vector", true, |out| {
+                write_values(out, values, ", ")
+            })?;
+        }
+        // the default texture coordinates stretch the texture over the tile
+        if let (ModelType::Texture, Some(_)) = (&self.model.model_type, tile.origin) {
+            let (width, height) = ((self.width - 1) as Coord, self.model.model_size as Coord);
+            let values = tile.indices(self.width).map(|i| {
+                let (s, t) = ((i % self.width) as Coord, (i / self.width) as Coord);
+                format!("{} {}", s / width, t / height)
+            });
+            let elem = BytesStart::new("TextureCoordinate");
+            write_streamed(out, &elem, "point", true, |out| {
+                write_values(out, values, ", ")
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a start or an empty tag with a last attribute streamed by `write_value`
+fn write_streamed<W: Write>(
+    out: &mut W,
+    elem: &BytesStart,
+    name: &str,
+    empty: bool,
+    write_value: impl FnOnce(&mut W) -> io::Result<()>,
+) -> io::Result<()> {
+    out.write_all(b"<")?;
+    out.write_all(elem)?;
+    write!(out, " {}=\"", name)?;
+    write_value(out)?;
+    out.write_all(if empty { b"\"/>" } else { b"\">" })
+}
+
+/// Writes values with a separator
+fn write_values<W: Write, T: Display>(
+    out: &mut W,
+    values: impl Iterator<Item = T>,
+    separator: &str,
+) -> io::Result<()> {
+    for (k, value) in values.enumerate() {
+        if k > 0 {
+            out.write_all(separator.as_bytes())?;
+        }
+        write!(out, "{}", value)?;
+    }
+    Ok(())
+}
+
+/// Checks if the template event is the elevation grid placeholder
+fn is_grid_placeholder(event: &Event) -> bool {
+    match event {
+        Event::Empty(e) | Event::Start(e) => e.name().as_ref() == b"_GeoElevationGrid",
+        _ => false,
     }
 }
 
+/// Checks if the template has a `Shape` with the elevation grid placeholder
+fn has_grid_shape(template_file: &Path) -> Result<bool, ErrBox> {
+    let mut reader =
+        Reader::from_file(template_file).map_err(|err| format!("Can't read template: {}", err))?;
+    let mut buf = Vec::new();
+    let mut shapes = 0;
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| format!("Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match &event {
+            Event::Eof => return Ok(false),
+            Event::Start(e) if e.name().as_ref() == b"Shape" => shapes += 1,
+            Event::End(e) if e.name().as_ref() == b"Shape" => shapes -= 1,
+            e if shapes > 0 && is_grid_placeholder(e) => return Ok(true),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Returns `yScale` of the elevation grid, the template value if it isn't replaced
+fn grid_y_scale(e: &BytesStart, y_scale: Height) -> Height {
+    if y_scale > 0.0 {
+        return y_scale;
+    }
+    e.attributes()
+        .filter_map(|attr| attr.ok())
+        .find(|attr| attr.key.as_ref() == b"yScale")
+        .and_then(|attr| String::from_utf8_lossy(&attr.value).parse().ok())
+        .unwrap_or(1.0)
+}
+
+/// Returns attributes of the elevation grid in the template, without `yScale` if it's replaced
+fn grid_attributes<'b>(e: &'b BytesStart, replace_y_scale: bool) -> Vec<Attribute<'b>> {
+    e.attributes()
+        .map(|attr| attr.unwrap())
+        .filter(|attr| !replace_y_scale || attr.key.as_ref() != b"yScale")
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -347,4 +901,69 @@ mod tests {
         // For model_size=2, that's 3 rows and 5 columns = 15 vertices
         assert_eq!(vertices.len(), 15);
     }
+
+    #[test]
+    fn lod_tile_t0() {
+        let tile = LodTile {
+            level: 1,
+            x: 3,
+            y: 0,
+        };
+        assert_eq!(tile.center(), (-45.0, 135.0));
+        let children = tile.children();
+        assert_eq!(
+            children[0],
+            LodTile {
+                level: 2,
+                x: 6,
+                y: 0
+            }
+        );
+        assert_eq!(
+            children[3],
+            LodTile {
+                level: 2,
+                x: 7,
+                y: 1
+            }
+        );
+    }
+
+    #[test]
+    fn grid_tile_t0() {
+        // model size 4 with 1 level: root tiles of 3x3 points with step 2
+        let width = 2 * 4 + 1;
+        let root = GridTile::lod(
+            4,
+            1,
+            LodTile {
+                level: 0,
+                x: 1,
+                y: 0,
+            },
+        );
+        assert_eq!(root.origin, Some((-90.0, 0.0)));
+        assert_eq!(
+            root.indices(width).collect::<Vec<usize>>(),
+            vec![4, 6, 8, 22, 24, 26, 40, 42, 44]
+        );
+
+        let leaf = GridTile::lod(
+            4,
+            1,
+            LodTile {
+                level: 1,
+                x: 1,
+                y: 1,
+            },
+        );
+        assert_eq!(leaf.origin, Some((0.0, -90.0)));
+        assert_eq!(
+            leaf.indices(width).collect::<Vec<usize>>(),
+            vec![20, 21, 22, 29, 30, 31, 38, 39, 40]
+        );
+
+        let whole = GridTile::whole(4);
+        assert!(whole.indices(width).eq(0..45));
+    }
 }